@@ -1,6 +1,10 @@
 syntax case match
 
 syntax keyword firKeyword
+    \ break
+    \ catch
+    \ const
+    \ continue
     \ elif
     \ else
     \ fn
@@ -11,9 +15,12 @@ syntax keyword firKeyword
     \ in
     \ let
     \ match
+    \ memo
     \ return
     \ self
+    \ throw
     \ trait
+    \ try
     \ type
     \ var
     \ while
@@ -11,7 +11,7 @@ pub struct L<T> {
     pub node: T,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Loc {
     pub module: Rc<str>,
     pub line_start: u16,
@@ -56,6 +56,9 @@ pub enum TopDecl {
     /// A function declaration: `fn f(...) = ...`.
     Fun(L<FunDecl>),
 
+    /// An associated constant declaration: `const T.NAME = ...`.
+    Const(L<ConstDecl>),
+
     /// An import declaration.
     Import(L<ImportDecl>),
 }
@@ -136,11 +139,15 @@ pub struct FunDecl {
     /// Whether the function has a `self` parameter.
     pub self_: bool,
 
-    pub params: Vec<(SmolStr, Type)>,
+    pub params: Vec<FunParam>,
 
     pub return_ty: Option<Type>,
 
     pub body: L<Vec<L<Stmt>>>,
+
+    /// Whether the function is declared with `memo fn`. Calls to memoized functions with
+    /// arguments equal to the arguments of a previous call return the previous result.
+    pub memo: bool,
 }
 
 impl FunDecl {
@@ -149,6 +156,34 @@ impl FunDecl {
     }
 }
 
+/// An associated constant declaration: `const I32.BITS: I32 = 32`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConstDecl {
+    /// Name of the type the constant belongs.
+    pub type_name: SmolStr,
+
+    /// Name of the constant.
+    pub name: SmolStr,
+
+    #[allow(unused)]
+    pub ty: Option<Type>,
+
+    /// Evaluated once, before `main`. Constants are initialized in declaration order, so the
+    /// right-hand side can only use the constants declared before it.
+    pub rhs: L<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunParam {
+    pub name: SmolStr,
+
+    pub ty: Type,
+
+    /// Value of the parameter when the caller does not pass an argument for it. Evaluated in the
+    /// callee's scope, so it can refer to the parameters before it.
+    pub default: Option<L<Expr>>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Stmt {
     Let(LetStatement),
@@ -157,6 +192,16 @@ pub enum Stmt {
     Expr(L<Expr>),
     For(ForStatement),
     While(WhileStatement),
+    WhileLet(WhileLetStatement),
+
+    /// `break` or `break <expr>`. The value becomes the value of the loop.
+    ///
+    /// With a label, `break 'outer`, exits the enclosing loop with the label instead of the
+    /// closest loop.
+    Break(Option<SmolStr>, Option<L<Expr>>),
+
+    /// `continue`, or `continue 'outer` to continue the enclosing loop with the label.
+    Continue(Option<SmolStr>),
 }
 
 /// A let statement: `let x: T = expr`.
@@ -195,13 +240,25 @@ pub enum Pat {
 
     Str(String),
 
-    /// Match the prefix, bind the rest. E.g. `"a" .. rest`.
-    StrPfx(String, SmolStr),
+    /// Match the prefix, bind the rest. E.g. `"a" rest`, or `"a" as pfx rest` to also bind the
+    /// matched prefix.
+    StrPfx(StrPfxPattern),
 
     /// Or pattern: `<pat1> | <pat2>`.
     Or(Box<L<Pat>>, Box<L<Pat>>),
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StrPfxPattern {
+    pub pfx: String,
+
+    /// Variable to bind the matched prefix.
+    pub pfx_var: Option<SmolStr>,
+
+    /// Variable to bind the rest of the string.
+    pub rest_var: SmolStr,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ConstrPattern {
     pub constr: Constructor,
@@ -214,6 +271,14 @@ pub struct Constructor {
     pub constr: Option<SmolStr>,
 }
 
+/// `try: <body> catch <var>: <handler>`. Values thrown in `body` are bound to `var` in `handler`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TryExpr {
+    pub body: Vec<L<Stmt>>,
+    pub var: SmolStr,
+    pub handler: Vec<L<Stmt>>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct IfExpr {
     // At least one element
@@ -233,10 +298,15 @@ pub enum AssignOp {
     Eq,
     PlusEq,
     MinusEq,
+    StarEq,
+    SlashEq,
+    PercentEq,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ForStatement {
+    /// Label of the loop: `'outer` in `'outer: for ...`.
+    pub label: Option<SmolStr>,
     pub var: SmolStr,
     pub ty: Option<Type>,
     pub expr: L<Expr>,
@@ -245,10 +315,21 @@ pub struct ForStatement {
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct WhileStatement {
+    pub label: Option<SmolStr>,
     pub cond: L<Expr>,
     pub body: Vec<L<Stmt>>,
 }
 
+/// `while let <pat> = <expr>: <body>`: evaluates `expr` and runs the body with the pattern
+/// bindings until the value doesn't match the pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WhileLetStatement {
+    pub label: Option<SmolStr>,
+    pub pat: L<Pat>,
+    pub expr: L<Expr>,
+    pub body: Vec<L<Stmt>>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Expr {
     /// A variable: `x`.
@@ -283,13 +364,23 @@ pub enum Expr {
 
     ArrayIndex(ArrayIndexExpr),
 
+    /// An array literal: `[a, b, c]`.
+    Array(Vec<L<Expr>>),
+
     Record(Vec<Named<Box<L<Expr>>>>),
 
+    /// A record update: `{r | x = 1}`.
+    RecordUpdate(RecordUpdateExpr),
+
     Return(Box<L<Expr>>),
 
     Match(MatchExpr),
 
     If(IfExpr),
+
+    Throw(Box<L<Expr>>),
+
+    Try(TryExpr),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -336,6 +427,14 @@ pub struct UnOpExpr {
     pub expr: Box<L<Expr>>,
 }
 
+/// Copies the record and replaces the values of the given fields. The record needs to have named
+/// fields.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordUpdateExpr {
+    pub record: Box<L<Expr>>,
+    pub fields: Vec<(SmolStr, L<Expr>)>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ArrayIndexExpr {
     pub array: Box<L<Expr>>,
@@ -349,6 +448,8 @@ pub enum BinOp {
     Equal,
     NotEqual,
     Multiply,
+    Divide,
+    Modulo,
     Lt,
     Gt,
     LtEq,
@@ -360,10 +461,266 @@ pub enum BinOp {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum UnOp {
     Not,
+
+    /// Negation: `-x`.
+    Neg,
 }
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ImportDecl {
     /// Import path, e.g. `Fir.Prelude`.
     pub path: Vec<SmolStr>,
     // TODO: Imported thing list, renaming (`as`).
 }
+
+/// Generates a visitor trait and the functions that walk the children of the nodes, for shared
+/// (`Visitor`) and mutable (`VisitorMut`) traversals.
+///
+/// The default methods of the trait visit the children of the node with the walk functions. Passes
+/// override the methods of the nodes they handle, and call the walk functions to continue with the
+/// children.
+macro_rules! visitor {
+    (
+        $visitor:ident,
+        $walk_fun_decl:ident,
+        $walk_stmts:ident,
+        $walk_stmt:ident,
+        $walk_expr:ident,
+        $walk_pat:ident,
+        $($mut_:tt)?
+    ) => {
+        pub trait $visitor {
+            fn visit_fun_decl(&mut self, fun_decl: &$($mut_)? FunDecl) {
+                $walk_fun_decl(self, fun_decl);
+            }
+
+            fn visit_stmts(&mut self, stmts: &$($mut_)? [L<Stmt>]) {
+                $walk_stmts(self, stmts);
+            }
+
+            fn visit_stmt(&mut self, stmt: &$($mut_)? L<Stmt>) {
+                $walk_stmt(self, stmt);
+            }
+
+            fn visit_expr(&mut self, expr: &$($mut_)? L<Expr>) {
+                $walk_expr(self, expr);
+            }
+
+            fn visit_pat(&mut self, pat: &$($mut_)? L<Pat>) {
+                $walk_pat(self, pat);
+            }
+        }
+
+        /// Visit the parameter defaults, then the body.
+        pub fn $walk_fun_decl<V: $visitor + ?Sized>(visitor: &mut V, fun_decl: &$($mut_)? FunDecl) {
+            for param in &$($mut_)? fun_decl.params {
+                if let Some(default) = &$($mut_)? param.default {
+                    visitor.visit_expr(default);
+                }
+            }
+            visitor.visit_stmts(&$($mut_)? fun_decl.body.node);
+        }
+
+        pub fn $walk_stmts<V: $visitor + ?Sized>(visitor: &mut V, stmts: &$($mut_)? [L<Stmt>]) {
+            for stmt in stmts {
+                visitor.visit_stmt(stmt);
+            }
+        }
+
+        pub fn $walk_stmt<V: $visitor + ?Sized>(visitor: &mut V, stmt: &$($mut_)? L<Stmt>) {
+            match &$($mut_)? stmt.node {
+                Stmt::Let(LetStatement { lhs, ty: _, rhs }) => {
+                    visitor.visit_pat(lhs);
+                    visitor.visit_expr(rhs);
+                }
+
+                Stmt::Assign(AssignStatement { lhs, rhs, op: _ }) => {
+                    visitor.visit_expr(lhs);
+                    visitor.visit_expr(rhs);
+                }
+
+                Stmt::Expr(expr) => visitor.visit_expr(expr),
+
+                Stmt::Break(_, expr) => {
+                    if let Some(expr) = expr {
+                        visitor.visit_expr(expr);
+                    }
+                }
+
+                Stmt::Continue(_) => {}
+
+                Stmt::For(ForStatement {
+                    label: _,
+                    var: _,
+                    ty: _,
+                    expr,
+                    body,
+                }) => {
+                    visitor.visit_expr(expr);
+                    visitor.visit_stmts(body);
+                }
+
+                Stmt::While(WhileStatement {
+                    label: _,
+                    cond,
+                    body,
+                }) => {
+                    visitor.visit_expr(cond);
+                    visitor.visit_stmts(body);
+                }
+
+                Stmt::WhileLet(WhileLetStatement {
+                    label: _,
+                    pat,
+                    expr,
+                    body,
+                }) => {
+                    visitor.visit_pat(pat);
+                    visitor.visit_expr(expr);
+                    visitor.visit_stmts(body);
+                }
+            }
+        }
+
+        pub fn $walk_expr<V: $visitor + ?Sized>(visitor: &mut V, expr: &$($mut_)? L<Expr>) {
+            match &$($mut_)? expr.node {
+                Expr::Var(_)
+                | Expr::UpperVar(_)
+                | Expr::Int(_)
+                | Expr::Self_
+                | Expr::ConstrSelect(_) => {}
+
+                Expr::String(parts) => {
+                    for part in parts {
+                        match part {
+                            StringPart::Str(_) => {}
+                            StringPart::Expr(expr, _) => visitor.visit_expr(expr),
+                        }
+                    }
+                }
+
+                Expr::FieldSelect(FieldSelectExpr { object, field: _ }) => {
+                    visitor.visit_expr(object)
+                }
+
+                Expr::Call(CallExpr { fun, args }) => {
+                    visitor.visit_expr(fun);
+                    for arg in args {
+                        visitor.visit_expr(&$($mut_)? arg.expr);
+                    }
+                }
+
+                Expr::Range(RangeExpr {
+                    from,
+                    to,
+                    inclusive: _,
+                }) => {
+                    visitor.visit_expr(from);
+                    visitor.visit_expr(to);
+                }
+
+                Expr::BinOp(BinOpExpr { left, right, op: _ }) => {
+                    visitor.visit_expr(left);
+                    visitor.visit_expr(right);
+                }
+
+                Expr::UnOp(UnOpExpr { op: _, expr }) => visitor.visit_expr(expr),
+
+                Expr::ArrayIndex(ArrayIndexExpr { array, index }) => {
+                    visitor.visit_expr(array);
+                    visitor.visit_expr(index);
+                }
+
+                Expr::Array(elems) => {
+                    for elem in elems {
+                        visitor.visit_expr(elem);
+                    }
+                }
+
+                Expr::Record(fields) => {
+                    for field in fields {
+                        visitor.visit_expr(&$($mut_)? field.node);
+                    }
+                }
+
+                Expr::RecordUpdate(RecordUpdateExpr { record, fields }) => {
+                    visitor.visit_expr(record);
+                    for (_, expr) in fields {
+                        visitor.visit_expr(expr);
+                    }
+                }
+
+                Expr::Return(expr) | Expr::Throw(expr) => visitor.visit_expr(expr),
+
+                Expr::Try(TryExpr {
+                    body,
+                    var: _,
+                    handler,
+                }) => {
+                    visitor.visit_stmts(body);
+                    visitor.visit_stmts(handler);
+                }
+
+                Expr::Match(MatchExpr { scrutinee, alts }) => {
+                    visitor.visit_expr(scrutinee);
+                    for alt in alts {
+                        visitor.visit_pat(&$($mut_)? alt.pattern);
+                        if let Some(guard) = &$($mut_)? alt.guard {
+                            visitor.visit_expr(guard);
+                        }
+                        visitor.visit_stmts(&$($mut_)? alt.rhs);
+                    }
+                }
+
+                Expr::If(IfExpr {
+                    branches,
+                    else_branch,
+                }) => {
+                    for (cond, stmts) in branches {
+                        visitor.visit_expr(cond);
+                        visitor.visit_stmts(stmts);
+                    }
+                    if let Some(else_branch) = else_branch {
+                        visitor.visit_stmts(else_branch);
+                    }
+                }
+            }
+        }
+
+        pub fn $walk_pat<V: $visitor + ?Sized>(visitor: &mut V, pat: &$($mut_)? L<Pat>) {
+            match &$($mut_)? pat.node {
+                Pat::Var(_) | Pat::Ignore | Pat::Str(_) | Pat::StrPfx(_) => {}
+
+                Pat::Constr(ConstrPattern { constr: _, fields }) | Pat::Record(fields) => {
+                    for field in fields {
+                        visitor.visit_pat(&$($mut_)? field.node);
+                    }
+                }
+
+                Pat::Or(pat1, pat2) => {
+                    visitor.visit_pat(pat1);
+                    visitor.visit_pat(pat2);
+                }
+            }
+        }
+    };
+}
+
+visitor!(
+    Visitor,
+    walk_fun_decl,
+    walk_stmts,
+    walk_stmt,
+    walk_expr,
+    walk_pat,
+);
+
+visitor!(
+    VisitorMut,
+    walk_fun_decl_mut,
+    walk_stmts_mut,
+    walk_stmt_mut,
+    walk_expr_mut,
+    walk_pat_mut,
+    mut
+);
@@ -0,0 +1,61 @@
+//! Folds arithmetic on integer literals, e.g. `2 * 3 + 1`, into literals.
+//!
+//! Only `+`, `-`, `*`, `/`, `%` and negation of `I32` literals are folded. Expressions that would
+//! fail at run time (overflow, division by zero) are left as they are so the error is reported when
+//! the expression is evaluated.
+
+use crate::ast::{self, VisitorMut};
+
+pub fn fold_constants(pgm: &mut [ast::L<ast::TopDecl>]) {
+    for decl in pgm {
+        match &mut decl.node {
+            ast::TopDecl::Type(_) => {}
+            ast::TopDecl::Fun(fun_decl) => ConstFolder.visit_fun_decl(&mut fun_decl.node),
+            ast::TopDecl::Const(const_decl) => ConstFolder.visit_expr(&mut const_decl.node.rhs),
+            ast::TopDecl::Import(_) => panic!("Import declaration in constant folder"),
+        }
+    }
+}
+
+struct ConstFolder;
+
+impl VisitorMut for ConstFolder {
+    fn visit_expr(&mut self, expr: &mut ast::L<ast::Expr>) {
+        // Fold the operands first, so that nested operations are folded bottom-up.
+        ast::walk_expr_mut(self, expr);
+
+        match &expr.node {
+            ast::Expr::BinOp(ast::BinOpExpr { left, right, op }) => {
+                if let (ast::Expr::Int(i1), ast::Expr::Int(i2)) = (&left.node, &right.node) {
+                    if let Some(i) = fold_binop(*i1, *i2, op) {
+                        expr.node = ast::Expr::Int(i);
+                    }
+                }
+            }
+
+            ast::Expr::UnOp(ast::UnOpExpr { op, expr: operand }) => {
+                if let (ast::UnOp::Neg, ast::Expr::Int(i)) = (op, &operand.node) {
+                    if let Some(i) = i.checked_neg() {
+                        expr.node = ast::Expr::Int(i);
+                    }
+                }
+            }
+
+            _ => {}
+        }
+    }
+}
+
+/// Result of `i1 <op> i2`, following the semantics of the `I32` built-ins. `None` if `op` is not an
+/// arithmetic operator or evaluating it would fail.
+fn fold_binop(i1: i32, i2: i32, op: &ast::BinOp) -> Option<i32> {
+    match op {
+        ast::BinOp::Add => i1.checked_add(i2),
+        ast::BinOp::Subtract => i1.checked_sub(i2),
+        ast::BinOp::Multiply => i1.checked_mul(i2),
+        // `I32.__div` and `I32.__mod` wrap on `I32.MIN / -1`.
+        ast::BinOp::Divide if i2 != 0 => Some(i1.wrapping_div(i2)),
+        ast::BinOp::Modulo if i2 != 0 => Some(i1.wrapping_rem(i2)),
+        _ => None,
+    }
+}
@@ -0,0 +1,23 @@
+//! Warnings about a program, found before running it.
+
+use crate::ast::Loc;
+use crate::interpreter::LocDisplay;
+
+/// A warning with the location of the code it's about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// `None` for diagnostics about the whole program, e.g. a missing `main`.
+    pub loc: Option<Loc>,
+    pub msg: String,
+}
+
+/// Displays the diagnostic as `module:line:col: msg`, or just `msg` when it doesn't have a
+/// location.
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(loc) = &self.loc {
+            write!(f, "{}: ", LocDisplay(loc))?;
+        }
+        write!(f, "{}", self.msg)
+    }
+}
@@ -27,7 +27,9 @@ fn resolve_imports_(
 ) {
     for decl in module {
         match &decl.node {
-            ast::TopDecl::Type(_) | ast::TopDecl::Fun(_) => new_module.push(decl),
+            ast::TopDecl::Type(_) | ast::TopDecl::Fun(_) | ast::TopDecl::Const(_) => {
+                new_module.push(decl)
+            }
             ast::TopDecl::Import(import) => {
                 assert_eq!(import.node.path.len(), 1, "TODO: Import with long path");
                 let import_path = &import.node.path[0];
@@ -10,7 +10,51 @@ use lexgen_util::Loc;
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum StringPart {
     Str(String),
-    Expr(ast::L<ast::Expr>),
+
+    /// An interpolated expression, with an optional format spec: `$(expr)` or `$(expr:spec)`.
+    Expr(ast::L<ast::Expr>, Option<FormatSpec>),
+}
+
+/// Format spec of an interpolation: an optional width, then an optional radix. E.g. `$(n:4)`,
+/// `$(n:04)`, `$(n:hex)`, `$(n:08bin)`.
+///
+/// Width pads the value's `toStr` on the left with spaces, or with zeros when the width starts with
+/// `0`. Radix is only valid for `I32` values, the interpreter returns an error when it's used with
+/// other values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatSpec {
+    pub zero_pad: bool,
+    pub width: usize,
+    pub radix: Radix,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Radix {
+    Decimal,
+    Hex,
+    Binary,
+}
+
+fn parse_format_spec(spec: &str) -> FormatSpec {
+    let width_len = spec
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(spec.len());
+    let (width, radix) = spec.split_at(width_len);
+    let radix = match radix {
+        "" => Radix::Decimal,
+        "hex" => Radix::Hex,
+        "bin" => Radix::Binary,
+        _ => panic!("Invalid format spec in interpolation: {:?}", spec),
+    };
+    FormatSpec {
+        zero_pad: width.starts_with('0'),
+        width: if width.is_empty() {
+            0
+        } else {
+            width.parse().unwrap()
+        },
+        radix,
+    }
 }
 
 // Lexer ensures any interpolation (the part between `$(` and `)`) have balanced parens. In
@@ -39,6 +83,7 @@ pub fn parse_string_parts(module: &Rc<str>, s: &str) -> Vec<StringPart> {
                 parts.push(StringPart::Str(s[str_part_start..byte_idx].to_string()));
 
                 let mut parens: u32 = 1;
+                let mut spec_colon_idx: Option<usize> = None;
                 for (byte_idx, char) in chars.by_ref() {
                     if escape {
                         escape = false;
@@ -55,15 +100,28 @@ pub fn parse_string_parts(module: &Rc<str>, s: &str) -> Vec<StringPart> {
                         continue;
                     }
 
+                    // Inline expressions don't have colons, so a colon outside of parens starts the
+                    // format spec.
+                    if char == ':' && parens == 1 && spec_colon_idx.is_none() {
+                        spec_colon_idx = Some(byte_idx);
+                        continue;
+                    }
+
                     if char == ')' {
                         parens -= 1;
                         if parens == 0 {
                             // Lex and parse interpolation.
-                            let interpolation = &s[lparen_idx + 1..byte_idx];
+                            let (interpolation, spec) = match spec_colon_idx {
+                                Some(colon_idx) => (
+                                    &s[lparen_idx + 1..colon_idx],
+                                    Some(parse_format_spec(&s[colon_idx + 1..byte_idx])),
+                                ),
+                                None => (&s[lparen_idx + 1..byte_idx], None),
+                            };
                             let tokens: Vec<(Loc, Token, Loc)> = lex(interpolation);
                             let parser = LExprParser::new();
                             let expr = parser.parse(module, tokens).unwrap();
-                            parts.push(StringPart::Expr(expr));
+                            parts.push(StringPart::Expr(expr, spec));
                             str_part_start = byte_idx + 1;
                             continue 'outer;
                         }
@@ -93,5 +151,34 @@ fn interpolation_parsing_2() {
     let parts = parse_string_parts(&"test".into(), s);
     assert_eq!(parts.len(), 2);
     assert_eq!(parts[0], StringPart::Str("abc ".into()));
-    assert!(matches!(parts[1], StringPart::Expr(_)));
+    assert!(matches!(parts[1], StringPart::Expr(_, None)));
+}
+
+#[test]
+fn interpolation_parsing_format_spec() {
+    let s = r#"$(a:04hex) $(f(b):3)"#;
+    let parts = parse_string_parts(&"test".into(), s);
+    assert_eq!(parts.len(), 4);
+    assert!(matches!(
+        &parts[1],
+        StringPart::Expr(
+            _,
+            Some(FormatSpec {
+                zero_pad: true,
+                width: 4,
+                radix: Radix::Hex
+            })
+        )
+    ));
+    assert!(matches!(
+        &parts[3],
+        StringPart::Expr(
+            _,
+            Some(FormatSpec {
+                zero_pad: false,
+                width: 3,
+                radix: Radix::Decimal
+            })
+        )
+    ));
 }
@@ -3,55 +3,515 @@
 //! Since we don't have a proper type checker (it will be implemented in the bootstrapped compiler)
 //! we don't assume type safety here and always check types.
 
-#![allow(clippy::needless_range_loop, clippy::too_many_arguments)]
+#![allow(
+    clippy::large_enum_variant,
+    clippy::needless_range_loop,
+    clippy::too_many_arguments
+)]
 
 mod builtins;
+mod clock;
+mod debugger;
+mod events;
+mod exhaustiveness;
 mod heap;
 mod init;
-
-use builtins::{call_builtin_fun, BuiltinFun};
+mod memo;
+#[cfg(test)]
+mod tests;
+mod validate;
+
+use builtins::{call_builtin_fun, BuiltinFun, Rng};
+use clock::RunClock;
+use exhaustiveness::non_exhaustive_matches;
 use heap::Heap;
+use memo::MemoCache;
+use validate::validate;
+
+pub use clock::Clock;
+pub use debugger::{Breakpoint, DebugAction, Debugger, PauseState};
+pub use events::{Event, EventSink, EventWriter, StderrSink};
 
 use crate::ast::{self, Loc, L};
 use crate::collections::{Map, Set};
-use crate::interpolation::StringPart;
+use crate::const_fold::fold_constants;
+use crate::diagnostics::Diagnostic;
+use crate::interpolation::{FormatSpec, Radix, StringPart};
 use crate::record_collector::{collect_records, RecordShape};
+use crate::unused_vars::unused_vars;
 
+use std::cell::RefCell;
 use std::cmp::Ordering;
 use std::io::Write;
+use std::rc::Rc;
 
 use bytemuck::cast_slice_mut;
 use smol_str::SmolStr;
 
-pub fn run<W: Write>(w: &mut W, pgm: Vec<L<ast::TopDecl>>, input: &str) {
-    let mut heap = Heap::new();
-    let pgm = Pgm::new(pgm, &mut heap);
+/// Interpreter options.
+#[derive(Debug, Clone)]
+pub struct RunOptions {
+    /// Initial size of the heap, in 8-byte words. The heap grows as needed.
+    pub initial_heap_size_words: usize,
 
-    // Allocate command line arguments to be passed to the program.
-    let input = heap.allocate_str(input.as_bytes());
+    /// Print heap allocation statistics to stderr when the program exits.
+    pub print_heap_stats: bool,
 
-    // Find the main function.
-    let main_fun = pgm
-        .top_level_funs
-        .get("main")
-        .unwrap_or_else(|| panic!("main function not defined"));
-    call(
-        w,
-        &pgm,
-        &mut heap,
-        main_fun,
-        vec![input],
-        // `main` doesn't have a call site, called by the interpreter.
-        &Loc {
-            module: "".into(),
-            line_start: 0,
-            col_start: 0,
-            byte_offset_start: 0,
-            line_end: 0,
-            col_end: 0,
-            byte_offset_end: 0,
-        },
-    );
+    /// Count allocations per expression and print the locations with the most allocations to
+    /// stderr when the program exits.
+    pub profile_allocations: bool,
+
+    /// Print each executed statement with its location, and function entries and exits with
+    /// argument and return values, to stderr.
+    pub trace: bool,
+
+    /// Omit the module from the locations in traces and the allocation profile. For
+    /// single-module programs, where the module is the same in every location.
+    pub compact_locations: bool,
+
+    /// Breakpoints and the callback to call when the program pauses.
+    pub debugger: Option<Rc<RefCell<Debugger>>>,
+
+    /// Print warnings about the program, e.g. unused variables and non-exhaustive matches, to
+    /// stderr before running it.
+    pub print_warnings: bool,
+
+    /// Where to report warnings, traces and statistics. When not set these are printed to stderr.
+    pub events: Option<Rc<RefCell<dyn EventSink>>>,
+
+    /// Time source of the `now()` built-in.
+    pub clock: Clock,
+
+    /// Initial seed of the `random()` built-in. Runs with the same seed produce the same
+    /// numbers.
+    pub random_seed: u64,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        RunOptions {
+            initial_heap_size_words: INITIAL_HEAP_SIZE_WORDS,
+            print_heap_stats: false,
+            profile_allocations: false,
+            trace: false,
+            compact_locations: false,
+            debugger: None,
+            print_warnings: false,
+            events: None,
+            clock: Clock::Monotonic,
+            random_seed: 0,
+        }
+    }
+}
+
+/// Number of locations shown in the allocation profile.
+const ALLOC_PROFILE_NUM_SITES: usize = 20;
+
+/// Validate and run the program. Same as building a `Program` and running it once.
+pub fn run<W: Write>(
+    w: &mut W,
+    pgm: Vec<L<ast::TopDecl>>,
+    input: &str,
+    options: &RunOptions,
+) -> Result<(), RunError> {
+    Program::new(pgm, options)?.run(w, input)
+}
+
+/// A validated program, built once and run any number of times with different inputs.
+///
+/// Each run starts with a new heap, the clock at 0 and the random number generator seeded with
+/// `RunOptions::random_seed`, so runs don't see the values of the previous runs.
+#[derive(Debug)]
+pub struct Program {
+    pgm: Pgm,
+    options: RunOptions,
+}
+
+impl Program {
+    /// Validate and build the program. Warnings are reported here, once, when
+    /// `RunOptions::print_warnings` is set.
+    pub fn new(pgm: Vec<L<ast::TopDecl>>, options: &RunOptions) -> Result<Program, RunError> {
+        let events: Rc<RefCell<dyn EventSink>> = match &options.events {
+            Some(events) => events.clone(),
+            None => Rc::new(RefCell::new(StderrSink)),
+        };
+
+        let (ty_cons, _) = init::collect_types(&pgm);
+        let errors = validate(&pgm, &ty_cons);
+        if !errors.is_empty() {
+            return Err(RunError::InvalidProgram { errors });
+        }
+
+        if options.print_warnings {
+            let mut diagnostics = unused_vars(&pgm);
+            diagnostics.extend(non_exhaustive_matches(&pgm, &ty_cons));
+            for diagnostic in &diagnostics {
+                events.borrow_mut().event(Event::Warning(diagnostic));
+            }
+        }
+
+        let mut pgm = Pgm::new(pgm);
+        pgm.trace = options.trace;
+        pgm.compact_locations = options.compact_locations;
+        pgm.debugger = options.debugger.clone();
+        pgm.events = Some(events);
+        pgm.clock = RunClock::new(options.clock);
+        pgm.random_seed = options.random_seed;
+
+        Ok(Program {
+            pgm,
+            options: options.clone(),
+        })
+    }
+
+    /// Run `main` with the input, in a new heap.
+    pub fn run<W: Write>(&mut self, w: &mut W, input: &str) -> Result<(), RunError> {
+        let mut heap = Heap::with_capacity(self.options.initial_heap_size_words);
+        if self.options.profile_allocations {
+            heap.enable_alloc_profiling();
+        }
+
+        let result = self.pgm.run_main(w, &mut heap, input);
+
+        if self.options.print_heap_stats {
+            self.pgm.event(Event::Stats(&heap.stats().to_string()));
+        }
+
+        if self.options.profile_allocations {
+            self.pgm.event(Event::Stats("Allocation sites:"));
+            for (loc, count) in heap.alloc_profile().iter().take(ALLOC_PROFILE_NUM_SITES) {
+                // Show the end positions too, to tell apart nested expressions that start at
+                // the same location.
+                let line = if self.options.compact_locations {
+                    format!("{:>10} {:#}", count, LocDisplay(loc).without_module())
+                } else {
+                    format!("{:>10} {:#}", count, LocDisplay(loc))
+                };
+                self.pgm.event(Event::Stats(&line));
+            }
+        }
+
+        result
+    }
+}
+
+/// Errors raised by the interpreted program.
+#[derive(Debug, Clone)]
+pub enum RunError {
+    /// Program called `panic`.
+    UserPanic { msg: String, loc: Loc },
+
+    /// Condition of an `assert` call was false.
+    AssertionFailed { msg: Option<String>, loc: Loc },
+
+    /// Integer division or modulo by zero.
+    DivByZero { loc: Loc },
+
+    /// Result of an `I32` arithmetic operator (`+`, `-`, `*`, or negation) doesn't fit into
+    /// `I32`. The `addWrapping`, `addSaturating` and `addChecked` methods can be used for other
+    /// overflow behavior.
+    IntOverflow { loc: Loc },
+
+    /// A constructor was applied to the wrong number of arguments.
+    ConstrArity {
+        constr: String,
+        expected: usize,
+        found: usize,
+        loc: Loc,
+    },
+
+    /// A constructor or record expression is missing a named field.
+    MissingField {
+        constr: String,
+        field: SmolStr,
+        loc: Loc,
+    },
+
+    /// A constructor expression has a named field that the constructor doesn't have.
+    UnknownField {
+        constr: String,
+        field: SmolStr,
+        loc: Loc,
+    },
+
+    /// A constructor with named fields was applied to positional arguments.
+    PositionalFields { constr: String, loc: Loc },
+
+    /// A string view would read bytes outside of the viewed string. This is a bug in the
+    /// interpreter.
+    StrViewOutOfBounds {
+        start: u64,
+        end: u64,
+        len: u64,
+        loc: Loc,
+    },
+
+    /// An array was created with a negative length.
+    NegativeLength { len: i32, loc: Loc },
+
+    /// An index or position passed to a built-in, or an array index, is out of bounds.
+    OutOfBounds { msg: String, loc: Loc },
+
+    /// An array element was read before it was initialized.
+    UninitializedArrayElement { idx: u64, loc: Loc },
+
+    /// A built-in was called with an argument it doesn't accept, e.g. `Option.unwrap` with
+    /// `Option.None`, or a negative count.
+    InvalidArgument { msg: String, loc: Loc },
+
+    /// `now()` was called, but the clock is disabled with `Clock::Disabled`.
+    ClockDisabled { loc: Loc },
+
+    /// The program has errors found before running it, see `validate`.
+    InvalidProgram { errors: Vec<Diagnostic> },
+
+    /// A record expression has both named and unnamed fields.
+    MixedFields { loc: Loc },
+
+    /// An associated constant was read before it's initialized, e.g. in the right-hand side of a
+    /// constant declared before it.
+    ConstNotInitialized {
+        ty: SmolStr,
+        name: SmolStr,
+        loc: Loc,
+    },
+
+    /// A constructor or record expression has a named field more than once.
+    DuplicateField {
+        constr: String,
+        field: SmolStr,
+        loc: Loc,
+    },
+
+    /// A value doesn't have the type expected by the code using it, e.g. the type of the pattern
+    /// it's matched against. The interpreter doesn't have a type checker, so these are found when
+    /// running the code.
+    TypeError { msg: String, loc: Loc },
+
+    /// `format` template is invalid, or the number of placeholders doesn't match the number of
+    /// arguments.
+    FormatError { msg: String, loc: Loc },
+
+    /// The pattern of a `let` statement didn't match the value. `value` is the value rendered as
+    /// string.
+    PatternMismatch { value: String, loc: Loc },
+
+    /// A thrown value was not caught. `value` is the thrown value rendered as string.
+    UncaughtThrow { value: String, loc: Loc },
+
+    /// A call has both named and positional arguments.
+    MixedArgs { loc: Loc },
+
+    /// A call has a named argument that the function doesn't have a parameter for.
+    UnknownArg { fun: String, arg: SmolStr, loc: Loc },
+
+    /// A call has a named argument more than once.
+    DuplicateArg { fun: String, arg: SmolStr, loc: Loc },
+
+    /// A call doesn't pass an argument for a parameter without a default.
+    MissingArg { fun: String, arg: SmolStr, loc: Loc },
+
+    /// A built-in function was called with named arguments.
+    BuiltinNamedArgs { loc: Loc },
+
+    /// An associated function with a `self` parameter was called without a receiver, as
+    /// `Type.fun(...)`.
+    MethodWithoutReceiver { fun: String, loc: Loc },
+
+    /// An associated function without a `self` parameter was called as a method.
+    NotAMethod { fun: String, loc: Loc },
+
+    /// A method was called on a value that doesn't have it. `msg` also lists the methods that the
+    /// value has.
+    MethodNotFound { msg: String, loc: Loc },
+}
+
+impl std::fmt::Display for RunError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RunError::UserPanic { msg, loc } => write!(f, "{}: PANIC: {}", LocDisplay(loc), msg),
+            RunError::AssertionFailed { msg: None, loc } => {
+                write!(f, "{}: assertion failed", LocDisplay(loc))
+            }
+            RunError::AssertionFailed {
+                msg: Some(msg),
+                loc,
+            } => write!(f, "{}: assertion failed: {}", LocDisplay(loc), msg),
+            RunError::DivByZero { loc } => write!(f, "{}: division by zero", LocDisplay(loc)),
+            RunError::IntOverflow { loc } => write!(f, "{}: integer overflow", LocDisplay(loc)),
+            RunError::ConstrArity {
+                constr,
+                expected,
+                found,
+                loc,
+            } => write!(
+                f,
+                "{}: constructor {} takes {} arguments, but {} were given",
+                LocDisplay(loc),
+                constr,
+                expected,
+                found
+            ),
+            RunError::MissingField { constr, field, loc } => write!(
+                f,
+                "{}: {} is missing field {}",
+                LocDisplay(loc),
+                constr,
+                field
+            ),
+            RunError::UnknownField { constr, field, loc } => write!(
+                f,
+                "{}: {} does not have field {}",
+                LocDisplay(loc),
+                constr,
+                field
+            ),
+            RunError::PositionalFields { constr, loc } => write!(
+                f,
+                "{}: {} has named fields, but was given positional arguments",
+                LocDisplay(loc),
+                constr
+            ),
+            RunError::StrViewOutOfBounds {
+                start,
+                end,
+                len,
+                loc,
+            } => write!(
+                f,
+                "{}: string view {}..{} out of bounds, byte length = {}",
+                LocDisplay(loc),
+                start,
+                end,
+                len
+            ),
+            RunError::NegativeLength { len, loc } => {
+                write!(f, "{}: negative array length {}", LocDisplay(loc), len)
+            }
+            RunError::OutOfBounds { msg, loc } | RunError::InvalidArgument { msg, loc } => {
+                write!(f, "{}: {}", LocDisplay(loc), msg)
+            }
+            RunError::UninitializedArrayElement { idx, loc } => write!(
+                f,
+                "{}: Reading uninitialized array element {}",
+                LocDisplay(loc),
+                idx
+            ),
+            RunError::ClockDisabled { loc } => {
+                write!(
+                    f,
+                    "{}: now() is not available, clock is disabled",
+                    LocDisplay(loc)
+                )
+            }
+            RunError::ConstNotInitialized { ty, name, loc } => write!(
+                f,
+                "{}: constant {}.{} is used before it's initialized",
+                LocDisplay(loc),
+                ty,
+                name
+            ),
+            RunError::InvalidProgram { errors } => {
+                for (i, error) in errors.iter().enumerate() {
+                    if i != 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}", error)?;
+                }
+                Ok(())
+            }
+            RunError::MixedFields { loc } => write!(
+                f,
+                "{}: record has both named and unnamed fields",
+                LocDisplay(loc)
+            ),
+            RunError::DuplicateField { constr, field, loc } => write!(
+                f,
+                "{}: field {} of {} is given more than once",
+                LocDisplay(loc),
+                field,
+                constr
+            ),
+            RunError::TypeError { msg, loc } => {
+                write!(f, "{}: type error: {}", LocDisplay(loc), msg)
+            }
+            RunError::FormatError { msg, loc } => {
+                write!(f, "{}: format error: {}", LocDisplay(loc), msg)
+            }
+            RunError::PatternMismatch { value, loc } => write!(
+                f,
+                "{}: value {} does not match the pattern",
+                LocDisplay(loc),
+                value
+            ),
+            RunError::UncaughtThrow { value, loc } => {
+                write!(f, "{}: uncaught throw: {}", LocDisplay(loc), value)
+            }
+            RunError::MixedArgs { loc } => write!(
+                f,
+                "{}: named and positional arguments cannot be mixed",
+                LocDisplay(loc)
+            ),
+            RunError::UnknownArg { fun, arg, loc } => write!(
+                f,
+                "{}: {} does not have parameter {}",
+                LocDisplay(loc),
+                fun,
+                arg
+            ),
+            RunError::DuplicateArg { fun, arg, loc } => write!(
+                f,
+                "{}: argument {} of {} is given more than once",
+                LocDisplay(loc),
+                arg,
+                fun
+            ),
+            RunError::MissingArg { fun, arg, loc } => write!(
+                f,
+                "{}: argument {} of {} is not passed",
+                LocDisplay(loc),
+                arg,
+                fun
+            ),
+            RunError::BuiltinNamedArgs { loc } => write!(
+                f,
+                "{}: built-in functions cannot be called with named arguments",
+                LocDisplay(loc)
+            ),
+            RunError::MethodWithoutReceiver { fun, loc } => write!(
+                f,
+                "{}: {} has a self parameter and needs to be called as a method",
+                LocDisplay(loc),
+                fun
+            ),
+            RunError::NotAMethod { fun, loc } => write!(
+                f,
+                "{}: {} doesn't have a self parameter and cannot be called as a method",
+                LocDisplay(loc),
+                fun
+            ),
+            RunError::MethodNotFound { msg, loc } => write!(f, "{}: {}", LocDisplay(loc), msg),
+        }
+    }
+}
+
+impl std::error::Error for RunError {}
+
+/// Errors of the functions that evaluate the program: the errors returned by `run`, and thrown
+/// values unwinding the call stack to the closest `try`. `run` returns uncaught throws as
+/// `RunError::UncaughtThrow`.
+#[derive(Debug)]
+enum EvalError {
+    Error(RunError),
+
+    /// The thrown value and the location of the `throw`.
+    Throw(u64, Loc),
+}
+
+impl From<RunError> for EvalError {
+    fn from(err: RunError) -> Self {
+        EvalError::Error(err)
+    }
 }
 
 macro_rules! generate_tags {
@@ -76,6 +536,16 @@ generate_tags!(
     CONSTR_TYPE_TAG,    // Constructor closure, e.g. `Option.Some`.
     TOP_FUN_TYPE_TAG,   // Top-level function closure, e.g. `id`.
     ASSOC_FUN_TYPE_TAG, // Associated function closure, e.g. `Value.toString`.
+    ARRAY_ITER_TYPE_TAG,
+    RANGE_TYPE_TAG,
+    RANGE_ITER_TYPE_TAG,
+    CHAR_TYPE_TAG,
+    STR_CHARS_TYPE_TAG,
+    F64_TYPE_TAG,
+    SET_TYPE_TAG,
+    SCANNER_TYPE_TAG,
+    TREE_MAP_TYPE_TAG,
+    STR_BUILDER_TYPE_TAG,
     FIRST_TYPE_TAG,     // First available type tag for user types.
 );
 
@@ -108,6 +578,37 @@ struct Pgm {
     // Some allocations and constructors used by the built-ins.
     true_alloc: u64,
     false_alloc: u64,
+
+    /// Whether to trace statements and function calls, see `RunOptions::trace`.
+    trace: bool,
+
+    /// See `RunOptions::compact_locations`.
+    compact_locations: bool,
+
+    /// See `RunOptions::debugger`.
+    debugger: Option<Rc<RefCell<Debugger>>>,
+
+    /// See `RunOptions::events`. When not set events are printed with `StderrSink`.
+    events: Option<Rc<RefCell<dyn EventSink>>>,
+
+    /// Associated constants, e.g. `I32.MAX`, by type name and constant name. Built-in constants are
+    /// `None` until `init_heap` initializes them, constants declared in the program are `None`
+    /// until `init_consts` initializes them.
+    consts: Map<SmolStr, Map<SmolStr, Option<u64>>>,
+
+    /// Declarations of the constants in the program, in declaration order.
+    const_decls: Vec<L<ast::ConstDecl>>,
+
+    /// See `RunOptions::clock`.
+    clock: RunClock,
+
+    /// See `RunOptions::random_seed`. `run_main` seeds `rng` with it.
+    random_seed: u64,
+
+    rng: Rng,
+
+    /// Results of the calls to `memo` functions.
+    memo_cache: RefCell<MemoCache>,
 }
 
 #[derive(Debug)]
@@ -116,7 +617,8 @@ struct Con {
 
     fields: Fields,
 
-    /// For constructors with no fields, this holds the canonical allocation.
+    /// For constructors with no fields, this holds the canonical allocation, allocated by
+    /// `Pgm::init_heap`.
     alloc: Option<u64>,
 }
 
@@ -225,7 +727,8 @@ impl Fields {
     }
 }
 
-const INITIAL_HEAP_SIZE_WORDS: usize = (1024 * 1024 * 1024) / 8; // 1 GiB
+// Small programs and tests don't need a large heap, larger programs grow the heap as needed.
+const INITIAL_HEAP_SIZE_WORDS: usize = (1024 * 1024) / 8; // 1 MiB
 
 #[derive(Debug)]
 enum ControlFlow {
@@ -234,19 +737,36 @@ enum ControlFlow {
 
     /// Return value from the function.
     Ret(u64),
+
+    /// Return from the function by calling the top-level function with the given index with the
+    /// arguments. The caller reuses its frame for the callee instead of recursing, so tail
+    /// recursive functions run in constant native stack space.
+    TailCall(u64, Vec<Option<u64>>),
+
+    /// Exit the loop with the label, or the closest loop when the label is `None`, with the value
+    /// of the loop.
+    Break(Option<SmolStr>, u64),
+
+    /// Continue with the next iteration of the loop with the label, or the closest loop when the
+    /// label is `None`.
+    Continue(Option<SmolStr>),
 }
 
 macro_rules! val {
     ($expr:expr) => {
-        match $expr {
+        match $expr? {
             ControlFlow::Val(val) => val,
-            ControlFlow::Ret(val) => return ControlFlow::Ret(val),
+            cf => return Ok(cf),
         }
     };
 }
 
 impl Pgm {
-    fn new(pgm: Vec<L<ast::TopDecl>>, heap: &mut Heap) -> Pgm {
+    /// Build the program. The program can be run multiple times with `run_main`, with the same or
+    /// different heaps.
+    fn new(mut pgm: Vec<L<ast::TopDecl>>) -> Pgm {
+        fold_constants(&mut pgm);
+
         // Initialize `ty_cons`.
         let (ty_cons, mut next_type_tag): (Map<SmolStr, TyCon>, u64) = init::collect_types(&pgm);
 
@@ -278,8 +798,9 @@ impl Pgm {
                 });
             } else {
                 for constr in ty_con.value_constrs {
+                    // Allocated by `init_heap`.
                     let alloc: Option<u64> = if constr.fields.is_empty() {
-                        Some(heap.allocate_tag(cons_by_tag.len() as u64))
+                        Some(0)
                     } else {
                         None
                     };
@@ -312,6 +833,28 @@ impl Pgm {
             next_type_tag += 1;
         }
 
+        // Initialize `consts`. The values are initialized by `init_consts`.
+        let (consts, const_decls) = init::collect_consts(&pgm);
+
+        for const_decl in &const_decls {
+            let ast::ConstDecl {
+                type_name, name, ..
+            } = &const_decl.node;
+            let ty_con = ty_cons
+                .get(type_name)
+                .unwrap_or_else(|| panic!("Type not defined: {}", type_name));
+            assert!(
+                !ty_con
+                    .value_constrs
+                    .iter()
+                    .any(|constr| constr.name.as_ref() == Some(name)),
+                "{}: Constant {}.{} has the same name as a constructor",
+                LocDisplay(&const_decl.loc),
+                type_name,
+                name
+            );
+        }
+
         // Initialize `associated_funs` and `top_level_funs`.
         let (top_level_funs, associated_funs) = init::collect_funs(pgm);
 
@@ -319,9 +862,17 @@ impl Pgm {
             vec![Default::default(); next_type_tag as usize];
 
         for (ty_name, funs) in associated_funs {
-            let ty_con = ty_cons
-                .get(&ty_name)
-                .unwrap_or_else(|| panic!("Type not defined: {}", ty_name));
+            let ty_con = match ty_cons.get(&ty_name) {
+                Some(ty_con) => ty_con,
+                // Builtins of types defined in the prelude, when the prelude is not imported.
+                None if funs
+                    .values()
+                    .all(|fun| matches!(fun.kind, FunKind::Builtin(_))) =>
+                {
+                    continue
+                }
+                None => panic!("Type not defined: {}", ty_name),
+            };
             let first_tag = ty_cons.get(&ty_name).unwrap().type_tag as usize;
             let n_constrs = ty_con.value_constrs.len();
             if n_constrs == 0 {
@@ -354,11 +905,6 @@ impl Pgm {
             Some(SmolStr::new("True"))
         );
 
-        let false_alloc = cons_by_tag[bool_ty_con.type_tag as usize].alloc.unwrap();
-        let true_alloc = cons_by_tag[bool_ty_con.type_tag as usize + 1]
-            .alloc
-            .unwrap();
-
         Pgm {
             ty_cons,
             cons_by_tag,
@@ -366,8 +912,144 @@ impl Pgm {
             associated_funs: associated_funs_vec,
             top_level_funs,
             top_level_funs_by_idx,
-            false_alloc,
-            true_alloc,
+            false_alloc: 0,
+            true_alloc: 0,
+            trace: false,
+            compact_locations: false,
+            debugger: None,
+            events: None,
+            consts,
+            const_decls,
+            clock: Default::default(),
+            random_seed: 0,
+            rng: Default::default(),
+            memo_cache: Default::default(),
+        }
+    }
+
+    /// Run `main` with the input, in `heap`. `heap` can be a heap used by a previous run, or a new
+    /// one.
+    fn run_main<W: Write>(
+        &mut self,
+        w: &mut W,
+        heap: &mut Heap,
+        input: &str,
+    ) -> Result<(), RunError> {
+        self.init_heap(heap);
+
+        // Start each run with the same clock and random numbers, for reproducible runs.
+        self.clock.reset();
+        self.rng.seed(self.random_seed);
+
+        // Find the main function. `validate` checks that it takes the input or nothing.
+        let main_fun = self
+            .top_level_funs
+            .get("main")
+            .unwrap_or_else(|| panic!("main function not defined"))
+            .clone();
+
+        let main_decl = match &main_fun.kind {
+            FunKind::Source(fun_decl) => fun_decl,
+            FunKind::Builtin(_) => unreachable!("main is not a builtin"),
+        };
+
+        // Allocate the input to be passed to the program, when `main` takes it.
+        let main_args = if main_decl.params.is_empty() {
+            vec![]
+        } else {
+            vec![heap.allocate_str(input.as_bytes())]
+        };
+
+        // `main` doesn't have a call site, use the location of its body.
+        let main_loc = main_decl.body.loc.clone();
+
+        self.init_consts(w, heap)
+            .and_then(|()| call(w, self, heap, &main_fun, main_args, &main_loc))
+            .map(|_| ())
+            .map_err(|err| match err {
+                EvalError::Error(err) => err,
+                EvalError::Throw(value, loc) => RunError::UncaughtThrow {
+                    value: value_to_string(self, heap, value),
+                    loc,
+                },
+            })
+    }
+
+    /// Allocate the values shared by the program in `heap`: constructors without fields and the
+    /// built-in constants. Values of the previous heap, and memoized results in them, are dropped.
+    fn init_heap(&mut self, heap: &mut Heap) {
+        for (tag, con) in self.cons_by_tag.iter_mut().enumerate() {
+            if let Some(alloc) = &mut con.alloc {
+                *alloc = heap.allocate_tag(tag as u64);
+            }
+        }
+
+        let bool_tag = self.ty_cons.get("Bool").unwrap().type_tag as usize;
+        self.false_alloc = self.cons_by_tag[bool_tag].alloc.unwrap();
+        self.true_alloc = self.cons_by_tag[bool_tag + 1].alloc.unwrap();
+
+        for consts in self.consts.values_mut() {
+            for value in consts.values_mut() {
+                *value = None;
+            }
+        }
+        for (ty, name, value) in init::BUILTIN_CONSTS {
+            *self.consts.get_mut(ty).unwrap().get_mut(name).unwrap() =
+                Some(heap.allocate_i32(value));
+        }
+
+        self.memo_cache = Default::default();
+    }
+
+    /// Initialize the constants declared in the program, in declaration order.
+    fn init_consts<W: Write>(&mut self, w: &mut W, heap: &mut Heap) -> Result<(), EvalError> {
+        for const_decl_idx in 0..self.const_decls.len() {
+            let const_decl = self.const_decls[const_decl_idx].clone();
+            let ast::ConstDecl {
+                type_name,
+                name,
+                ty: _,
+                rhs,
+            } = &const_decl.node;
+            let value = match eval(w, self, heap, &mut Default::default(), rhs)? {
+                ControlFlow::Val(value) => value,
+                ControlFlow::Ret(_) | ControlFlow::TailCall(_, _) => {
+                    panic!("{}: return outside of a function", LocDisplay(&rhs.loc))
+                }
+                ControlFlow::Break(_, _) | ControlFlow::Continue(_) => {
+                    panic!(
+                        "{}: break or continue outside of a loop",
+                        LocDisplay(&rhs.loc)
+                    )
+                }
+            };
+            *self
+                .consts
+                .get_mut(type_name)
+                .unwrap()
+                .get_mut(name)
+                .unwrap() = Some(value);
+        }
+        Ok(())
+    }
+
+    fn event(&self, event: Event) {
+        match &self.events {
+            Some(events) => events.borrow_mut().event(event),
+            None => StderrSink.event(event),
+        }
+    }
+
+    fn trace(&self, line: &str) {
+        self.event(Event::Trace(line));
+    }
+
+    /// Formats a location for traces, see `RunOptions::compact_locations`.
+    fn trace_loc(&self, loc: &Loc) -> String {
+        if self.compact_locations {
+            LocDisplay(loc).without_module().to_string()
+        } else {
+            LocDisplay(loc).to_string()
         }
     }
 
@@ -391,13 +1073,185 @@ fn call<W: Write>(
     fun: &Fun,
     args: Vec<u64>,
     loc: &Loc,
-) -> u64 {
+) -> Result<u64, EvalError> {
+    call_ordered(w, pgm, heap, fun, args.into_iter().map(Some).collect(), loc)
+}
+
+/// Call `fun` with arguments ordered by `order_args`. `None` arguments are omitted by the caller,
+/// and the parameter defaults are used instead.
+fn call_ordered<W: Write>(
+    w: &mut W,
+    pgm: &Pgm,
+    heap: &mut Heap,
+    fun: &Fun,
+    args: Vec<Option<u64>>,
+    loc: &Loc,
+) -> Result<u64, EvalError> {
     match &fun.kind {
-        FunKind::Builtin(builtin) => call_builtin_fun(w, pgm, heap, builtin, args, loc),
+        // Built-ins can't be called with named arguments, so all arguments are passed.
+        FunKind::Builtin(builtin) => {
+            let args = args.into_iter().map(Option::unwrap).collect();
+            call_builtin_fun(w, pgm, heap, builtin, args, loc)
+        }
         FunKind::Source(source) => call_source_fun(w, pgm, heap, source, args, loc),
     }
 }
 
+/// Reorder evaluated call arguments to match the parameter order of `fun`.
+///
+/// `arg_values` are the values of `args`, evaluated in the order they appear in the call. When
+/// `receiver` is true the call is a method call and the receiver is not in `args`, otherwise a
+/// `self` parameter needs to be passed as an argument.
+fn order_args(
+    fun: &Fun,
+    receiver: bool,
+    args: &[ast::CallArg],
+    arg_values: Vec<u64>,
+    loc: &Loc,
+) -> Result<Vec<Option<u64>>, RunError> {
+    if args.iter().all(|arg| arg.name.is_none()) {
+        return Ok(arg_values.into_iter().map(Some).collect());
+    }
+
+    if args.iter().any(|arg| arg.name.is_none()) {
+        return Err(RunError::MixedArgs { loc: loc.clone() });
+    }
+
+    let fun_decl = match &fun.kind {
+        FunKind::Source(fun_decl) => fun_decl,
+        FunKind::Builtin(_) => return Err(RunError::BuiltinNamedArgs { loc: loc.clone() }),
+    };
+
+    let self_param: Option<&str> = if fun_decl.self_ && !receiver {
+        Some("self")
+    } else {
+        None
+    };
+    let param_names: Vec<&str> = self_param
+        .into_iter()
+        .chain(fun_decl.params.iter().map(|param| param.name.as_str()))
+        .collect();
+    let num_self_params = param_names.len() - fun_decl.params.len();
+
+    let mut ordered: Vec<Option<u64>> = vec![None; param_names.len()];
+    for (arg, arg_value) in args.iter().zip(arg_values) {
+        let arg_name = arg.name.as_ref().unwrap();
+        let param_idx = match param_names
+            .iter()
+            .position(|param_name| param_name == arg_name)
+        {
+            Some(param_idx) => param_idx,
+            None => {
+                return Err(RunError::UnknownArg {
+                    fun: fun_display_name(fun_decl),
+                    arg: arg_name.clone(),
+                    loc: loc.clone(),
+                })
+            }
+        };
+        if ordered[param_idx].replace(arg_value).is_some() {
+            return Err(RunError::DuplicateArg {
+                fun: fun_display_name(fun_decl),
+                arg: arg_name.clone(),
+                loc: loc.clone(),
+            });
+        }
+    }
+
+    // Parameters with defaults that are not passed are left as `None`, for `run_source_fun` to
+    // fill.
+    for (param_idx, (arg_value, param_name)) in ordered.iter().zip(param_names).enumerate() {
+        if arg_value.is_none()
+            && (param_idx < num_self_params
+                || fun_decl.params[param_idx - num_self_params]
+                    .default
+                    .is_none())
+        {
+            return Err(RunError::MissingArg {
+                fun: fun_display_name(fun_decl),
+                arg: SmolStr::new(param_name),
+                loc: loc.clone(),
+            });
+        }
+    }
+
+    Ok(ordered)
+}
+
+/// Evaluate the arguments of a call to `fun` and call it. `receiver` is the receiver of a method
+/// call, which is passed as the first argument.
+fn eval_args_and_call<W: Write>(
+    w: &mut W,
+    pgm: &Pgm,
+    heap: &mut Heap,
+    locals: &mut Map<SmolStr, u64>,
+    fun: &Fun,
+    receiver: Option<u64>,
+    args: &[ast::CallArg],
+    loc: &Loc,
+) -> Result<ControlFlow, EvalError> {
+    let mut arg_values: Vec<u64> = Vec::with_capacity(args.len());
+    for arg in args {
+        arg_values.push(val!(eval(w, pgm, heap, locals, &arg.expr)));
+    }
+    let mut arg_values = order_args(fun, receiver.is_some(), args, arg_values, loc)?;
+    if let Some(receiver) = receiver {
+        arg_values.insert(0, Some(receiver));
+    }
+    Ok(ControlFlow::Val(call_ordered(
+        w, pgm, heap, fun, arg_values, loc,
+    )?))
+}
+
+/// Call a function value: a constructor, top-level function, or associated function closure.
+fn call_fun_value<W: Write>(
+    w: &mut W,
+    pgm: &Pgm,
+    heap: &mut Heap,
+    fun: u64,
+    args: Vec<u64>,
+    loc: &Loc,
+) -> Result<u64, EvalError> {
+    match heap.tag(pgm, fun) {
+        CONSTR_TYPE_TAG => {
+            let constr_tag = heap[fun + 1];
+            let num_fields = match pgm.get_tag_fields(constr_tag) {
+                Fields::Unnamed(arity) => *arity as usize,
+                Fields::Named(names) => names.len(),
+            };
+            if num_fields != args.len() {
+                return Err(RunError::ConstrArity {
+                    constr: constr_name(pgm, constr_tag),
+                    expected: num_fields,
+                    found: args.len(),
+                    loc: loc.clone(),
+                }
+                .into());
+            }
+            let object = heap.allocate(1 + args.len());
+            heap[object] = constr_tag;
+            for (arg_idx, arg_value) in args.into_iter().enumerate() {
+                heap[object + 1 + (arg_idx as u64)] = arg_value;
+            }
+            Ok(object)
+        }
+
+        TOP_FUN_TYPE_TAG => {
+            let top_fun_idx = heap[fun + 1];
+            let top_fun = &pgm.top_level_funs_by_idx[top_fun_idx as usize];
+            call(w, pgm, heap, top_fun, args, loc)
+        }
+
+        ASSOC_FUN_TYPE_TAG => {
+            let _ty_tag = heap[fun + 1];
+            let _fun_tag = heap[fun + 2];
+            todo!()
+        }
+
+        _ => panic!("{}: Function evaluated to non-callable", LocDisplay(loc)),
+    }
+}
+
 fn call_method<W: Write>(
     w: &mut W,
     pgm: &Pgm,
@@ -406,61 +1260,270 @@ fn call_method<W: Write>(
     method: &SmolStr,
     mut args: Vec<u64>,
     loc: &Loc,
-) -> u64 {
-    let tag = heap[receiver];
-    let fun = pgm.associated_funs[tag as usize]
-        .get(method)
-        .unwrap_or_else(|| panic!("Receiver with tag {} does not have {} method", tag, method));
+) -> Result<u64, EvalError> {
+    let tag = heap.tag(pgm, receiver);
+    let fun = match pgm.associated_funs[tag as usize].get(method) {
+        Some(fun) => fun,
+        None => {
+            // Values without a user-defined `toStr` are rendered structurally. `toDebugStr` is
+            // always structural unless defined by the user: unlike `toStr` of `Str` it quotes and
+            // escapes strings.
+            if (method == "toStr" || method == "toDebugStr") && args.is_empty() {
+                let str = value_to_string(pgm, heap, receiver);
+                return Ok(heap.allocate_str(str.as_bytes()));
+            }
+            // Values without a user-defined `__eq` are compared structurally.
+            if method == "__eq" && args.len() == 1 {
+                return Ok(pgm.bool_alloc(structural_eq(pgm, heap, receiver, args[0])));
+            }
+            // Records without a user-defined `__cmp` are compared lexicographically.
+            if method == "__cmp"
+                && args.len() == 1
+                && matches!(pgm.cons_by_tag[tag as usize].info, ConInfo::Record { .. })
+            {
+                let ordering = record_cmp(w, pgm, heap, receiver, args[0], loc)?;
+                return Ok(ordering_value(pgm, ordering));
+            }
+            return Err(method_not_found(pgm, tag, method, loc).into());
+        }
+    };
     args.insert(0, receiver);
     call(w, pgm, heap, fun, args, loc)
 }
 
+/// Error for calling a method that the receiver doesn't have. Lists the methods that the receiver
+/// has, to make typos in method names easy to spot.
+fn method_not_found(pgm: &Pgm, tag: u64, method: &str, loc: &Loc) -> RunError {
+    let mut methods: Vec<&str> = pgm.associated_funs[tag as usize]
+        .keys()
+        .map(|method| method.as_str())
+        .collect();
+    methods.sort_unstable();
+    let methods = if methods.is_empty() {
+        "it does not have any methods".to_string()
+    } else {
+        format!("available methods: {}", methods.join(", "))
+    };
+    RunError::MethodNotFound {
+        msg: format!(
+            "{} does not have method {}, {}",
+            constr_name(pgm, tag),
+            method,
+            methods
+        ),
+        loc: loc.clone(),
+    }
+}
+
 fn call_source_fun<W: Write>(
     w: &mut W,
     pgm: &Pgm,
     heap: &mut Heap,
     fun: &ast::FunDecl,
-    args: Vec<u64>,
+    mut args: Vec<Option<u64>>,
     loc: &Loc,
-) -> u64 {
-    assert_eq!(
-        fun.num_params(),
-        args.len() as u32,
-        "{}, fun: {}",
-        LocDisplay(loc),
-        fun.name
-    );
-
-    let mut locals: Map<SmolStr, u64> = Default::default();
-
-    let mut arg_idx: usize = 0;
-    if fun.self_ {
-        locals.insert(SmolStr::new("self"), args[0]);
-        arg_idx += 1;
-    }
-
-    for (param_name, _param_type) in &fun.params {
-        let old = locals.insert(param_name.clone(), args[arg_idx]);
-        assert!(old.is_none());
-        arg_idx += 1;
+) -> Result<u64, EvalError> {
+    if !fun.memo {
+        return run_source_fun(w, pgm, heap, fun, args, loc);
     }
 
-    match exec(w, pgm, heap, &mut locals, &fun.body.node) {
-        ControlFlow::Val(val) | ControlFlow::Ret(val) => val,
+    // Omitted arguments are cached as `None`, so that calls with and without the omitted trailing
+    // arguments have the same keys.
+    args.resize(fun.num_params() as usize, None);
+    if let Some(result) = pgm.memo_cache.borrow().get(pgm, heap, fun, &args) {
+        return Ok(result);
     }
+    let result = run_source_fun(w, pgm, heap, fun, args.clone(), loc)?;
+    pgm.memo_cache.borrow_mut().insert(heap, fun, args, result);
+    Ok(result)
 }
 
-/// Allocate an object from type name and optional constructor name.
-fn allocate_object_from_names<W: Write>(
+fn run_source_fun<W: Write>(
     w: &mut W,
     pgm: &Pgm,
     heap: &mut Heap,
-    locals: &mut Map<SmolStr, u64>,
-    ty: &SmolStr,
-    constr_name: Option<SmolStr>,
-    args: &[ast::CallArg],
+    fun: &ast::FunDecl,
+    args: Vec<Option<u64>>,
     loc: &Loc,
-) -> ControlFlow {
+) -> Result<u64, EvalError> {
+    // Each iteration runs one function body. Tail calls to top-level source functions update `fun`
+    // and `args` and continue the loop instead of recursing.
+    let mut fun: &ast::FunDecl = fun;
+    let mut args: Vec<Option<u64>> = args;
+    loop {
+        // Trailing arguments can be omitted when the parameters have defaults.
+        assert!(
+            args.len() as u32 <= fun.num_params(),
+            "{}, fun: {}",
+            LocDisplay(loc),
+            fun.name
+        );
+        args.resize(fun.num_params() as usize, None);
+
+        let mut locals: Map<SmolStr, u64> = Default::default();
+
+        let mut arg_idx: usize = 0;
+        if fun.self_ {
+            let self_ = args[0].ok_or_else(|| RunError::MissingArg {
+                fun: fun_display_name(fun),
+                arg: SmolStr::new("self"),
+                loc: loc.clone(),
+            })?;
+            locals.insert(SmolStr::new("self"), self_);
+            arg_idx += 1;
+        }
+
+        // `None` arguments are not passed by the caller, use the parameter default.
+        for param in &fun.params {
+            let arg = match (args[arg_idx], &param.default) {
+                (Some(arg), _) => arg,
+                (None, Some(default)) => match eval(w, pgm, heap, &mut locals, default)? {
+                    ControlFlow::Val(val) => val,
+                    _ => panic!(
+                        "{}: Default of parameter {} of {} returned",
+                        LocDisplay(&default.loc),
+                        param.name,
+                        fun.name
+                    ),
+                },
+                (None, None) => {
+                    return Err(RunError::MissingArg {
+                        fun: fun_display_name(fun),
+                        arg: param.name.clone(),
+                        loc: loc.clone(),
+                    }
+                    .into())
+                }
+            };
+            let old = locals.insert(param.name.clone(), arg);
+            assert!(old.is_none());
+            arg_idx += 1;
+        }
+
+        if pgm.trace {
+            let args: Vec<String> = fun
+                .params
+                .iter()
+                .map(|param| trace_value(pgm, heap, *locals.get(&param.name).unwrap()))
+                .collect();
+            pgm.trace(&format!(
+                "{}: enter {}({})",
+                pgm.trace_loc(loc),
+                fun_display_name(fun),
+                args.join(", ")
+            ));
+        }
+
+        let result = exec(w, pgm, heap, &mut locals, &fun.body.node);
+
+        if pgm.trace {
+            let name = fun_display_name(fun);
+            match &result {
+                Ok(ControlFlow::Val(val) | ControlFlow::Ret(val)) => {
+                    pgm.trace(&format!("exit {} = {}", name, trace_value(pgm, heap, *val)))
+                }
+                Ok(ControlFlow::TailCall(_, _)) => pgm.trace(&format!("exit {}: tail call", name)),
+                Ok(ControlFlow::Break(_, _) | ControlFlow::Continue(_)) => {}
+                Err(EvalError::Throw(val, _)) => pgm.trace(&format!(
+                    "exit {}: throw {}",
+                    name,
+                    trace_value(pgm, heap, *val)
+                )),
+                Err(EvalError::Error(err)) => pgm.trace(&format!("exit {}: error: {}", name, err)),
+            }
+        }
+
+        match result? {
+            ControlFlow::Val(val) | ControlFlow::Ret(val) => return Ok(val),
+            ControlFlow::Break(_, _) | ControlFlow::Continue(_) => {
+                panic!("{}: break or continue outside of a loop", fun.name)
+            }
+            ControlFlow::TailCall(fun_idx, tail_args) => {
+                match &pgm.top_level_funs_by_idx[fun_idx as usize].kind {
+                    // Tail calls to memoized functions are not eliminated, as the result needs to
+                    // be cached.
+                    FunKind::Source(tail_fun) if tail_fun.memo => {
+                        return call_source_fun(w, pgm, heap, tail_fun, tail_args, loc);
+                    }
+                    FunKind::Source(tail_fun) => {
+                        fun = tail_fun;
+                        args = tail_args;
+                    }
+                    // `return` only makes tail calls to source functions.
+                    FunKind::Builtin(_) => unreachable!(),
+                }
+            }
+        }
+    }
+}
+
+/// Check that an associated function called as a method (`value.f(...)`) has a `self` parameter,
+/// and that one called on the type (`Type.f(...)`) doesn't. Built-ins are not checked.
+fn check_self_param(fun: &Fun, method_call: bool, loc: &Loc) -> Result<(), RunError> {
+    let fun_decl = match &fun.kind {
+        FunKind::Builtin(_) => return Ok(()),
+        FunKind::Source(fun_decl) => fun_decl,
+    };
+    if fun_decl.self_ && !method_call {
+        return Err(RunError::MethodWithoutReceiver {
+            fun: fun_display_name(fun_decl),
+            loc: loc.clone(),
+        });
+    }
+    if !fun_decl.self_ && method_call {
+        return Err(RunError::NotAMethod {
+            fun: fun_display_name(fun_decl),
+            loc: loc.clone(),
+        });
+    }
+    Ok(())
+}
+
+/// Maximum number of characters of a value shown in traces.
+const TRACE_VALUE_MAX_CHARS: usize = 40;
+
+/// Render a value for tracing, truncated to `TRACE_VALUE_MAX_CHARS` characters.
+fn trace_value(pgm: &Pgm, heap: &Heap, value: u64) -> String {
+    let mut s = value_to_string(pgm, heap, value);
+    if let Some((idx, _)) = s.char_indices().nth(TRACE_VALUE_MAX_CHARS) {
+        s.truncate(idx);
+        s.push_str("...");
+    }
+    s
+}
+
+/// `Type.fun` for associated functions, `fun` for top-level functions.
+fn fun_display_name(fun: &ast::FunDecl) -> String {
+    match &fun.type_name {
+        Some(type_name) => format!("{}.{}", type_name, fun.name),
+        None => fun.name.to_string(),
+    }
+}
+
+fn stmt_kind(stmt: &ast::Stmt) -> &'static str {
+    match stmt {
+        ast::Stmt::Let(_) => "let",
+        ast::Stmt::Assign(_) => "assign",
+        ast::Stmt::Expr(_) => "expr",
+        ast::Stmt::For(_) => "for",
+        ast::Stmt::While(_) => "while",
+        ast::Stmt::WhileLet(_) => "while let",
+        ast::Stmt::Break(_, _) => "break",
+        ast::Stmt::Continue(_) => "continue",
+    }
+}
+
+/// Allocate an object from type name and optional constructor name.
+fn allocate_object_from_names<W: Write>(
+    w: &mut W,
+    pgm: &Pgm,
+    heap: &mut Heap,
+    locals: &mut Map<SmolStr, u64>,
+    ty: &SmolStr,
+    constr_name: Option<SmolStr>,
+    args: &[ast::CallArg],
+    loc: &Loc,
+) -> Result<ControlFlow, EvalError> {
     let ty_con = pgm
         .ty_cons
         .get(ty)
@@ -495,6 +1558,7 @@ fn allocate_object_from_names<W: Write>(
         locals,
         ty_con.type_tag + constr_idx as u64,
         args,
+        loc,
     )
 }
 
@@ -506,29 +1570,61 @@ fn allocate_object_from_tag<W: Write>(
     locals: &mut Map<SmolStr, u64>,
     constr_tag: u64,
     args: &[ast::CallArg],
-) -> ControlFlow {
+    loc: &Loc,
+) -> Result<ControlFlow, EvalError> {
     let fields = pgm.get_tag_fields(constr_tag);
     let mut arg_values = Vec::with_capacity(args.len());
 
     match fields {
         Fields::Unnamed(num_fields) => {
+            if *num_fields as usize != args.len() {
+                return Err(RunError::ConstrArity {
+                    constr: constr_name(pgm, constr_tag),
+                    expected: *num_fields as usize,
+                    found: args.len(),
+                    loc: loc.clone(),
+                }
+                .into());
+            }
+
+            if let Some(name) = args.iter().find_map(|arg| arg.name.as_ref()) {
+                return Err(RunError::UnknownField {
+                    constr: constr_name(pgm, constr_tag),
+                    field: name.clone(),
+                    loc: loc.clone(),
+                }
+                .into());
+            }
+
             // Evaluate in program order and store in the same order.
-            assert_eq!(*num_fields as usize, args.len());
             for arg in args {
-                assert!(arg.name.is_none());
                 arg_values.push(val!(eval(w, pgm, heap, locals, &arg.expr)));
             }
         }
 
         Fields::Named(field_names) => {
-            // Evalaute in program order, store based on the order of the names
+            if args.iter().any(|arg| arg.name.is_none()) {
+                return Err(RunError::PositionalFields {
+                    constr: constr_name(pgm, constr_tag),
+                    loc: loc.clone(),
+                }
+                .into());
+            }
+
+            check_named_fields(
+                field_names,
+                args.iter().map(|arg| arg.name.as_ref().unwrap()),
+                || constr_name(pgm, constr_tag),
+                loc,
+            )?;
+
+            // Evaluate in program order, store based on the order of the names
             // in the type.
             let mut named_values: Map<SmolStr, u64> = Default::default();
             for arg in args {
                 let name = arg.name.as_ref().unwrap().clone();
                 let value = val!(eval(w, pgm, heap, locals, &arg.expr));
-                let old = named_values.insert(name.clone(), value);
-                assert!(old.is_none());
+                named_values.insert(name, value);
             }
             for name in field_names {
                 arg_values.push(*named_values.get(name).unwrap());
@@ -542,7 +1638,42 @@ fn allocate_object_from_tag<W: Write>(
         heap[object + 1 + (arg_idx as u64)] = arg_value;
     }
 
-    ControlFlow::Val(object)
+    Ok(ControlFlow::Val(object))
+}
+
+/// Check that the named fields `given` in a constructor or record expression are the fields
+/// `expected`, each given exactly once.
+fn check_named_fields<'a>(
+    expected: &[SmolStr],
+    given: impl Iterator<Item = &'a SmolStr>,
+    constr_name: impl Fn() -> String,
+    loc: &Loc,
+) -> Result<(), RunError> {
+    let mut seen: Set<&SmolStr> = Default::default();
+    for name in given {
+        if !expected.contains(name) {
+            return Err(RunError::UnknownField {
+                constr: constr_name(),
+                field: name.clone(),
+                loc: loc.clone(),
+            });
+        }
+        if !seen.insert(name) {
+            return Err(RunError::DuplicateField {
+                constr: constr_name(),
+                field: name.clone(),
+                loc: loc.clone(),
+            });
+        }
+    }
+    if let Some(missing) = expected.iter().find(|name| !seen.contains(name)) {
+        return Err(RunError::MissingField {
+            constr: constr_name(),
+            field: missing.clone(),
+            loc: loc.clone(),
+        });
+    }
+    Ok(())
 }
 
 fn exec<W: Write>(
@@ -551,16 +1682,36 @@ fn exec<W: Write>(
     heap: &mut Heap,
     locals: &mut Map<SmolStr, u64>,
     stmts: &[L<ast::Stmt>],
-) -> ControlFlow {
+) -> Result<ControlFlow, EvalError> {
     let mut return_value: u64 = 0;
 
     for stmt in stmts {
+        if pgm.trace {
+            pgm.trace(&format!(
+                "{}: {}",
+                pgm.trace_loc(&stmt.loc),
+                stmt_kind(&stmt.node)
+            ));
+        }
+
+        if let Some(debugger) = &pgm.debugger {
+            debugger
+                .borrow_mut()
+                .before_stmt(pgm, heap, locals, &stmt.loc);
+        }
+
         return_value = match &stmt.node {
             ast::Stmt::Let(ast::LetStatement { lhs, ty: _, rhs }) => {
                 let val = val!(eval(w, pgm, heap, locals, rhs));
-                match try_bind_pat(pgm, heap, lhs, val) {
-                    Some(binds) => locals.extend(binds.into_iter()),
-                    None => panic!("Pattern binding at {} failed", LocDisplay(&stmt.loc)),
+                match try_bind_pat(pgm, heap, lhs, val)? {
+                    Some(binds) => locals.extend(binds),
+                    None => {
+                        return Err(RunError::PatternMismatch {
+                            value: value_to_string(pgm, heap, val),
+                            loc: stmt.loc.clone(),
+                        }
+                        .into())
+                    }
                 }
                 val
             }
@@ -572,86 +1723,213 @@ fn exec<W: Write>(
 
             ast::Stmt::Expr(expr) => val!(eval(w, pgm, heap, locals, expr)),
 
-            ast::Stmt::While(ast::WhileStatement { cond, body }) => loop {
+            ast::Stmt::While(ast::WhileStatement { label, cond, body }) => loop {
                 let cond = val!(eval(w, pgm, heap, locals, cond));
                 debug_assert!(cond == pgm.true_alloc || cond == pgm.false_alloc);
                 if cond == pgm.false_alloc {
                     break 0; // FIXME: Return unit
                 }
-                match exec(w, pgm, heap, locals, body) {
-                    ControlFlow::Val(_val) => {}
-                    ControlFlow::Ret(val) => return ControlFlow::Ret(val),
+                match exec(w, pgm, heap, locals, body)? {
+                    ControlFlow::Val(_) => {}
+                    ControlFlow::Continue(target) if targets_loop(&target, label) => {}
+                    ControlFlow::Break(target, val) if targets_loop(&target, label) => break val,
+                    cf => return Ok(cf),
+                }
+            },
+
+            ast::Stmt::WhileLet(ast::WhileLetStatement {
+                label,
+                pat,
+                expr,
+                body,
+            }) => loop {
+                let val = val!(eval(w, pgm, heap, locals, expr));
+                match try_bind_pat(pgm, heap, pat, val)? {
+                    Some(binds) => locals.extend(binds),
+                    None => break 0, // unit
+                }
+                match exec(w, pgm, heap, locals, body)? {
+                    ControlFlow::Val(_) => {}
+                    ControlFlow::Continue(target) if targets_loop(&target, label) => {}
+                    ControlFlow::Break(target, val) if targets_loop(&target, label) => break val,
+                    cf => return Ok(cf),
                 }
             },
 
+            ast::Stmt::Break(label, expr) => {
+                let val = match expr {
+                    Some(expr) => val!(eval(w, pgm, heap, locals, expr)),
+                    None => 0, // unit
+                };
+                return Ok(ControlFlow::Break(label.clone(), val));
+            }
+
+            ast::Stmt::Continue(label) => return Ok(ControlFlow::Continue(label.clone())),
+
             ast::Stmt::For(ast::ForStatement {
+                label,
                 var,
                 ty: _,
                 expr,
                 body,
             }) => {
-                let (from, to, inclusive) = match &expr.node {
+                // Value of the loop, set by `break`.
+                let mut loop_value: u64 = 0;
+
+                match &expr.node {
                     ast::Expr::Range(ast::RangeExpr {
                         from,
                         to,
                         inclusive,
-                    }) => (from, to, inclusive),
-                    _ => panic!(
-                        "Interpreter only supports for loops with a range expression in the head"
-                    ),
-                };
-
-                let from = val!(eval(w, pgm, heap, locals, from));
-                debug_assert_eq!(heap[from], I32_TYPE_TAG);
-                let from = heap[from + 1] as i32;
-
-                let to = val!(eval(w, pgm, heap, locals, to));
-                debug_assert_eq!(heap[to], I32_TYPE_TAG);
-                let to = heap[to + 1] as i32;
-
-                if *inclusive {
-                    for i in from..=to {
-                        let iter_value = heap.allocate_i32(i);
-                        locals.insert(var.clone(), iter_value);
-                        match exec(w, pgm, heap, locals, body) {
-                            ControlFlow::Val(_) => {}
-                            ControlFlow::Ret(val) => {
-                                locals.remove(var);
-                                return ControlFlow::Ret(val);
+                    }) => {
+                        // Iterate ranges without allocating range and iterator objects.
+                        let from = val!(eval(w, pgm, heap, locals, from));
+                        debug_assert_eq!(heap.tag(pgm, from), I32_TYPE_TAG);
+                        let from = heap[from + 1] as i32;
+
+                        let to = val!(eval(w, pgm, heap, locals, to));
+                        debug_assert_eq!(heap.tag(pgm, to), I32_TYPE_TAG);
+                        let to = heap[to + 1] as i32;
+
+                        for i in range_values(from, to, *inclusive, 1) {
+                            let iter_value = heap.allocate_i32(i);
+                            locals.insert(var.clone(), iter_value);
+                            match exec(w, pgm, heap, locals, body)? {
+                                ControlFlow::Val(_) => {}
+                                ControlFlow::Continue(target) if targets_loop(&target, label) => {}
+                                ControlFlow::Break(target, val) if targets_loop(&target, label) => {
+                                    loop_value = val;
+                                    break;
+                                }
+                                cf => {
+                                    locals.remove(var);
+                                    return Ok(cf);
+                                }
                             }
                         }
                     }
-                } else {
-                    for i in from..to {
-                        let iter_value = heap.allocate_i32(i);
-                        locals.insert(var.clone(), iter_value);
-                        match exec(w, pgm, heap, locals, body) {
-                            ControlFlow::Val(_) => {}
-                            ControlFlow::Ret(val) => {
-                                locals.remove(var);
-                                return ControlFlow::Ret(val);
+
+                    _ => {
+                        let iterable = val!(eval(w, pgm, heap, locals, expr));
+
+                        if heap.tag(pgm, iterable) == RANGE_TYPE_TAG {
+                            let (from, to, inclusive, step) = heap.range_fields(iterable);
+                            for i in range_values(from, to, inclusive, step) {
+                                let iter_value = heap.allocate_i32(i);
+                                locals.insert(var.clone(), iter_value);
+                                match exec(w, pgm, heap, locals, body)? {
+                                    ControlFlow::Val(_) => {}
+                                    ControlFlow::Continue(target)
+                                        if targets_loop(&target, label) => {}
+                                    ControlFlow::Break(target, val)
+                                        if targets_loop(&target, label) =>
+                                    {
+                                        loop_value = val;
+                                        break;
+                                    }
+                                    cf => {
+                                        locals.remove(var);
+                                        return Ok(cf);
+                                    }
+                                }
+                            }
+                        } else {
+                            // Iterate with the `iter` and `next` methods. `next` returns `Option`.
+                            let iter = call_method(
+                                w,
+                                pgm,
+                                heap,
+                                iterable,
+                                &"iter".into(),
+                                vec![],
+                                &expr.loc,
+                            )?;
+                            let some_tag = constr_tag(pgm, "Option", "Some");
+                            loop {
+                                let next = call_method(
+                                    w,
+                                    pgm,
+                                    heap,
+                                    iter,
+                                    &"next".into(),
+                                    vec![],
+                                    &expr.loc,
+                                )?;
+                                if heap.tag(pgm, next) != some_tag {
+                                    debug_assert_eq!(
+                                        heap.tag(pgm, next),
+                                        constr_tag(pgm, "Option", "None")
+                                    );
+                                    break;
+                                }
+                                locals.insert(var.clone(), heap[next + 1]);
+                                match exec(w, pgm, heap, locals, body)? {
+                                    ControlFlow::Val(_) => {}
+                                    ControlFlow::Continue(target)
+                                        if targets_loop(&target, label) => {}
+                                    ControlFlow::Break(target, val)
+                                        if targets_loop(&target, label) =>
+                                    {
+                                        loop_value = val;
+                                        break;
+                                    }
+                                    cf => {
+                                        locals.remove(var);
+                                        return Ok(cf);
+                                    }
+                                }
                             }
                         }
                     }
                 }
 
                 locals.remove(var);
-                0
+                loop_value
             }
         };
     }
 
-    ControlFlow::Val(return_value)
+    Ok(ControlFlow::Val(return_value))
 }
 
+/// Whether a `break` or `continue` with the label `target` exits or continues the loop with the
+/// label `label`. `break` and `continue` without a label target the closest loop.
+fn targets_loop(target: &Option<SmolStr>, label: &Option<SmolStr>) -> bool {
+    target.is_none() || target == label
+}
+
+/// Evaluate an expression.
+///
+/// Subexpressions are evaluated left to right in source order: the callee or receiver before the
+/// arguments, and arguments (also named arguments to functions and constructors, and record
+/// fields) in the order they are written, before they're reordered to match the parameters or
+/// fields.
 fn eval<W: Write>(
     w: &mut W,
     pgm: &Pgm,
     heap: &mut Heap,
     locals: &mut Map<SmolStr, u64>,
     expr: &L<ast::Expr>,
-) -> ControlFlow {
-    match &expr.node {
+) -> Result<ControlFlow, EvalError> {
+    if !heap.alloc_profiling_enabled() {
+        return eval_expr(w, pgm, heap, locals, expr);
+    }
+
+    // Attribute allocations to the innermost expression being evaluated.
+    let parent_loc = heap.set_alloc_site(Some(expr.loc.clone()));
+    let result = eval_expr(w, pgm, heap, locals, expr);
+    heap.set_alloc_site(parent_loc);
+    result
+}
+
+fn eval_expr<W: Write>(
+    w: &mut W,
+    pgm: &Pgm,
+    heap: &mut Heap,
+    locals: &mut Map<SmolStr, u64>,
+    expr: &L<ast::Expr>,
+) -> Result<ControlFlow, EvalError> {
+    Ok(match &expr.node {
         ast::Expr::Var(var) => match locals.get(var) {
             Some(value) => ControlFlow::Val(*value),
             None => match pgm.top_level_funs.get(var) {
@@ -670,7 +1948,7 @@ fn eval<W: Write>(
 
         ast::Expr::FieldSelect(ast::FieldSelectExpr { object, field }) => {
             let object = val!(eval(w, pgm, heap, locals, object));
-            let object_tag = heap[object];
+            let object_tag = heap.tag(pgm, object);
             let fields = pgm.get_tag_fields(object_tag);
             match fields {
                 Fields::Unnamed(_) => panic!(
@@ -694,6 +1972,23 @@ fn eval<W: Write>(
             ty,
             constr: constr_name,
         }) => {
+            // Associated constants are selected like constructors, e.g. `I32.MAX`.
+            if let Some(value) = pgm
+                .consts
+                .get(ty)
+                .and_then(|consts| consts.get(constr_name))
+            {
+                return match value {
+                    Some(value) => Ok(ControlFlow::Val(*value)),
+                    None => Err(RunError::ConstNotInitialized {
+                        ty: ty.clone(),
+                        name: constr_name.clone(),
+                        loc: expr.loc.clone(),
+                    }
+                    .into()),
+                };
+            }
+
             let ty_con = pgm.ty_cons.get(ty).unwrap();
             let (constr_idx, constr) = ty_con
                 .value_constrs
@@ -703,9 +1998,9 @@ fn eval<W: Write>(
                 .unwrap();
             let tag = ty_con.type_tag + (constr_idx as u64);
             ControlFlow::Val(if constr.fields.is_empty() {
-                let addr = heap.allocate(1);
-                heap[addr] = tag;
-                addr
+                // Constructors without fields are allocated once, so e.g. `Bool.True` is the same
+                // value as the `Bool` results of built-ins.
+                pgm.cons_by_tag[tag as usize].alloc.unwrap()
             } else {
                 heap.allocate_constr(tag)
             })
@@ -719,13 +2014,9 @@ fn eval<W: Write>(
                     Some(val) => *val,
                     None => match pgm.top_level_funs.get(var) {
                         Some(fun) => {
-                            let mut arg_values: Vec<u64> = Vec::with_capacity(args.len());
-                            for arg in args {
-                                arg_values.push(val!(eval(w, pgm, heap, locals, &arg.expr)));
-                            }
-                            return ControlFlow::Val(call(
-                                w, pgm, heap, fun, arg_values, &expr.loc,
-                            ));
+                            return eval_args_and_call(
+                                w, pgm, heap, locals, fun, None, args, &expr.loc,
+                            );
                         }
                         None => val!(eval(w, pgm, heap, locals, fun)),
                     },
@@ -760,34 +2051,42 @@ fn eval<W: Write>(
                                         ty, field
                                     )
                                 });
+                            check_self_param(fun, false, &expr.loc)?;
+                            return eval_args_and_call(
+                                w, pgm, heap, locals, fun, None, args, &expr.loc,
+                            );
+                        }
+                    }
 
+                    let object = val!(eval(w, pgm, heap, locals, object));
+                    let object_tag = heap.tag(pgm, object);
+                    let fun = match pgm.associated_funs[object_tag as usize].get(field) {
+                        Some(fun) => fun,
+                        // Methods with default implementations, e.g. `toDebugStr`.
+                        None if args.iter().all(|arg| arg.name.is_none()) => {
                             let mut arg_vals: Vec<u64> = Vec::with_capacity(args.len());
                             for arg in args {
                                 arg_vals.push(val!(eval(w, pgm, heap, locals, &arg.expr)));
                             }
-
-                            return ControlFlow::Val(call(w, pgm, heap, fun, arg_vals, &expr.loc));
+                            return Ok(ControlFlow::Val(call_method(
+                                w, pgm, heap, object, field, arg_vals, &expr.loc,
+                            )?));
                         }
-                    }
-
-                    let object = val!(eval(w, pgm, heap, locals, object));
-                    let object_tag = heap[object];
-                    let fun = pgm.associated_funs[object_tag as usize]
-                        .get(field)
-                        .unwrap_or_else(|| {
-                            panic!(
-                                "{}: Object with tag {} doesn't have field or method {:?}",
-                                LocDisplay(&expr.loc),
-                                object_tag,
-                                field
-                            )
-                        });
-                    let mut arg_vals: Vec<u64> = Vec::with_capacity(args.len());
-                    for arg in args {
-                        arg_vals.push(val!(eval(w, pgm, heap, locals, &arg.expr)));
-                    }
-                    arg_vals.insert(0, object);
-                    return ControlFlow::Val(call(w, pgm, heap, fun, arg_vals, &expr.loc));
+                        None => {
+                            return Err(method_not_found(pgm, object_tag, field, &expr.loc).into())
+                        }
+                    };
+                    check_self_param(fun, true, &expr.loc)?;
+                    return eval_args_and_call(
+                        w,
+                        pgm,
+                        heap,
+                        locals,
+                        fun,
+                        Some(object),
+                        args,
+                        &expr.loc,
+                    );
                 }
 
                 ast::Expr::UpperVar(ty) => {
@@ -799,30 +2098,25 @@ fn eval<W: Write>(
                 _ => val!(eval(w, pgm, heap, locals, fun)),
             };
 
-            match heap[fun] {
-                CONSTR_TYPE_TAG => {
+            match heap.tag(pgm, fun) {
+                // Constructor closures called with positional arguments, e.g. `f(1, 2)` where
+                // `f = Point.Point`, are handled like calls from built-ins (e.g. `Array.map`) below:
+                // arguments are fields in declaration order.
+                CONSTR_TYPE_TAG if args.iter().any(|arg| arg.name.is_some()) => {
                     let constr_tag = heap[fun + 1];
-                    allocate_object_from_tag(w, pgm, heap, locals, constr_tag, args)
+                    return allocate_object_from_tag(
+                        w, pgm, heap, locals, constr_tag, args, &expr.loc,
+                    );
                 }
 
-                TOP_FUN_TYPE_TAG => {
-                    let top_fun_idx = heap[fun + 1];
-                    let top_fun = &pgm.top_level_funs_by_idx[top_fun_idx as usize];
+                _ => {
                     let mut arg_values: Vec<u64> = Vec::with_capacity(args.len());
                     for arg in args {
                         assert!(arg.name.is_none());
                         arg_values.push(val!(eval(w, pgm, heap, locals, &arg.expr)));
                     }
-                    ControlFlow::Val(call(w, pgm, heap, top_fun, arg_values, &expr.loc))
+                    ControlFlow::Val(call_fun_value(w, pgm, heap, fun, arg_values, &expr.loc)?)
                 }
-
-                ASSOC_FUN_TYPE_TAG => {
-                    let _ty_tag = heap[fun + 1];
-                    let _fun_tag = heap[fun + 2];
-                    todo!()
-                }
-
-                _ => panic!("Function evaluated to non-callable"),
             }
         }
 
@@ -833,57 +2127,99 @@ fn eval<W: Write>(
             for part in parts {
                 match part {
                     StringPart::Str(str) => bytes.extend(str.as_bytes()),
-                    StringPart::Expr(expr) => {
+                    StringPart::Expr(expr, spec) => {
                         let part_val = val!(eval(w, pgm, heap, locals, expr));
-                        // Call toStr
-                        let part_str_val =
-                            call_method(w, pgm, heap, part_val, &"toStr".into(), vec![], &expr.loc);
-                        assert_eq!(heap[part_str_val], STR_TYPE_TAG);
-                        let part_bytes = heap.str_bytes(part_str_val);
-                        bytes.extend(part_bytes);
+                        match spec {
+                            None => {
+                                // Call toStr
+                                let part_str_val = call_method(
+                                    w,
+                                    pgm,
+                                    heap,
+                                    part_val,
+                                    &"toStr".into(),
+                                    vec![],
+                                    &expr.loc,
+                                )?;
+                                assert_eq!(heap.tag(pgm, part_str_val), STR_TYPE_TAG);
+                                let part_bytes = heap.str_bytes(part_str_val);
+                                bytes.extend(part_bytes);
+                            }
+                            Some(spec) => {
+                                let part_str =
+                                    format_interpolation(w, pgm, heap, part_val, spec, &expr.loc)?;
+                                bytes.extend(part_str.as_bytes());
+                            }
+                        }
                     }
                 }
             }
-            ControlFlow::Val(heap.allocate_str(&bytes))
+            // `toStr` results are not necessarily valid UTF-8, e.g. a `Str` built from a `substr`
+            // that splits a character. Invalid sequences are replaced with U+FFFD.
+            ControlFlow::Val(heap.allocate_utf8_str(&bytes))
         }
 
         ast::Expr::Self_ => ControlFlow::Val(*locals.get("self").unwrap()),
 
         ast::Expr::BinOp(ast::BinOpExpr { left, right, op }) => {
             let left = val!(eval(w, pgm, heap, locals, left));
+
+            // Short-circuit `&&` and `||` on booleans. Other types are handled with `__and` and
+            // `__or` methods below.
+            match op {
+                ast::BinOp::And if left == pgm.false_alloc => {
+                    return Ok(ControlFlow::Val(left));
+                }
+                ast::BinOp::Or if left == pgm.true_alloc => {
+                    return Ok(ControlFlow::Val(left));
+                }
+                ast::BinOp::And | ast::BinOp::Or
+                    if left == pgm.true_alloc || left == pgm.false_alloc =>
+                {
+                    return eval(w, pgm, heap, locals, right);
+                }
+                _ => {}
+            }
+
             let right = val!(eval(w, pgm, heap, locals, right));
 
             let method_name = match op {
                 ast::BinOp::Add => "__add",
                 ast::BinOp::Subtract => "__sub",
                 ast::BinOp::Multiply => "__mul",
+                ast::BinOp::Divide => "__div",
+                ast::BinOp::Modulo => "__mod",
                 ast::BinOp::Equal => {
-                    let eq = eq(w, pgm, heap, left, right, &expr.loc);
-                    return ControlFlow::Val(pgm.bool_alloc(eq));
+                    let eq = eq(w, pgm, heap, left, right, &expr.loc)?;
+                    return Ok(ControlFlow::Val(pgm.bool_alloc(eq)));
                 }
                 ast::BinOp::NotEqual => {
-                    let eq = eq(w, pgm, heap, left, right, &expr.loc);
-                    return ControlFlow::Val(pgm.bool_alloc(!eq));
+                    let eq = eq(w, pgm, heap, left, right, &expr.loc)?;
+                    return Ok(ControlFlow::Val(pgm.bool_alloc(!eq)));
                 }
                 ast::BinOp::Lt => {
-                    let ord = cmp(w, pgm, heap, left, right, &expr.loc);
-                    return ControlFlow::Val(pgm.bool_alloc(matches!(ord, Ordering::Less)));
+                    let ord = cmp(w, pgm, heap, left, right, &expr.loc)?;
+                    return Ok(ControlFlow::Val(
+                        pgm.bool_alloc(matches!(ord, Ordering::Less)),
+                    ));
                 }
                 ast::BinOp::Gt => {
-                    let ord = cmp(w, pgm, heap, left, right, &expr.loc);
-                    return ControlFlow::Val(pgm.bool_alloc(matches!(ord, Ordering::Greater)));
+                    let ord = cmp(w, pgm, heap, left, right, &expr.loc)?;
+                    return Ok(ControlFlow::Val(
+                        pgm.bool_alloc(matches!(ord, Ordering::Greater)),
+                    ));
                 }
                 ast::BinOp::LtEq => {
-                    let ord = cmp(w, pgm, heap, left, right, &expr.loc);
-                    return ControlFlow::Val(
+                    let ord = cmp(w, pgm, heap, left, right, &expr.loc)?;
+                    return Ok(ControlFlow::Val(
                         pgm.bool_alloc(matches!(ord, Ordering::Less | Ordering::Equal)),
-                    );
+                    ));
                 }
                 ast::BinOp::GtEq => {
-                    let ord = cmp(w, pgm, heap, left, right, &expr.loc);
-                    return ControlFlow::Val(
+                    let ord = cmp(w, pgm, heap, left, right, &expr.loc)?;
+                    return Ok(ControlFlow::Val(
                         pgm.bool_alloc(matches!(ord, Ordering::Greater | Ordering::Equal)),
-                    );
+                    ));
                 }
                 ast::BinOp::And => "__and",
                 ast::BinOp::Or => "__or",
@@ -897,61 +2233,106 @@ fn eval<W: Write>(
                 &method_name.into(),
                 vec![right],
                 &expr.loc,
-            ))
+            )?)
         }
 
-        ast::Expr::UnOp(ast::UnOpExpr { op, expr }) => {
-            let val = val!(eval(w, pgm, heap, locals, expr));
-            debug_assert!(val == pgm.true_alloc || val == pgm.false_alloc);
+        ast::Expr::UnOp(ast::UnOpExpr { op, expr: operand }) => {
+            let val = val!(eval(w, pgm, heap, locals, operand));
 
             match op {
-                ast::UnOp::Not => ControlFlow::Val(pgm.bool_alloc(val == pgm.false_alloc)),
+                ast::UnOp::Not => {
+                    debug_assert!(val == pgm.true_alloc || val == pgm.false_alloc);
+                    ControlFlow::Val(pgm.bool_alloc(val == pgm.false_alloc))
+                }
+                ast::UnOp::Neg => {
+                    if heap.tag(pgm, val) == I32_TYPE_TAG {
+                        let i = (heap[val + 1] as i32).checked_neg().ok_or_else(|| {
+                            RunError::IntOverflow {
+                                loc: expr.loc.clone(),
+                            }
+                        })?;
+                        ControlFlow::Val(heap.allocate_i32(i))
+                    } else {
+                        ControlFlow::Val(call_method(
+                            w,
+                            pgm,
+                            heap,
+                            val,
+                            &"__neg".into(),
+                            vec![],
+                            &expr.loc,
+                        )?)
+                    }
+                }
             }
         }
 
         ast::Expr::ArrayIndex(ast::ArrayIndexExpr { array, index }) => {
-            let array = val!(eval(w, pgm, heap, locals, array));
-            let index_boxed = val!(eval(w, pgm, heap, locals, index));
-            let index = heap[index_boxed + 1];
-            let array_len = heap[array + 1];
-            if index >= array_len {
-                panic!("OOB array access, len = {}, index = {}", array_len, index);
+            let array_value = val!(eval(w, pgm, heap, locals, array));
+            let index = val!(eval(w, pgm, heap, locals, index));
+            let addr = array_elem_addr(pgm, heap, array_value, index, &array.node, &expr.loc)?;
+            ControlFlow::Val(heap[addr])
+        }
+
+        ast::Expr::Array(elems) => {
+            let array = heap.allocate_array(elems.len() as u64);
+            for (idx, elem) in elems.iter().enumerate() {
+                let elem = val!(eval(w, pgm, heap, locals, elem));
+                heap[array + 2 + idx as u64] = elem;
             }
-            ControlFlow::Val(heap[array + 2 + index])
+            ControlFlow::Val(array)
         }
 
         ast::Expr::Record(exprs) => {
+            if let Some(first) = exprs.first() {
+                let named = first.name.is_some();
+                if exprs.iter().any(|expr| expr.name.is_some() != named) {
+                    return Err(RunError::MixedFields {
+                        loc: expr.loc.clone(),
+                    }
+                    .into());
+                }
+            }
+
             let shape = RecordShape::from_named_things(exprs);
             let type_tag = *pgm.record_ty_tags.get(&shape).unwrap();
 
-            let record = heap.allocate(exprs.len() + 1);
+            // Allocate the number of fields in the shape, the fields of the expression are
+            // checked against the shape below.
+            let num_fields = match pgm.get_tag_fields(type_tag) {
+                Fields::Unnamed(arity) => *arity as usize,
+                Fields::Named(field_names) => field_names.len(),
+            };
+            let record = heap.allocate(num_fields + 1);
             heap[record] = type_tag;
 
-            if !exprs.is_empty() && exprs[0].name.is_some() {
-                heap[record] = type_tag;
-
-                let mut names: Vec<SmolStr> = exprs
-                    .iter()
-                    .map(|ast::Named { name, node: _ }| name.as_ref().unwrap().clone())
-                    .collect();
-                names.sort();
+            if let Fields::Named(field_names) = pgm.get_tag_fields(type_tag) {
+                check_named_fields(
+                    field_names,
+                    exprs.iter().map(|expr| expr.name.as_ref().unwrap()),
+                    || constr_name(pgm, type_tag),
+                    &expr.loc,
+                )?;
 
-                for (name_idx, name_) in names.iter().enumerate() {
-                    let expr = exprs
+                // Evaluate in program order, store in sorted name order.
+                for ast::Named { name, node } in exprs {
+                    let value = val!(eval(w, pgm, heap, locals, node));
+                    let name_idx = field_names
                         .iter()
-                        .find_map(|ast::Named { name, node }| {
-                            if name.as_ref().unwrap() == name_ {
-                                Some(node)
-                            } else {
-                                None
-                            }
-                        })
+                        .position(|field_name| field_name == name.as_ref().unwrap())
                         .unwrap();
-
-                    let value = val!(eval(w, pgm, heap, locals, expr));
                     heap[record + (name_idx as u64) + 1] = value;
                 }
             } else {
+                if exprs.len() != num_fields {
+                    return Err(RunError::ConstrArity {
+                        constr: constr_name(pgm, type_tag),
+                        expected: num_fields,
+                        found: exprs.len(),
+                        loc: expr.loc.clone(),
+                    }
+                    .into());
+                }
                 for (idx, ast::Named { name: _, node }) in exprs.iter().enumerate() {
                     let value = val!(eval(w, pgm, heap, locals, node));
                     heap[record + (idx as u64) + 1] = value;
@@ -961,11 +2342,133 @@ fn eval<W: Write>(
             ControlFlow::Val(record)
         }
 
-        ast::Expr::Range(_) => {
-            panic!("Interpreter only supports range expressions in for loops")
+        ast::Expr::RecordUpdate(ast::RecordUpdateExpr { record, fields }) => {
+            let record = val!(eval(w, pgm, heap, locals, record));
+            let type_tag = heap.tag(pgm, record);
+            let field_names = match &pgm.cons_by_tag[type_tag as usize] {
+                Con {
+                    info: ConInfo::Record { .. },
+                    fields: Fields::Named(field_names),
+                    ..
+                } => field_names,
+                _ => {
+                    return Err(RunError::TypeError {
+                        msg: format!(
+                            "updated value {} is not a record with named fields",
+                            value_to_string(pgm, heap, record)
+                        ),
+                        loc: expr.loc.clone(),
+                    }
+                    .into())
+                }
+            };
+
+            let mut seen: Set<&SmolStr> = Default::default();
+            for (name, _) in fields {
+                if !field_names.contains(name) {
+                    return Err(RunError::UnknownField {
+                        constr: constr_name(pgm, type_tag),
+                        field: name.clone(),
+                        loc: expr.loc.clone(),
+                    }
+                    .into());
+                }
+                if !seen.insert(name) {
+                    return Err(RunError::DuplicateField {
+                        constr: constr_name(pgm, type_tag),
+                        field: name.clone(),
+                        loc: expr.loc.clone(),
+                    }
+                    .into());
+                }
+            }
+
+            // Copy the tag and the fields, then evaluate the updated fields in program order.
+            let num_fields = field_names.len() as u64;
+            let copy = heap.allocate(num_fields as usize + 1);
+            for i in 0..=num_fields {
+                heap[copy + i] = heap[record + i];
+            }
+
+            for (name, field_expr) in fields {
+                let value = val!(eval(w, pgm, heap, locals, field_expr));
+                let name_idx = field_names
+                    .iter()
+                    .position(|field_name| field_name == name)
+                    .unwrap();
+                heap[copy + (name_idx as u64) + 1] = value;
+            }
+
+            ControlFlow::Val(copy)
+        }
+
+        ast::Expr::Range(ast::RangeExpr {
+            from,
+            to,
+            inclusive,
+        }) => {
+            let from = val!(eval(w, pgm, heap, locals, from));
+            debug_assert_eq!(heap.tag(pgm, from), I32_TYPE_TAG);
+            let to = val!(eval(w, pgm, heap, locals, to));
+            debug_assert_eq!(heap.tag(pgm, to), I32_TYPE_TAG);
+            ControlFlow::Val(heap.allocate_range(
+                heap[from + 1] as i32,
+                heap[to + 1] as i32,
+                *inclusive,
+                1,
+            ))
         }
 
-        ast::Expr::Return(expr) => ControlFlow::Ret(val!(eval(w, pgm, heap, locals, expr))),
+        ast::Expr::Return(expr) => {
+            // Calls to top-level source functions in return position are tail calls: evaluate the
+            // arguments here and let the caller's `call_source_fun` loop run the callee.
+            if let ast::Expr::Call(ast::CallExpr { fun, args }) = &expr.node {
+                if let ast::Expr::Var(var) = &fun.node {
+                    if !locals.contains_key(var) {
+                        if let Some(
+                            fun @ Fun {
+                                idx,
+                                kind: FunKind::Source(_),
+                            },
+                        ) = pgm.top_level_funs.get(var)
+                        {
+                            let mut arg_values: Vec<u64> = Vec::with_capacity(args.len());
+                            for arg in args {
+                                arg_values.push(val!(eval(w, pgm, heap, locals, &arg.expr)));
+                            }
+                            let arg_values = order_args(fun, false, args, arg_values, &expr.loc)?;
+                            return Ok(ControlFlow::TailCall(*idx, arg_values));
+                        }
+                    }
+                }
+            }
+            ControlFlow::Ret(val!(eval(w, pgm, heap, locals, expr)))
+        }
+
+        ast::Expr::Throw(expr) => {
+            let value = val!(eval(w, pgm, heap, locals, expr));
+            return Err(EvalError::Throw(value, expr.loc.clone()));
+        }
+
+        ast::Expr::Try(ast::TryExpr { body, var, handler }) => {
+            // Tail calls in the body are run here instead of in the caller's `run_source_fun` loop,
+            // so that the callee's throws are caught.
+            let result = match exec(w, pgm, heap, locals, body) {
+                Ok(ControlFlow::TailCall(fun_idx, args)) => {
+                    let fun = &pgm.top_level_funs_by_idx[fun_idx as usize];
+                    call_ordered(w, pgm, heap, fun, args, &expr.loc).map(ControlFlow::Ret)
+                }
+                other => other,
+            };
+            let value = match result {
+                Err(EvalError::Throw(value, _)) => value,
+                other => return other,
+            };
+            locals.insert(var.clone(), value);
+            let result = exec(w, pgm, heap, locals, handler);
+            locals.remove(var);
+            return result;
+        }
 
         ast::Expr::Match(ast::MatchExpr { scrutinee, alts }) => {
             let scrut = val!(eval(w, pgm, heap, locals, scrutinee));
@@ -975,13 +2478,46 @@ fn eval<W: Write>(
                 rhs,
             } in alts
             {
-                assert!(guard.is_none()); // TODO
-                if let Some(binds) = try_bind_pat(pgm, heap, pattern, scrut) {
-                    locals.extend(binds.into_iter());
+                let binds = match try_bind_pat(pgm, heap, pattern, scrut)? {
+                    Some(binds) => binds,
+                    None => continue,
+                };
+
+                // Bind the pattern variables for the guard, keep the shadowed values to restore
+                // when the guard fails.
+                let shadowed: Vec<(SmolStr, Option<u64>)> = binds
+                    .into_iter()
+                    .map(|(var, value)| {
+                        let old = locals.insert(var.clone(), value);
+                        (var, old)
+                    })
+                    .collect();
+
+                let guard_passed = match guard {
+                    Some(guard) => {
+                        let guard = val!(eval(w, pgm, heap, locals, guard));
+                        debug_assert!(guard == pgm.true_alloc || guard == pgm.false_alloc);
+                        guard == pgm.true_alloc
+                    }
+                    None => true,
+                };
+
+                if guard_passed {
                     return exec(w, pgm, heap, locals, rhs);
                 }
+
+                for (var, old) in shadowed {
+                    match old {
+                        Some(old) => locals.insert(var, old),
+                        None => locals.remove(&var),
+                    };
+                }
             }
-            panic!("Non-exhaustive pattern match");
+            panic!(
+                "{}: non-exhaustive pattern match, scrutinee = {}",
+                LocDisplay(&expr.loc),
+                value_to_string(pgm, heap, scrut)
+            );
         }
 
         ast::Expr::If(ast::IfExpr {
@@ -1000,7 +2536,7 @@ fn eval<W: Write>(
             }
             ControlFlow::Val(0) // TODO: return unit
         }
-    }
+    })
 }
 
 fn assign<W: Write>(
@@ -1012,38 +2548,61 @@ fn assign<W: Write>(
     val: u64,
     op: ast::AssignOp,
     loc: &Loc,
-) -> ControlFlow {
+) -> Result<ControlFlow, EvalError> {
     match &lhs.node {
-        ast::Expr::Var(var) => match op {
-            ast::AssignOp::Eq => {
-                let old = locals.insert(var.clone(), val);
-                assert!(old.is_some());
-            }
-            ast::AssignOp::PlusEq => todo!(),
-            ast::AssignOp::MinusEq => todo!(),
-        },
-        ast::Expr::FieldSelect(ast::FieldSelectExpr { object, field }) => {
-            let object = val!(eval(w, pgm, heap, locals, object));
-            let object_tag = heap[object];
-            let object_con = &pgm.cons_by_tag[object_tag as usize];
-            let object_fields = &object_con.fields;
-            let field_idx = object_fields.find_named_field_idx(field);
-            let new_val = match op {
-                ast::AssignOp::Eq => val,
-                ast::AssignOp::PlusEq => {
-                    let field_value = heap[object + 1 + field_idx];
-                    call_method(w, pgm, heap, field_value, &"__add".into(), vec![val], loc)
-                }
-                ast::AssignOp::MinusEq => {
-                    let field_value = heap[object + 1 + field_idx];
-                    call_method(w, pgm, heap, field_value, &"__sub".into(), vec![val], loc)
+        ast::Expr::Var(var) => {
+            let new_val = match assign_op_method(op) {
+                None => val,
+                Some(method) => {
+                    let old_val = *locals.get(var).unwrap();
+                    call_method(w, pgm, heap, old_val, &method.into(), vec![val], loc)?
+                }
+            };
+            let old = locals.insert(var.clone(), new_val);
+            assert!(old.is_some());
+        }
+        ast::Expr::FieldSelect(_) | ast::Expr::ArrayIndex(_) => {
+            // The heap address of the updated slot is computed once, so subexpressions of the
+            // LHS are evaluated once, and compound assignments read and write the same slot.
+            let addr = match &lhs.node {
+                ast::Expr::FieldSelect(ast::FieldSelectExpr { object, field }) => {
+                    // In `a.b.c = v`, `a.b` is evaluated to the object holding `c`.
+                    let object = val!(eval(w, pgm, heap, locals, object));
+                    let object_tag = heap.tag(pgm, object);
+                    let field_idx = pgm.get_tag_fields(object_tag).find_named_field_idx(field);
+                    object + 1 + field_idx
+                }
+                ast::Expr::ArrayIndex(ast::ArrayIndexExpr { array, index }) => {
+                    let array_value = val!(eval(w, pgm, heap, locals, array));
+                    let index = val!(eval(w, pgm, heap, locals, index));
+                    array_elem_addr(pgm, heap, array_value, index, &array.node, &lhs.loc)?
                 }
+                _ => unreachable!(),
             };
-            heap[object + 1 + field_idx] = new_val;
+            let new_val = match assign_op_method(op) {
+                None => val,
+                Some(method) => {
+                    let old_val = heap[addr];
+                    call_method(w, pgm, heap, old_val, &method.into(), vec![val], loc)?
+                }
+            };
+            heap[addr] = new_val;
         }
         _ => todo!("Assign statement with fancy LHS at {:?}", &lhs.loc),
     }
-    ControlFlow::Val(val)
+    Ok(ControlFlow::Val(val))
+}
+
+/// The method a compound assignment calls on the old value, `None` for plain assignment.
+fn assign_op_method(op: ast::AssignOp) -> Option<&'static str> {
+    match op {
+        ast::AssignOp::Eq => None,
+        ast::AssignOp::PlusEq => Some("__add"),
+        ast::AssignOp::MinusEq => Some("__sub"),
+        ast::AssignOp::StarEq => Some("__mul"),
+        ast::AssignOp::SlashEq => Some("__div"),
+        ast::AssignOp::PercentEq => Some("__mod"),
+    }
 }
 
 fn cmp<W: Write>(
@@ -1053,9 +2612,25 @@ fn cmp<W: Write>(
     val1: u64,
     val2: u64,
     loc: &Loc,
-) -> Ordering {
-    let ret = call_method(w, pgm, heap, val1, &"__cmp".into(), vec![val2], loc);
-    let ret_tag = heap[ret];
+) -> Result<Ordering, EvalError> {
+    // Compare `I32`s and `Char`s without calling `__cmp` and converting the returned `Ordering`.
+    match (heap.tag(pgm, val1), heap.tag(pgm, val2)) {
+        (I32_TYPE_TAG, I32_TYPE_TAG) => {
+            return Ok(heap.i32_value(val1).cmp(&heap.i32_value(val2)));
+        }
+        (CHAR_TYPE_TAG, CHAR_TYPE_TAG) => {
+            return Ok(heap.char_value(val1).cmp(&heap.char_value(val2)));
+        }
+        _ => {}
+    }
+
+    let ret = call_method(w, pgm, heap, val1, &"__cmp".into(), vec![val2], loc)?;
+    Ok(ordering_from_value(pgm, heap, ret))
+}
+
+/// Convert an `Ordering` value to Rust `Ordering`.
+fn ordering_from_value(pgm: &Pgm, heap: &Heap, value: u64) -> Ordering {
+    let ret_tag = heap.tag(pgm, value);
     let ordering_ty_con = pgm
         .ty_cons
         .get("Ordering")
@@ -1077,10 +2652,354 @@ fn cmp<W: Write>(
     }
 }
 
-fn eq<W: Write>(w: &mut W, pgm: &Pgm, heap: &mut Heap, val1: u64, val2: u64, loc: &Loc) -> bool {
-    let ret = call_method(w, pgm, heap, val1, &"__eq".into(), vec![val2], loc);
+/// Compare two records with the same shape field by field, with `cmp`.
+///
+/// Named fields are stored sorted by name, so the fields are compared in sorted-name order.
+fn record_cmp<W: Write>(
+    w: &mut W,
+    pgm: &Pgm,
+    heap: &mut Heap,
+    val1: u64,
+    val2: u64,
+    loc: &Loc,
+) -> Result<Ordering, EvalError> {
+    assert_eq!(
+        heap.tag(pgm, val1),
+        heap.tag(pgm, val2),
+        "{}: Comparing records with different shapes",
+        LocDisplay(loc)
+    );
+    for (slot1, slot2) in heap
+        .object_ref_slots(pgm, val1)
+        .zip(heap.object_ref_slots(pgm, val2))
+    {
+        let ordering = cmp(w, pgm, heap, heap[slot1], heap[slot2], loc)?;
+        if ordering != Ordering::Equal {
+            return Ok(ordering);
+        }
+    }
+    Ok(Ordering::Equal)
+}
+
+/// The `Ordering` value for `ordering`.
+fn ordering_value(pgm: &Pgm, ordering: Ordering) -> u64 {
+    let ordering_ty_con = pgm
+        .ty_cons
+        .get("Ordering")
+        .unwrap_or_else(|| panic!("__cmp was called, but the Ordering type is not defined"));
+
+    let constr_name = match ordering {
+        Ordering::Less => "Less",
+        Ordering::Equal => "Equal",
+        Ordering::Greater => "Greater",
+    };
+
+    // `Ordering` constructors don't have fields, so they are allocated once.
+    let tag = ordering_ty_con.get_constr_with_tag(constr_name).0;
+    pgm.cons_by_tag[tag as usize].alloc.unwrap()
+}
+
+/// Heap address of the element at `index` of `array`, in an `array[index]` expression.
+/// `array_expr` is the array expression, used to report the dimension of out-of-bounds indices in
+/// nested indexing like `table[i][j]`.
+fn array_elem_addr(
+    pgm: &Pgm,
+    heap: &Heap,
+    array: u64,
+    index: u64,
+    array_expr: &ast::Expr,
+    loc: &Loc,
+) -> Result<u64, EvalError> {
+    if array == 0 || heap.tag(pgm, array) != ARRAY_TYPE_TAG {
+        return Err(RunError::TypeError {
+            msg: format!(
+                "value {} is not an array, it can't be indexed",
+                value_to_string(pgm, heap, array)
+            ),
+            loc: loc.clone(),
+        }
+        .into());
+    }
+    if index == 0 || heap.tag(pgm, index) != I32_TYPE_TAG {
+        return Err(RunError::TypeError {
+            msg: format!(
+                "array index {} is not an I32",
+                value_to_string(pgm, heap, index)
+            ),
+            loc: loc.clone(),
+        }
+        .into());
+    }
+
+    let array_len = heap[array + 1];
+    let index = resolve_index(heap[index + 1] as i32, array_len).map_err(|index| {
+        let mut dimension = 1;
+        let mut expr = array_expr;
+        while let ast::Expr::ArrayIndex(ast::ArrayIndexExpr { array, .. }) = expr {
+            dimension += 1;
+            expr = &array.node;
+        }
+        RunError::OutOfBounds {
+            msg: format!(
+                "OOB array access, len = {}, index = {}{}",
+                array_len,
+                index,
+                if dimension == 1 {
+                    String::new()
+                } else {
+                    format!(", dimension = {}", dimension)
+                }
+            ),
+            loc: loc.clone(),
+        }
+    })?;
+    Ok(array + 2 + index)
+}
+
+/// Resolve an array or string index. Negative indices count from the end: `-1` is the last
+/// element. Returns the adjusted index as `Err` when it's out of bounds.
+fn resolve_index(index: i32, len: u64) -> Result<u64, i64> {
+    let adjusted = if index < 0 {
+        i64::from(index) + len as i64
+    } else {
+        i64::from(index)
+    };
+    if adjusted < 0 || adjusted as u64 >= len {
+        Err(adjusted)
+    } else {
+        Ok(adjusted as u64)
+    }
+}
+
+/// Values of a range with a non-zero step. Negative steps iterate down to `to`.
+fn range_values(from: i32, to: i32, inclusive: bool, step: i32) -> impl Iterator<Item = i32> {
+    debug_assert_ne!(step, 0);
+    let mut next: Option<i32> = Some(from);
+    std::iter::from_fn(move || {
+        let i = next?;
+        if !range_contains(to, inclusive, step, i) {
+            return None;
+        }
+        next = i.checked_add(step);
+        Some(i)
+    })
+}
+
+/// Whether `i`, reached by stepping from the start of a range, is in the range.
+fn range_contains(to: i32, inclusive: bool, step: i32, i: i32) -> bool {
+    match (step > 0, inclusive) {
+        (true, false) => i < to,
+        (true, true) => i <= to,
+        (false, false) => i > to,
+        (false, true) => i >= to,
+    }
+}
+
+/// Name of a constructor, as `Type.Constr`, or `Type` for product types and `record` for records.
+fn constr_name(pgm: &Pgm, constr_tag: u64) -> String {
+    match &pgm.cons_by_tag[constr_tag as usize].info {
+        ConInfo::Named {
+            ty_name,
+            con_name: Some(con_name),
+        } if ty_name != con_name => format!("{}.{}", ty_name, con_name),
+        ConInfo::Named { ty_name, .. } => ty_name.to_string(),
+        ConInfo::Record { .. } => "record".to_string(),
+    }
+}
+
+/// Get the tag of a value constructor of a type defined in the program, e.g. `Option.Some`.
+fn constr_tag(pgm: &Pgm, ty_name: &str, constr_name: &str) -> u64 {
+    pgm.ty_cons
+        .get(ty_name)
+        .unwrap_or_else(|| panic!("{} type is not defined", ty_name))
+        .get_constr_with_tag(constr_name)
+        .0
+}
+
+fn eq<W: Write>(
+    w: &mut W,
+    pgm: &Pgm,
+    heap: &mut Heap,
+    val1: u64,
+    val2: u64,
+    loc: &Loc,
+) -> Result<bool, EvalError> {
+    // Unit is not in the heap, and is only equal to unit.
+    if val1 == 0 || val2 == 0 {
+        return Ok(val1 == val2);
+    }
+
+    // `I32`s and `Char`s are equal when their payload words are equal.
+    if heap.tag(pgm, val1) == heap.tag(pgm, val2)
+        && matches!(heap.tag(pgm, val1), I32_TYPE_TAG | CHAR_TYPE_TAG)
+    {
+        return Ok(heap[val1 + 1] == heap[val2 + 1]);
+    }
+
+    // Values without an `__eq` method, e.g. arrays and records, are compared structurally.
+    if !pgm.associated_funs[heap.tag(pgm, val1) as usize].contains_key("__eq") {
+        return Ok(structural_eq(pgm, heap, val1, val2));
+    }
+
+    let ret = call_method(w, pgm, heap, val1, &"__eq".into(), vec![val2], loc)?;
     debug_assert!(ret == pgm.true_alloc || ret == pgm.false_alloc);
-    ret == pgm.true_alloc
+    Ok(ret == pgm.true_alloc)
+}
+
+/// Render an interpolated value with a format spec.
+fn format_interpolation<W: Write>(
+    w: &mut W,
+    pgm: &Pgm,
+    heap: &mut Heap,
+    value: u64,
+    spec: &FormatSpec,
+    loc: &Loc,
+) -> Result<String, EvalError> {
+    let FormatSpec {
+        zero_pad,
+        width,
+        radix,
+    } = spec;
+
+    if heap.tag(pgm, value) == I32_TYPE_TAG {
+        let i = heap[value + 1] as i32;
+        return Ok(match (radix, zero_pad) {
+            (Radix::Decimal, false) => format!("{:>width$}", i),
+            (Radix::Decimal, true) => format!("{:0width$}", i),
+            (Radix::Hex, false) => format!("{:>width$x}", i),
+            (Radix::Hex, true) => format!("{:0width$x}", i),
+            (Radix::Binary, false) => format!("{:>width$b}", i),
+            (Radix::Binary, true) => format!("{:0width$b}", i),
+        });
+    }
+
+    if *radix != Radix::Decimal {
+        return Err(RunError::FormatError {
+            msg: format!(
+                "radix format spec used with non-I32 value {}",
+                value_to_string(pgm, heap, value)
+            ),
+            loc: loc.clone(),
+        }
+        .into());
+    }
+
+    let str = call_method(w, pgm, heap, value, &"toStr".into(), vec![], loc)?;
+    let str = String::from_utf8_lossy(heap.str_bytes(str)).into_owned();
+    let pad = if *zero_pad { '0' } else { ' ' };
+    let num_pad_chars = width.saturating_sub(str.chars().count());
+    let mut padded: String = std::iter::repeat_n(pad, num_pad_chars).collect();
+    padded.push_str(&str);
+    Ok(padded)
+}
+
+/// Compare two values structurally, without calling user-defined `__eq` methods.
+///
+/// Objects are equal when they have the same tag and their fields are equal. `Str` and `StrView`
+/// values are equal when their bytes are equal. Cyclic values are handled by assuming a pair of
+/// objects already being compared are equal.
+fn structural_eq(pgm: &Pgm, heap: &Heap, val1: u64, val2: u64) -> bool {
+    let mut visited: Set<(u64, u64)> = Default::default();
+    structural_eq_(pgm, heap, val1, val2, &mut visited)
+}
+
+fn structural_eq_(
+    pgm: &Pgm,
+    heap: &Heap,
+    val1: u64,
+    val2: u64,
+    visited: &mut Set<(u64, u64)>,
+) -> bool {
+    if val1 == val2 {
+        return true;
+    }
+
+    // Unit or uninitialized array elements.
+    if val1 == 0 || val2 == 0 {
+        return false;
+    }
+
+    if !visited.insert((val1, val2)) {
+        return true;
+    }
+
+    let tag1 = heap.tag(pgm, val1);
+    let tag2 = heap.tag(pgm, val2);
+
+    let str_bytes = |val: u64, tag: u64| -> Option<&[u8]> {
+        match tag {
+            STR_TYPE_TAG => Some(heap.str_bytes(val)),
+            STR_VIEW_TYPE_TAG => Some(heap.str_view_bytes(val)),
+            _ => None,
+        }
+    };
+
+    if let (Some(bytes1), Some(bytes2)) = (str_bytes(val1, tag1), str_bytes(val2, tag2)) {
+        return bytes1 == bytes2;
+    }
+
+    if tag1 != tag2 {
+        return false;
+    }
+
+    match tag1 {
+        // `F64` values are compared by bits, which is consistent with the total order used by
+        // `F64.__eq`.
+        I32_TYPE_TAG | CHAR_TYPE_TAG | F64_TYPE_TAG | CONSTR_TYPE_TAG | TOP_FUN_TYPE_TAG => {
+            heap[val1 + 1] == heap[val2 + 1]
+        }
+
+        ASSOC_FUN_TYPE_TAG => heap[val1 + 1] == heap[val2 + 1] && heap[val1 + 2] == heap[val2 + 2],
+
+        RANGE_TYPE_TAG => heap.range_fields(val1) == heap.range_fields(val2),
+
+        ARRAY_ITER_TYPE_TAG | RANGE_ITER_TYPE_TAG | STR_CHARS_TYPE_TAG | SCANNER_TYPE_TAG => {
+            heap[val1 + 2] == heap[val2 + 2]
+                && structural_eq_(pgm, heap, heap[val1 + 1], heap[val2 + 1], visited)
+        }
+
+        STR_BUILDER_TYPE_TAG => heap.str_builder_bytes(val1) == heap.str_builder_bytes(val2),
+
+        // Entries of tree maps are sorted by key, so equal maps have equal entries at each index.
+        TREE_MAP_TYPE_TAG => {
+            let len = heap[val1 + 1];
+            len == heap[val2 + 1]
+                && [2, 3].iter().all(|&field| {
+                    let (array1, array2) = (heap[val1 + field], heap[val2 + field]);
+                    (0..len).all(|i| {
+                        structural_eq_(
+                            pgm,
+                            heap,
+                            heap[array1 + 2 + i],
+                            heap[array2 + 2 + i],
+                            visited,
+                        )
+                    })
+                })
+        }
+
+        // Sets are equal when they have the same elements, in any order.
+        SET_TYPE_TAG => {
+            let (elems1, elems2) = (heap.set_elems(val1), heap.set_elems(val2));
+            elems1.len() == elems2.len()
+                && elems1.iter().all(|elem1| {
+                    elems2.iter().any(|elem2| {
+                        // Failed comparisons are recorded in `visited`, so each pair of elements
+                        // is compared with a copy.
+                        structural_eq_(pgm, heap, *elem1, *elem2, &mut visited.clone())
+                    })
+                })
+        }
+
+        _ => {
+            let slots1 = heap.object_ref_slots(pgm, val1);
+            let slots2 = heap.object_ref_slots(pgm, val2);
+            slots1.end - slots1.start == slots2.end - slots2.start
+                && slots1.zip(slots2).all(|(slot1, slot2)| {
+                    structural_eq_(pgm, heap, heap[slot1], heap[slot2], visited)
+                })
+        }
+    }
 }
 
 fn try_bind_field_pats(
@@ -1089,7 +3008,7 @@ fn try_bind_field_pats(
     constr_fields: &Fields,
     field_pats: &[ast::Named<Box<L<ast::Pat>>>],
     value: u64,
-) -> Option<Map<SmolStr, u64>> {
+) -> Result<Option<Map<SmolStr, u64>>, RunError> {
     let mut ret: Map<SmolStr, u64> = Default::default();
 
     match constr_fields {
@@ -1102,8 +3021,10 @@ fn try_bind_field_pats(
             for (field_pat_idx, field_pat) in field_pats.iter().enumerate() {
                 let field_value = heap[value + (field_pat_idx as u64) + 1];
                 assert!(field_pat.name.is_none());
-                let map = try_bind_pat(pgm, heap, &field_pat.node, field_value)?;
-                ret.extend(map.into_iter());
+                match try_bind_pat(pgm, heap, &field_pat.node, field_value)? {
+                    Some(map) => ret.extend(map),
+                    None => return Ok(None),
+                }
             }
         }
 
@@ -1118,23 +3039,24 @@ fn try_bind_field_pats(
                     .iter()
                     .find(|field| field.name.as_ref().unwrap() == field_name)
                     .unwrap();
-                let map = try_bind_pat(
-                    pgm,
-                    heap,
-                    &field_pat.node,
-                    heap[value + 1 + field_idx as u64],
-                )?;
-                ret.extend(map.into_iter());
+                let field_value = heap[value + 1 + field_idx as u64];
+                match try_bind_pat(pgm, heap, &field_pat.node, field_value)? {
+                    Some(map) => ret.extend(map),
+                    None => return Ok(None),
+                }
             }
         }
     }
 
-    Some(ret)
+    Ok(Some(ret))
 }
 
 /// Tries to match a pattern. On successful match, returns a map with variables bound in the
 /// pattern. On failure returns `None`.
 ///
+/// Returns `RunError::TypeError` when the value is not of the pattern's type, which is a bug in
+/// the program rather than a failed match.
+///
 /// `heap` argument is `mut` to be able to allocate `StrView`s in string prefix patterns. In the
 /// compiled version `StrView`s will be allocated on stack.
 fn try_bind_pat(
@@ -1142,8 +3064,8 @@ fn try_bind_pat(
     heap: &mut Heap,
     pattern: &L<ast::Pat>,
     value: u64,
-) -> Option<Map<SmolStr, u64>> {
-    match &pattern.node {
+) -> Result<Option<Map<SmolStr, u64>>, RunError> {
+    Ok(match &pattern.node {
         ast::Pat::Var(var) => {
             let mut map: Map<SmolStr, u64> = Default::default();
             map.insert(var.clone(), value);
@@ -1156,18 +3078,20 @@ fn try_bind_pat(
             constr: ast::Constructor { type_, constr },
             fields: field_pats,
         }) => {
-            let value_tag = heap[value];
+            let value_tag = heap.tag(pgm, value);
 
             let ty_con = pgm.ty_cons.get(type_).unwrap();
             let (ty_con_first_tag, ty_con_last_tag) = ty_con.tag_range();
 
             if value_tag < ty_con_first_tag || value_tag > ty_con_last_tag {
-                eprintln!("TYPE ERROR: Value type doesn't match type constructor type tag in pattern match");
-                eprintln!(
-                    "  value tag = {}, ty con first tag = {}, ty con last tag = {}",
-                    value_tag, ty_con_first_tag, ty_con_last_tag
-                );
-                return None;
+                return Err(RunError::TypeError {
+                    msg: format!(
+                        "value {} does not have the pattern's type {}",
+                        value_to_string(pgm, heap, value),
+                        type_
+                    ),
+                    loc: pattern.loc.clone(),
+                });
             }
 
             let constr_idx = match constr {
@@ -1182,30 +3106,39 @@ fn try_bind_pat(
                 }
                 None => {
                     if ty_con_first_tag != ty_con_last_tag {
-                        eprintln!("TYPE ERROR");
-                        return None;
+                        return Err(RunError::TypeError {
+                            msg: format!(
+                                "type {} has more than one constructor, pattern needs to specify \
+                                the constructor",
+                                type_
+                            ),
+                            loc: pattern.loc.clone(),
+                        });
                     }
                     0
                 }
             };
 
             if value_tag != ty_con.type_tag + (constr_idx as u64) {
-                return None;
+                return Ok(None);
             }
 
             let fields = pgm.get_tag_fields(value_tag);
-            try_bind_field_pats(pgm, heap, fields, field_pats, value)
+            try_bind_field_pats(pgm, heap, fields, field_pats, value)?
         }
 
         ast::Pat::Record(fields) => {
-            let value_tag = heap[value];
+            let value_tag = heap.tag(pgm, value);
             let value_fields = pgm.get_tag_fields(value_tag);
-            try_bind_field_pats(pgm, heap, value_fields, fields, value)
+            try_bind_field_pats(pgm, heap, value_fields, fields, value)?
         }
 
         ast::Pat::Str(str) => {
-            debug_assert!(matches!(heap[value], STR_TYPE_TAG | STR_VIEW_TYPE_TAG));
-            let value_bytes = if heap[value] == STR_TYPE_TAG {
+            debug_assert!(matches!(
+                heap.tag(pgm, value),
+                STR_TYPE_TAG | STR_VIEW_TYPE_TAG
+            ));
+            let value_bytes = if heap.tag(pgm, value) == STR_TYPE_TAG {
                 heap.str_bytes(value)
             } else {
                 heap.str_view_bytes(value)
@@ -1217,24 +3150,41 @@ fn try_bind_pat(
             }
         }
 
-        ast::Pat::StrPfx(pfx, var) => {
-            debug_assert!(matches!(heap[value], STR_TYPE_TAG | STR_VIEW_TYPE_TAG));
-            let value_bytes = if heap[value] == STR_TYPE_TAG {
+        ast::Pat::StrPfx(ast::StrPfxPattern {
+            pfx,
+            pfx_var,
+            rest_var,
+        }) => {
+            debug_assert!(matches!(
+                heap.tag(pgm, value),
+                STR_TYPE_TAG | STR_VIEW_TYPE_TAG
+            ));
+            let value_bytes = if heap.tag(pgm, value) == STR_TYPE_TAG {
                 heap.str_bytes(value)
             } else {
                 heap.str_view_bytes(value)
             };
             if value_bytes.starts_with(pfx.as_bytes()) {
-                let pfx_len = pfx.len();
-                let rest = if heap[value] == STR_TYPE_TAG {
-                    let len = heap.str_bytes(value).len();
-                    heap.allocate_str_view(value, pfx_len as u64, len as u64)
-                } else {
-                    let len = heap.str_view_bytes(value).len();
-                    heap.allocate_str_view_from_str_view(value, pfx_len as u64, len as u64)
+                let pfx_len = pfx.len() as u64;
+                let len = value_bytes.len() as u64;
+                let view = |heap: &mut Heap, start: u64, end: u64| -> Result<u64, RunError> {
+                    let view = if heap.tag(pgm, value) == STR_TYPE_TAG {
+                        heap.allocate_str_view(value, start, end)
+                    } else {
+                        heap.allocate_str_view_from_str_view(value, start, end)
+                    };
+                    view.ok_or_else(|| RunError::StrViewOutOfBounds {
+                        start,
+                        end,
+                        len,
+                        loc: pattern.loc.clone(),
+                    })
                 };
                 let mut map: Map<SmolStr, u64> = Default::default();
-                map.insert(var.clone(), rest);
+                if let Some(pfx_var) = pfx_var {
+                    map.insert(pfx_var.clone(), view(heap, 0, pfx_len)?);
+                }
+                map.insert(rest_var.clone(), view(heap, pfx_len, len)?);
                 Some(map)
             } else {
                 None
@@ -1242,15 +3192,12 @@ fn try_bind_pat(
         }
 
         ast::Pat::Or(pat1, pat2) => {
-            if let Some(binds) = try_bind_pat(pgm, heap, pat1, value) {
-                return Some(binds);
+            if let Some(binds) = try_bind_pat(pgm, heap, pat1, value)? {
+                return Ok(Some(binds));
             }
-            if let Some(binds) = try_bind_pat(pgm, heap, pat2, value) {
-                return Some(binds);
-            }
-            None
+            try_bind_pat(pgm, heap, pat2, value)?
         }
-    }
+    })
 }
 
 fn obj_to_string(pgm: &Pgm, heap: &Heap, obj: u64, loc: &Loc) -> String {
@@ -1258,7 +3205,7 @@ fn obj_to_string(pgm: &Pgm, heap: &Heap, obj: u64, loc: &Loc) -> String {
 
     let mut s = String::new();
 
-    let tag = heap[obj];
+    let tag = heap.tag(pgm, obj);
     let con = &pgm.cons_by_tag[tag as usize];
 
     write!(&mut s, "{}: ", LocDisplay(loc)).unwrap();
@@ -1279,10 +3226,12 @@ fn obj_to_string(pgm: &Pgm, heap: &Heap, obj: u64, loc: &Loc) -> String {
 
     write!(&mut s, "(").unwrap();
 
+    // Field values are stored in the order of `con.fields`: declaration order for named types,
+    // sorted by name for records.
     match &con.fields {
         Fields::Unnamed(arity) => {
             for i in 0..*arity {
-                write!(&mut s, "{}", heap[obj + 1 + u64::from(i)]).unwrap();
+                write_value(pgm, heap, heap[obj + 1 + u64::from(i)], &mut s);
                 if i != arity - 1 {
                     write!(&mut s, ", ").unwrap();
                 }
@@ -1290,7 +3239,8 @@ fn obj_to_string(pgm: &Pgm, heap: &Heap, obj: u64, loc: &Loc) -> String {
         }
         Fields::Named(fields) => {
             for (i, field_name) in fields.iter().enumerate() {
-                write!(&mut s, "{} = {}", field_name, heap[obj + 1 + (i as u64)]).unwrap();
+                write!(&mut s, "{} = ", field_name).unwrap();
+                write_value(pgm, heap, heap[obj + 1 + (i as u64)], &mut s);
                 if i != fields.len() - 1 {
                     write!(&mut s, ", ").unwrap();
                 }
@@ -1303,10 +3253,174 @@ fn obj_to_string(pgm: &Pgm, heap: &Heap, obj: u64, loc: &Loc) -> String {
     s
 }
 
-struct LocDisplay<'a>(&'a Loc);
+/// Render a value in source syntax, used as the `toStr` of values without a `toStr` method.
+fn value_to_string(pgm: &Pgm, heap: &Heap, obj: u64) -> String {
+    let mut s = String::new();
+    write_value(pgm, heap, obj, &mut s);
+    s
+}
+
+fn write_value(pgm: &Pgm, heap: &Heap, obj: u64, s: &mut String) {
+    let mut path: Set<u64> = Default::default();
+    write_value_(pgm, heap, obj, s, &mut path);
+}
+
+/// `path` is the objects being written, from the outermost to `obj`. Objects that contain
+/// themselves are written as `<cycle>` when they're reached again, shared objects that are not in
+/// a cycle are written in full at each use.
+fn write_value_(pgm: &Pgm, heap: &Heap, obj: u64, s: &mut String, path: &mut Set<u64>) {
+    use std::fmt::Write;
+
+    if obj == 0 {
+        s.push_str("()");
+        return;
+    }
+
+    if !path.insert(obj) {
+        s.push_str("<cycle>");
+        return;
+    }
+
+    let tag = heap.tag(pgm, obj);
+    match tag {
+        I32_TYPE_TAG => write!(s, "{}", heap[obj + 1] as i32).unwrap(),
+
+        CHAR_TYPE_TAG => write!(s, "{:?}", heap.char_value(obj)).unwrap(),
+
+        F64_TYPE_TAG => write!(s, "{:?}", heap.f64_value(obj)).unwrap(),
+
+        STR_TYPE_TAG => write!(s, "{:?}", String::from_utf8_lossy(heap.str_bytes(obj))).unwrap(),
+
+        STR_VIEW_TYPE_TAG => {
+            write!(s, "{:?}", String::from_utf8_lossy(heap.str_view_bytes(obj))).unwrap()
+        }
+
+        ARRAY_TYPE_TAG => {
+            s.push('[');
+            let len = heap[obj + 1];
+            for i in 0..len {
+                if i != 0 {
+                    s.push_str(", ");
+                }
+                write_value_(pgm, heap, heap[obj + 2 + i], s, path);
+            }
+            s.push(']');
+        }
+
+        SET_TYPE_TAG => {
+            s.push_str("Set[");
+            for (i, elem) in heap.set_elems(obj).into_iter().enumerate() {
+                if i != 0 {
+                    s.push_str(", ");
+                }
+                write_value_(pgm, heap, elem, s, path);
+            }
+            s.push(']');
+        }
+
+        STR_BUILDER_TYPE_TAG => write!(
+            s,
+            "StrBuilder({:?})",
+            String::from_utf8_lossy(heap.str_builder_bytes(obj))
+        )
+        .unwrap(),
+
+        TREE_MAP_TYPE_TAG => {
+            s.push_str("TreeMap[");
+            let len = heap[obj + 1];
+            let (keys, values) = (heap[obj + 2], heap[obj + 3]);
+            for i in 0..len {
+                if i != 0 {
+                    s.push_str(", ");
+                }
+                write_value_(pgm, heap, heap[keys + 2 + i], s, path);
+                s.push_str(": ");
+                write_value_(pgm, heap, heap[values + 2 + i], s, path);
+            }
+            s.push(']');
+        }
+
+        CONSTR_TYPE_TAG | TOP_FUN_TYPE_TAG | ASSOC_FUN_TYPE_TAG => s.push_str("<function>"),
+
+        _ => {
+            let con = &pgm.cons_by_tag[tag as usize];
+
+            match &con.info {
+                // Product types have the type name as the constructor name.
+                ConInfo::Named {
+                    ty_name,
+                    con_name: Some(con_name),
+                } if ty_name != con_name => write!(s, "{}.{}", ty_name, con_name).unwrap(),
+
+                ConInfo::Named { ty_name, .. } => s.push_str(ty_name),
+
+                ConInfo::Record { .. } => {}
+            }
+
+            match &con.fields {
+                Fields::Unnamed(0) if !matches!(con.info, ConInfo::Record { .. }) => {}
+                Fields::Unnamed(arity) => {
+                    s.push('(');
+                    for i in 0..u64::from(*arity) {
+                        if i != 0 {
+                            s.push_str(", ");
+                        }
+                        write_value_(pgm, heap, heap[obj + 1 + i], s, path);
+                    }
+                    s.push(')');
+                }
+                Fields::Named(fields) => {
+                    s.push('(');
+                    for (i, field_name) in fields.iter().enumerate() {
+                        if i != 0 {
+                            s.push_str(", ");
+                        }
+                        write!(s, "{} = ", field_name).unwrap();
+                        write_value_(pgm, heap, heap[obj + 1 + (i as u64)], s, path);
+                    }
+                    s.push(')');
+                }
+            }
+        }
+    }
+
+    path.remove(&obj);
+}
+
+/// Displays a `Loc` as `module:line:col`.
+///
+/// The alternate form (`{:#}`) also shows the end position, as `module:line:col-line:col`.
+pub(crate) struct LocDisplay<'a>(pub(crate) &'a Loc);
+
+impl<'a> LocDisplay<'a> {
+    /// Drop the module, for output about a single-module program where the module name is the
+    /// same in every location.
+    pub(crate) fn without_module(self) -> CompactLocDisplay<'a> {
+        CompactLocDisplay(self.0)
+    }
+}
 
 impl<'a> std::fmt::Display for LocDisplay<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}:{}", self.0.line_start + 1, self.0.col_start + 1)
+        write!(f, "{}:", self.0.module)?;
+        fmt_loc_lines(self.0, f)
+    }
+}
+
+/// Displays a `Loc` as `line:col`, or `line:col-line:col` in the alternate form. Created with
+/// `LocDisplay::without_module`.
+pub(crate) struct CompactLocDisplay<'a>(&'a Loc);
+
+impl<'a> std::fmt::Display for CompactLocDisplay<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt_loc_lines(self.0, f)
+    }
+}
+
+fn fmt_loc_lines(loc: &Loc, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}:{}", loc.line_start + 1, loc.col_start + 1)?;
+    if f.alternate() {
+        write!(f, "-{}:{}", loc.line_end + 1, loc.col_end + 1)?;
     }
+    Ok(())
 }
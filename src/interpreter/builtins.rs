@@ -1,6 +1,9 @@
 use crate::ast::Loc;
 use crate::interpreter::*;
 
+use std::cell::Cell;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::io::Write;
 
 use bytemuck::cast_slice;
@@ -8,31 +11,141 @@ use bytemuck::cast_slice;
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BuiltinFun {
     // Top funs
+    Assert,
+    Clone,
+    DumpHeap,
+    Format,
+    Now,
     Panic,
     Print,
     PrintStr,
     PrintStrView,
+    Random,
+    RandomSeed,
+    RefEq,
+    SizeOf,
+    StructuralEq,
+    TypeName,
 
     // Assoc funs
+    ArrayConcat,
+    ArrayContains,
+    ArrayFill,
+    ArrayFilter,
+    ArrayFold,
+    ArrayForEach,
+    ArrayForEachIndexed,
+    ArrayForEachWhile,
     ArrayGet,
+    ArrayIndexOf,
+    ArrayIter,
+    ArrayIterNext,
+    ArrayJoin,
     ArrayLen,
+    ArrayMap,
     ArrayNew,
+    ArrayReverse,
     ArraySet,
+    ArraySort,
+    ArraySortBy,
+    BoolAnd,
+    BoolEq,
+    BoolOr,
+    BoolToStr,
+    CharCmp,
+    CharEq,
+    CharToStr,
+    F64Add,
+    F64Cmp,
+    F64Div,
+    F64Eq,
+    F64Mul,
+    F64Sub,
+    F64ToI32,
+    F64ToStr,
     I32Add,
+    I32AddChecked,
+    I32AddSaturating,
+    I32AddWrapping,
+    I32BitAnd,
+    I32BitNot,
+    I32BitOr,
+    I32BitXor,
     I32Cmp,
+    I32Div,
     I32Eq,
+    I32Mod,
     I32Mul,
+    I32Shl,
+    I32Shr,
     I32Sub,
+    I32ToF64,
     I32ToStr,
+    OptionAndThen,
+    OptionGetOrElse,
+    OptionMap,
+    OptionOrElse,
+    OptionUnwrap,
+    OptionUnwrapOr,
+    OrderingReverse,
+    OrderingThen,
+    RangeIter,
+    RangeIterNext,
+    RangeStep,
+    ResultAndThen,
+    ResultMap,
+    ResultUnwrap,
+    ResultUnwrapOr,
+    ScannerAdvance,
+    ScannerIter,
+    ScannerNew,
+    ScannerNext,
+    ScannerPeek,
+    ScannerPos,
+    ScannerRemaining,
+    ScannerStartsWith,
+    SetAdd,
+    SetContains,
+    SetIter,
+    SetLen,
+    SetNew,
+    SetRemove,
+    StrBuilderAppend,
+    StrBuilderLen,
+    StrBuilderNew,
+    StrBuilderToStr,
+    StrBuilderWithCapacity,
+    StrByteAt,
+    StrByteLen,
+    StrCharAt,
+    StrCharLen,
+    StrCmp,
+    StrIsValidUtf8,
+    StrChars,
+    StrCharsIter,
+    StrCharsNext,
+    StrEndsWith,
     StrEq,
+    StrIsEmpty,
     StrLen,
+    StrLines,
+    StrRepeat,
+    StrReplace,
+    StrStartsWith,
     StrSubstr,
+    StrToStr,
     StrViewEq,
     StrViewIsEmpty,
     StrViewLen,
     StrViewStartsWith,
     StrViewSubstr,
     StrViewToStr,
+    TreeMapGet,
+    TreeMapInsert,
+    TreeMapIter,
+    TreeMapLen,
+    TreeMapNew,
+    TreeMapRemove,
 }
 
 pub fn call_builtin_fun<W: Write>(
@@ -42,24 +155,163 @@ pub fn call_builtin_fun<W: Write>(
     fun: &BuiltinFun,
     args: Vec<u64>,
     loc: &Loc,
-) -> u64 {
-    match fun {
+) -> Result<u64, EvalError> {
+    Ok(match fun {
         BuiltinFun::Panic => {
             debug_assert!(args.len() <= 1);
 
-            let msg: String = if args.len() == 1 {
-                let msg = args[0];
-                let len_bytes = heap[msg + 1];
-                let len_words = len_bytes.div_ceil(8);
-                let words =
-                    &heap.values[(msg as usize) + 2..(msg as usize) + 2 + (len_words as usize)];
-                let bytes = cast_slice(words);
-                String::from_utf8_lossy(&bytes[..len_bytes as usize]).into_owned()
+            let msg: String = match args.first() {
+                Some(msg) => {
+                    let bytes = str_arg_bytes(heap, *msg, "panic message", loc)?;
+                    String::from_utf8_lossy(bytes).into_owned()
+                }
+                None => "".to_string(),
+            };
+
+            return Err(RunError::UserPanic {
+                msg,
+                loc: loc.clone(),
+            }
+            .into());
+        }
+
+        BuiltinFun::Assert => {
+            debug_assert!(args.len() == 1 || args.len() == 2);
+
+            let cond = args[0];
+            debug_assert!(cond == pgm.true_alloc || cond == pgm.false_alloc);
+            if cond == pgm.false_alloc {
+                let msg: Option<String> = args
+                    .get(1)
+                    .map(|msg| String::from_utf8_lossy(heap.str_bytes(*msg)).into_owned());
+                return Err(RunError::AssertionFailed {
+                    msg,
+                    loc: loc.clone(),
+                }
+                .into());
+            }
+            0
+        }
+
+        BuiltinFun::Now => {
+            // Milliseconds since the program started, see `RunOptions::clock`.
+            debug_assert!(args.is_empty());
+            let now = pgm
+                .clock
+                .now()
+                .ok_or_else(|| RunError::ClockDisabled { loc: loc.clone() })?;
+            heap.allocate_i32(now)
+        }
+
+        BuiltinFun::Random => {
+            // Uniformly distributed over the whole `I32` range, see `Rng`.
+            debug_assert!(args.is_empty());
+            heap.allocate_i32(pgm.rng.next_i32())
+        }
+
+        BuiltinFun::RandomSeed => {
+            debug_assert_eq!(args.len(), 1);
+            let seed = heap[args[0] + 1] as i32;
+            pgm.rng.seed(seed as u64);
+            0
+        }
+
+        BuiltinFun::Clone => {
+            debug_assert_eq!(args.len(), 1);
+            heap.clone_object(pgm, args[0])
+        }
+
+        BuiltinFun::SizeOf => {
+            // Words of the object, not including the objects it refers to. Unit is not
+            // allocated.
+            debug_assert_eq!(args.len(), 1);
+            let size = if args[0] == 0 {
+                0
             } else {
-                "".to_string()
+                heap.object_size(pgm, args[0])
             };
+            heap.allocate_i32(size as i32)
+        }
+
+        BuiltinFun::TypeName => {
+            debug_assert_eq!(args.len(), 1);
+            let name = type_name(pgm, heap, args[0]);
+            heap.allocate_str(name.as_bytes())
+        }
+
+        BuiltinFun::DumpHeap => {
+            debug_assert_eq!(args.len(), 1);
+            write!(w, "{}", heap.dump(pgm, &args)).unwrap();
+            0
+        }
+
+        BuiltinFun::Format => {
+            debug_assert_eq!(args.len(), 2);
+            let template = args[0];
+            let format_args = args[1];
+            debug_assert_eq!(heap.tag(pgm, format_args), ARRAY_TYPE_TAG);
+
+            let template: Vec<u8> = match heap.tag(pgm, template) {
+                STR_TYPE_TAG => heap.str_bytes(template).to_vec(),
+                STR_VIEW_TYPE_TAG => heap.str_view_bytes(template).to_vec(),
+                _ => {
+                    return Err(RunError::TypeError {
+                        msg: "format template is not a string".to_string(),
+                        loc: loc.clone(),
+                    }
+                    .into())
+                }
+            };
+
+            let format_error = |msg: String| RunError::FormatError {
+                msg,
+                loc: loc.clone(),
+            };
+
+            let num_args = heap[format_args + 1];
+            let mut next_arg = 0;
+            let mut out: Vec<u8> = Vec::with_capacity(template.len());
+            let mut bytes = template.iter().copied().peekable();
+            while let Some(byte) = bytes.next() {
+                match (byte, bytes.peek()) {
+                    (b'{', Some(b'{')) | (b'}', Some(b'}')) => {
+                        bytes.next();
+                        out.push(byte);
+                    }
+                    (b'{', Some(b'}')) => {
+                        bytes.next();
+                        if next_arg == num_args {
+                            return Err(format_error(format!(
+                                "template has more placeholders than the {} arguments",
+                                num_args
+                            ))
+                            .into());
+                        }
+                        let arg = array_elem(heap, format_args, next_arg, loc)?;
+                        next_arg += 1;
+                        let arg_str = call_method(w, pgm, heap, arg, &"toStr".into(), vec![], loc)?;
+                        out.extend_from_slice(heap.str_bytes(arg_str));
+                    }
+                    (b'{', _) | (b'}', _) => {
+                        return Err(format_error(format!(
+                            "unmatched '{}' in template, use '{}{}' for a literal brace",
+                            byte as char, byte as char, byte as char
+                        ))
+                        .into());
+                    }
+                    _ => out.push(byte),
+                }
+            }
 
-            panic!("{}: PANIC: {}", LocDisplay(loc), msg);
+            if next_arg != num_args {
+                return Err(format_error(format!(
+                    "template has {} placeholders, but {} arguments were given",
+                    next_arg, num_args
+                ))
+                .into());
+            }
+
+            heap.allocate_utf8_str(&out)
         }
 
         BuiltinFun::Print => {
@@ -73,7 +325,7 @@ pub fn call_builtin_fun<W: Write>(
         BuiltinFun::PrintStr => {
             debug_assert_eq!(args.len(), 1);
             let str = args[0];
-            debug_assert_eq!(heap[str], STR_TYPE_TAG);
+            debug_assert_eq!(heap.tag(pgm, str), STR_TYPE_TAG);
 
             let len_bytes = heap[str + 1];
             let len_words = len_bytes.div_ceil(8);
@@ -88,10 +340,24 @@ pub fn call_builtin_fun<W: Write>(
             0
         }
 
+        BuiltinFun::StructuralEq => {
+            debug_assert_eq!(args.len(), 2);
+            pgm.bool_alloc(structural_eq(pgm, heap, args[0], args[1]))
+        }
+
+        BuiltinFun::RefEq => {
+            // Compares the objects' addresses, without calling `__eq`. Values that the interpreter
+            // allocates once and reuses (unit, `Bool`s and other nullary constructors) are the same
+            // object, so `refEq` is true for those when they are equal, but e.g. two `I32`s or
+            // `Str`s with the same value can be different objects.
+            debug_assert_eq!(args.len(), 2);
+            pgm.bool_alloc(args[0] == args[1])
+        }
+
         BuiltinFun::PrintStrView => {
             debug_assert_eq!(args.len(), 1);
             let str = args[0];
-            debug_assert_eq!(heap[str], STR_VIEW_TYPE_TAG);
+            debug_assert_eq!(heap.tag(pgm, str), STR_VIEW_TYPE_TAG);
 
             let view_start = heap[str + 1];
             let view_end = heap[str + 2];
@@ -116,68 +382,885 @@ pub fn call_builtin_fun<W: Write>(
             0
         }
 
-        BuiltinFun::ArrayNew => {
+        BuiltinFun::ArrayNew => {
+            // `Array.new(len)` leaves the elements uninitialized, `Array.new(len, init)` sets all
+            // elements to `init`.
+            debug_assert!(args.len() == 1 || args.len() == 2);
+
+            let len = args[0];
+            debug_assert_eq!(heap.tag(pgm, len), I32_TYPE_TAG);
+            let len = heap[len + 1] as i32;
+            if len < 0 {
+                return Err(RunError::NegativeLength {
+                    len,
+                    loc: loc.clone(),
+                }
+                .into());
+            }
+            let array = heap.allocate_array(len as u64);
+            if let Some(init) = args.get(1) {
+                for i in 0..len as u64 {
+                    heap[array + 2 + i] = *init;
+                }
+            }
+            array
+        }
+
+        BuiltinFun::ArrayFill => {
+            debug_assert_eq!(args.len(), 2);
+
+            let array = args[0];
+            debug_assert_eq!(heap.tag(pgm, array), ARRAY_TYPE_TAG);
+            let len = heap[array + 1];
+            for i in 0..len {
+                heap[array + 2 + i] = args[1];
+            }
+            0
+        }
+
+        BuiltinFun::ArrayLen => {
+            debug_assert_eq!(args.len(), 1);
+
+            let array = args[0];
+            debug_assert_eq!(heap.tag(pgm, array), ARRAY_TYPE_TAG);
+            let len = heap[array + 1];
+            heap.allocate_i32(len as i32)
+        }
+
+        BuiltinFun::ArraySet => {
+            debug_assert_eq!(args.len(), 3);
+
+            let array = args[0];
+            debug_assert_eq!(heap.tag(pgm, array), ARRAY_TYPE_TAG);
+
+            let idx = args[1];
+            debug_assert_eq!(heap.tag(pgm, idx), I32_TYPE_TAG);
+
+            let elem = args[2];
+
+            let idx = array_get_set_idx(heap, array, idx, loc)?;
+            heap[array + 2 + idx] = elem;
+            elem
+        }
+
+        BuiltinFun::ArrayGet => {
+            debug_assert_eq!(args.len(), 2);
+
+            let array = args[0];
+            debug_assert_eq!(heap.tag(pgm, array), ARRAY_TYPE_TAG);
+
+            let idx = args[1];
+            debug_assert_eq!(heap.tag(pgm, idx), I32_TYPE_TAG);
+
+            let idx = array_get_set_idx(heap, array, idx, loc)?;
+            array_elem(heap, array, idx, loc)?
+        }
+
+        BuiltinFun::ArrayMap => {
+            debug_assert_eq!(args.len(), 2);
+
+            let array = args[0];
+            debug_assert_eq!(heap.tag(pgm, array), ARRAY_TYPE_TAG);
+            let f = args[1];
+
+            // Elements are mapped from left to right, into a new array with the same length.
+            let len = heap[array + 1];
+            let new_array = heap.allocate_array(len);
+            for i in 0..len {
+                let elem = array_elem(heap, array, i, loc)?;
+                let new_elem = call_fun_value(w, pgm, heap, f, vec![elem], loc)?;
+                heap[new_array + 2 + i] = new_elem;
+            }
+            new_array
+        }
+
+        BuiltinFun::ArrayForEach | BuiltinFun::ArrayForEachWhile => {
+            // Calls the function with the elements from left to right. `forEachWhile` stops when
+            // the function returns `False`, and returns whether all elements were visited.
+            // Exceptions thrown by the function stop the iteration and propagate to the caller.
+            debug_assert_eq!(args.len(), 2);
+
+            let array = args[0];
+            debug_assert_eq!(heap.tag(pgm, array), ARRAY_TYPE_TAG);
+            let f = args[1];
+
+            let len = heap[array + 1];
+            let mut completed = true;
+            for i in 0..len {
+                let elem = array_elem(heap, array, i, loc)?;
+                let ret = call_fun_value(w, pgm, heap, f, vec![elem], loc)?;
+                if *fun == BuiltinFun::ArrayForEachWhile {
+                    debug_assert!(ret == pgm.true_alloc || ret == pgm.false_alloc);
+                    if ret == pgm.false_alloc {
+                        completed = false;
+                        break;
+                    }
+                }
+            }
+
+            if *fun == BuiltinFun::ArrayForEach {
+                0 // unit
+            } else {
+                pgm.bool_alloc(completed)
+            }
+        }
+
+        BuiltinFun::ArrayForEachIndexed => {
+            // Like `forEach`, but the function is also passed the index of the element, as the
+            // first argument. Indices are zero-based `I32`s.
+            debug_assert_eq!(args.len(), 2);
+
+            let array = args[0];
+            debug_assert_eq!(heap.tag(pgm, array), ARRAY_TYPE_TAG);
+            let f = args[1];
+
+            let len = heap[array + 1];
+            for i in 0..len {
+                let elem = array_elem(heap, array, i, loc)?;
+                let idx = heap.allocate_i32(i as i32);
+                call_fun_value(w, pgm, heap, f, vec![idx, elem], loc)?;
+            }
+
+            0 // unit
+        }
+
+        BuiltinFun::ArrayFilter => {
+            debug_assert_eq!(args.len(), 2);
+
+            let array = args[0];
+            debug_assert_eq!(heap.tag(pgm, array), ARRAY_TYPE_TAG);
+            let pred = args[1];
+
+            // Predicate is called on elements from left to right. The result is a new array with
+            // the length of the number of elements kept.
+            let len = heap[array + 1];
+            let mut kept: Vec<u64> = vec![];
+            for i in 0..len {
+                let elem = array_elem(heap, array, i, loc)?;
+                let keep = call_fun_value(w, pgm, heap, pred, vec![elem], loc)?;
+                debug_assert!(keep == pgm.true_alloc || keep == pgm.false_alloc);
+                if keep == pgm.true_alloc {
+                    kept.push(elem);
+                }
+            }
+
+            let new_array = heap.allocate_array(kept.len() as u64);
+            for (i, elem) in kept.into_iter().enumerate() {
+                heap[new_array + 2 + (i as u64)] = elem;
+            }
+            new_array
+        }
+
+        BuiltinFun::ArrayFold => {
+            debug_assert_eq!(args.len(), 3);
+
+            let array = args[0];
+            debug_assert_eq!(heap.tag(pgm, array), ARRAY_TYPE_TAG);
+            let mut acc = args[1];
+            let f = args[2];
+
+            // `f` is called with the accumulator and the element, from left to right.
+            let len = heap[array + 1];
+            for i in 0..len {
+                let elem = array_elem(heap, array, i, loc)?;
+                acc = call_fun_value(w, pgm, heap, f, vec![acc, elem], loc)?;
+            }
+            acc
+        }
+
+        BuiltinFun::ArrayConcat => {
+            debug_assert_eq!(args.len(), 2);
+            let array1 = args[0];
+            let array2 = args[1];
+            debug_assert_eq!(heap.tag(pgm, array1), ARRAY_TYPE_TAG);
+            debug_assert_eq!(heap.tag(pgm, array2), ARRAY_TYPE_TAG);
+
+            // Returns a new array, the arguments are not modified.
+            let len1 = heap[array1 + 1];
+            let len2 = heap[array2 + 1];
+            let new_array = heap.allocate_array(len1 + len2);
+            for i in 0..len1 {
+                heap[new_array + 2 + i] = heap[array1 + 2 + i];
+            }
+            for i in 0..len2 {
+                heap[new_array + 2 + len1 + i] = heap[array2 + 2 + i];
+            }
+            new_array
+        }
+
+        BuiltinFun::ArrayReverse => {
+            debug_assert_eq!(args.len(), 1);
+            let array = args[0];
+            debug_assert_eq!(heap.tag(pgm, array), ARRAY_TYPE_TAG);
+
+            // Returns a new array, the argument is not modified.
+            let len = heap[array + 1];
+            let new_array = heap.allocate_array(len);
+            for i in 0..len {
+                heap[new_array + 2 + i] = heap[array + 2 + (len - 1 - i)];
+            }
+            new_array
+        }
+
+        BuiltinFun::ArrayJoin => {
+            // Concatenates `toStr` of the elements, with the separator between them.
+            debug_assert_eq!(args.len(), 2);
+            let array = args[0];
+            debug_assert_eq!(heap.tag(pgm, array), ARRAY_TYPE_TAG);
+
+            let sep: Vec<u8> = str_arg_bytes(heap, args[1], "join separator", loc)?.to_vec();
+
+            let len = heap[array + 1];
+            let mut out: Vec<u8> = vec![];
+            for i in 0..len {
+                if i != 0 {
+                    out.extend_from_slice(&sep);
+                }
+                let elem = array_elem(heap, array, i, loc)?;
+                let elem_str = call_method(w, pgm, heap, elem, &"toStr".into(), vec![], loc)?;
+                out.extend_from_slice(heap.str_bytes(elem_str));
+            }
+            heap.allocate_utf8_str(&out)
+        }
+
+        BuiltinFun::ArrayContains => {
+            debug_assert_eq!(args.len(), 2);
+            let array = args[0];
+            debug_assert_eq!(heap.tag(pgm, array), ARRAY_TYPE_TAG);
+            let idx = array_index_of(w, pgm, heap, array, args[1], loc)?;
+            pgm.bool_alloc(idx.is_some())
+        }
+
+        BuiltinFun::ArrayIndexOf => {
+            debug_assert_eq!(args.len(), 2);
+            let array = args[0];
+            debug_assert_eq!(heap.tag(pgm, array), ARRAY_TYPE_TAG);
+            let idx = array_index_of(w, pgm, heap, array, args[1], loc)?;
+            heap.allocate_i32(idx.map(|idx| idx as i32).unwrap_or(-1))
+        }
+
+        BuiltinFun::ArraySort | BuiltinFun::ArraySortBy => {
+            // Sorts the array in place, with `__cmp` of the elements or the given function. The sort
+            // is stable: equal elements keep their relative order.
+            let array = args[0];
+            debug_assert_eq!(heap.tag(pgm, array), ARRAY_TYPE_TAG);
+
+            let len = heap[array + 1];
+            let elems: Vec<u64> = (0..len)
+                .map(|i| array_elem(heap, array, i, loc))
+                .collect::<Result<_, _>>()?;
+
+            let sorted = match fun {
+                BuiltinFun::ArraySort => {
+                    debug_assert_eq!(args.len(), 1);
+                    merge_sort(elems, &mut |elem1, elem2| {
+                        cmp(w, pgm, heap, elem1, elem2, loc)
+                    })?
+                }
+                _ => {
+                    debug_assert_eq!(args.len(), 2);
+                    let f = args[1];
+                    merge_sort(elems, &mut |elem1, elem2| {
+                        let ordering = call_fun_value(w, pgm, heap, f, vec![elem1, elem2], loc)?;
+                        Ok(ordering_from_value(pgm, heap, ordering))
+                    })?
+                }
+            };
+
+            for (i, elem) in sorted.into_iter().enumerate() {
+                heap[array + 2 + i as u64] = elem;
+            }
+            0
+        }
+
+        BuiltinFun::ArrayIter => {
+            debug_assert_eq!(args.len(), 1);
+            let array = args[0];
+            debug_assert_eq!(heap.tag(pgm, array), ARRAY_TYPE_TAG);
+            let iter = heap.allocate(3);
+            heap[iter] = ARRAY_ITER_TYPE_TAG;
+            heap[iter + 1] = array;
+            heap[iter + 2] = 0; // index of the next element
+            iter
+        }
+
+        BuiltinFun::ArrayIterNext => {
+            debug_assert_eq!(args.len(), 1);
+            let iter = args[0];
+            debug_assert_eq!(heap.tag(pgm, iter), ARRAY_ITER_TYPE_TAG);
+            let array = heap[iter + 1];
+            let idx = heap[iter + 2];
+            if idx == heap[array + 1] {
+                none(pgm)
+            } else {
+                heap[iter + 2] = idx + 1;
+                let elem = array_elem(heap, array, idx, loc)?;
+                allocate_some(pgm, heap, elem)
+            }
+        }
+
+        BuiltinFun::RangeStep => {
+            debug_assert_eq!(args.len(), 2);
+            let range = args[0];
+            let step = args[1];
+            debug_assert_eq!(heap.tag(pgm, step), I32_TYPE_TAG);
+            let step = heap[step + 1] as i32;
+            if step == 0 {
+                return Err(RunError::InvalidArgument {
+                    msg: "Range step cannot be 0".to_string(),
+                    loc: loc.clone(),
+                }
+                .into());
+            }
+            let (from, to, inclusive, _) = heap.range_fields(range);
+            heap.allocate_range(from, to, inclusive, step)
+        }
+
+        BuiltinFun::RangeIter => {
+            debug_assert_eq!(args.len(), 1);
+            let range = args[0];
+            let (from, _, _, _) = heap.range_fields(range);
+            let iter = heap.allocate(3);
+            heap[iter] = RANGE_ITER_TYPE_TAG;
+            heap[iter + 1] = range;
+            // Next value as `i64`, to avoid overflows when stepping past the last value.
+            heap[iter + 2] = i64::from(from) as u64;
+            iter
+        }
+
+        BuiltinFun::RangeIterNext => {
+            debug_assert_eq!(args.len(), 1);
+            let iter = args[0];
+            debug_assert_eq!(heap.tag(pgm, iter), RANGE_ITER_TYPE_TAG);
+            let (_, to, inclusive, step) = heap.range_fields(heap[iter + 1]);
+            let next = heap[iter + 2] as i64;
+            match i32::try_from(next) {
+                Ok(i) if range_contains(to, inclusive, step, i) => {
+                    heap[iter + 2] = (next + i64::from(step)) as u64;
+                    let value = heap.allocate_i32(i);
+                    allocate_some(pgm, heap, value)
+                }
+                _ => none(pgm),
+            }
+        }
+
+        BuiltinFun::OptionUnwrap => {
+            debug_assert_eq!(args.len(), 1);
+            let option = args[0];
+            if heap.tag(pgm, option) == constr_tag(pgm, "Option", "Some") {
+                heap[option + 1]
+            } else {
+                return Err(RunError::InvalidArgument {
+                    msg: "Option.unwrap called on Option.None".to_string(),
+                    loc: loc.clone(),
+                }
+                .into());
+            }
+        }
+
+        BuiltinFun::OptionUnwrapOr => {
+            debug_assert_eq!(args.len(), 2);
+            let option = args[0];
+            if heap.tag(pgm, option) == constr_tag(pgm, "Option", "Some") {
+                heap[option + 1]
+            } else {
+                args[1]
+            }
+        }
+
+        BuiltinFun::OptionMap => {
+            debug_assert_eq!(args.len(), 2);
+            let option = args[0];
+            if heap.tag(pgm, option) == constr_tag(pgm, "Option", "Some") {
+                let value = call_fun_value(w, pgm, heap, args[1], vec![heap[option + 1]], loc)?;
+                allocate_some(pgm, heap, value)
+            } else {
+                option
+            }
+        }
+
+        BuiltinFun::OptionAndThen => {
+            // Like `map`, but the function returns an `Option`, which is returned as the result.
+            debug_assert_eq!(args.len(), 2);
+            let option = args[0];
+            if heap.tag(pgm, option) == constr_tag(pgm, "Option", "Some") {
+                call_fun_value(w, pgm, heap, args[1], vec![heap[option + 1]], loc)?
+            } else {
+                option
+            }
+        }
+
+        BuiltinFun::OptionOrElse => {
+            // The function is called without arguments when the option is `None`, and returns
+            // the `Option` to use instead.
+            debug_assert_eq!(args.len(), 2);
+            let option = args[0];
+            if heap.tag(pgm, option) == constr_tag(pgm, "Option", "Some") {
+                option
+            } else {
+                call_fun_value(w, pgm, heap, args[1], vec![], loc)?
+            }
+        }
+
+        BuiltinFun::OptionGetOrElse => {
+            // Like `unwrapOr`, but the default value is computed only when the option is `None`.
+            debug_assert_eq!(args.len(), 2);
+            let option = args[0];
+            if heap.tag(pgm, option) == constr_tag(pgm, "Option", "Some") {
+                heap[option + 1]
+            } else {
+                call_fun_value(w, pgm, heap, args[1], vec![], loc)?
+            }
+        }
+
+        BuiltinFun::OrderingReverse => {
+            debug_assert_eq!(args.len(), 1);
+            let ordering = ordering_from_value(pgm, heap, args[0]);
+            ordering_value(pgm, ordering.reverse())
+        }
+
+        BuiltinFun::OrderingThen => {
+            // `a.then(b)` is `a`, or `b` if `a` is `Equal`. Used to compare by multiple keys.
+            debug_assert_eq!(args.len(), 2);
+            if ordering_from_value(pgm, heap, args[0]) == Ordering::Equal {
+                args[1]
+            } else {
+                args[0]
+            }
+        }
+
+        BuiltinFun::ResultUnwrap => {
+            debug_assert_eq!(args.len(), 1);
+            let result = args[0];
+            if heap.tag(pgm, result) == constr_tag(pgm, "Result", "Ok") {
+                heap[result + 1]
+            } else {
+                return Err(RunError::InvalidArgument {
+                    msg: format!(
+                        "Result.unwrap called on Result.Err({})",
+                        value_to_string(pgm, heap, heap[result + 1])
+                    ),
+                    loc: loc.clone(),
+                }
+                .into());
+            }
+        }
+
+        BuiltinFun::ResultUnwrapOr => {
+            debug_assert_eq!(args.len(), 2);
+            let result = args[0];
+            if heap.tag(pgm, result) == constr_tag(pgm, "Result", "Ok") {
+                heap[result + 1]
+            } else {
+                args[1]
+            }
+        }
+
+        BuiltinFun::ResultMap => {
+            debug_assert_eq!(args.len(), 2);
+            let result = args[0];
+            let ok_tag = constr_tag(pgm, "Result", "Ok");
+            if heap.tag(pgm, result) == ok_tag {
+                let value = call_fun_value(w, pgm, heap, args[1], vec![heap[result + 1]], loc)?;
+                let new_result = heap.allocate(2);
+                heap[new_result] = ok_tag;
+                heap[new_result + 1] = value;
+                new_result
+            } else {
+                result
+            }
+        }
+
+        BuiltinFun::ResultAndThen => {
+            debug_assert_eq!(args.len(), 2);
+            let result = args[0];
+            if heap.tag(pgm, result) == constr_tag(pgm, "Result", "Ok") {
+                call_fun_value(w, pgm, heap, args[1], vec![heap[result + 1]], loc)?
+            } else {
+                result
+            }
+        }
+
+        BuiltinFun::ScannerNew => {
+            // A scanner is a string and the byte index of the cursor. Methods move the cursor
+            // without allocating views of the rest of the string.
+            debug_assert_eq!(args.len(), 1);
+            let str = args[0];
+            debug_assert_eq!(heap.tag(pgm, str), STR_TYPE_TAG);
+            let scanner = heap.allocate(3);
+            heap[scanner] = SCANNER_TYPE_TAG;
+            heap[scanner + 1] = str;
+            heap[scanner + 2] = 0;
+            scanner
+        }
+
+        BuiltinFun::ScannerIter => {
+            debug_assert_eq!(args.len(), 1);
+            debug_assert_eq!(heap.tag(pgm, args[0]), SCANNER_TYPE_TAG);
+            args[0]
+        }
+
+        BuiltinFun::ScannerPeek | BuiltinFun::ScannerNext => {
+            debug_assert_eq!(args.len(), 1);
+            let scanner = args[0];
+            debug_assert_eq!(heap.tag(pgm, scanner), SCANNER_TYPE_TAG);
+            let idx = heap[scanner + 2] as usize;
+            match decode_char(&heap.str_bytes(heap[scanner + 1])[idx..]) {
+                None => none(pgm),
+                Some((char, len)) => {
+                    if *fun == BuiltinFun::ScannerNext {
+                        heap[scanner + 2] = (idx + len) as u64;
+                    }
+                    let char = heap.allocate_char(char);
+                    allocate_some(pgm, heap, char)
+                }
+            }
+        }
+
+        BuiltinFun::ScannerAdvance => {
+            // Moves the cursor by the given number of bytes, e.g. the length of a string matched
+            // with `startsWith`.
+            debug_assert_eq!(args.len(), 2);
+            let scanner = args[0];
+            debug_assert_eq!(heap.tag(pgm, scanner), SCANNER_TYPE_TAG);
+            debug_assert_eq!(heap.tag(pgm, args[1]), I32_TYPE_TAG);
+            let n = heap[args[1] + 1] as i32;
+            let idx = heap[scanner + 2];
+            let len = heap[heap[scanner + 1] + 1];
+            if n < 0 || idx + n as u64 > len {
+                return Err(RunError::OutOfBounds {
+                    msg: format!(
+                        "Scanner.advance({}) out of bounds, position = {}, byte length = {}",
+                        n, idx, len
+                    ),
+                    loc: loc.clone(),
+                }
+                .into());
+            }
+            heap[scanner + 2] = idx + n as u64;
+            0
+        }
+
+        BuiltinFun::ScannerStartsWith => {
+            debug_assert_eq!(args.len(), 2);
+            let scanner = args[0];
+            debug_assert_eq!(heap.tag(pgm, scanner), SCANNER_TYPE_TAG);
+            let idx = heap[scanner + 2] as usize;
+            let rest = &heap.str_bytes(heap[scanner + 1])[idx..];
+            let pfx = str_arg_bytes(heap, args[1], "argument", loc)?;
+            pgm.bool_alloc(rest.starts_with(pfx))
+        }
+
+        BuiltinFun::ScannerPos => {
+            // Byte index of the cursor.
+            debug_assert_eq!(args.len(), 1);
+            let scanner = args[0];
+            debug_assert_eq!(heap.tag(pgm, scanner), SCANNER_TYPE_TAG);
+            heap.allocate_i32(heap[scanner + 2] as i32)
+        }
+
+        BuiltinFun::ScannerRemaining => {
+            debug_assert_eq!(args.len(), 1);
+            let scanner = args[0];
+            debug_assert_eq!(heap.tag(pgm, scanner), SCANNER_TYPE_TAG);
+            let str = heap[scanner + 1];
+            heap.allocate_str_view(str, heap[scanner + 2], heap[str + 1])
+                .unwrap()
+        }
+
+        BuiltinFun::SetNew => {
+            debug_assert_eq!(args.len(), 0);
+            heap.allocate_set()
+        }
+
+        BuiltinFun::SetAdd => {
+            // Returns whether the element was added, i.e. it was not already in the set.
+            debug_assert_eq!(args.len(), 2);
+            let set = args[0];
+            debug_assert_eq!(heap.tag(pgm, set), SET_TYPE_TAG);
+            let hash = value_hash(w, pgm, heap, args[1], loc)?;
+            if set_position_of(w, pgm, heap, set, args[1], hash, loc)?.is_some() {
+                pgm.false_alloc
+            } else {
+                heap.set_add(set, args[1], hash);
+                pgm.true_alloc
+            }
+        }
+
+        BuiltinFun::SetContains => {
+            debug_assert_eq!(args.len(), 2);
+            let set = args[0];
+            debug_assert_eq!(heap.tag(pgm, set), SET_TYPE_TAG);
+            let hash = value_hash(w, pgm, heap, args[1], loc)?;
+            let pos = set_position_of(w, pgm, heap, set, args[1], hash, loc)?;
+            pgm.bool_alloc(pos.is_some())
+        }
+
+        BuiltinFun::SetRemove => {
+            // Returns whether the element was in the set. The other elements keep their insertion
+            // order.
+            debug_assert_eq!(args.len(), 2);
+            let set = args[0];
+            debug_assert_eq!(heap.tag(pgm, set), SET_TYPE_TAG);
+            let hash = value_hash(w, pgm, heap, args[1], loc)?;
+            match set_position_of(w, pgm, heap, set, args[1], hash, loc)? {
+                None => pgm.false_alloc,
+                Some(pos) => {
+                    heap.set_remove(set, pos);
+                    pgm.true_alloc
+                }
+            }
+        }
+
+        BuiltinFun::SetLen => {
+            debug_assert_eq!(args.len(), 1);
+            let set = args[0];
+            debug_assert_eq!(heap.tag(pgm, set), SET_TYPE_TAG);
+            heap.allocate_i32(heap[set + 1] as i32)
+        }
+
+        BuiltinFun::SetIter => {
+            // Iterates a copy of the elements, so updating the set while iterating does not
+            // affect the iteration.
+            debug_assert_eq!(args.len(), 1);
+            let set = args[0];
+            debug_assert_eq!(heap.tag(pgm, set), SET_TYPE_TAG);
+            let array = heap.set_elems_array(set);
+            let iter = heap.allocate(3);
+            heap[iter] = ARRAY_ITER_TYPE_TAG;
+            heap[iter + 1] = array;
+            heap[iter + 2] = 0; // index of the next element
+            iter
+        }
+
+        BuiltinFun::StrToStr => {
+            debug_assert_eq!(args.len(), 1);
+            debug_assert_eq!(heap.tag(pgm, args[0]), STR_TYPE_TAG);
+            args[0]
+        }
+
+        BuiltinFun::StrIsEmpty => {
+            debug_assert_eq!(args.len(), 1);
+            let str = args[0];
+            debug_assert_eq!(heap.tag(pgm, str), STR_TYPE_TAG);
+            pgm.bool_alloc(heap[str + 1] == 0)
+        }
+
+        BuiltinFun::StrByteLen => {
+            debug_assert_eq!(args.len(), 1);
+            let str = args[0];
+            debug_assert_eq!(heap.tag(pgm, str), STR_TYPE_TAG);
+            heap.allocate_i32(heap[str + 1] as i32)
+        }
+
+        BuiltinFun::StrByteAt => {
+            debug_assert_eq!(args.len(), 2);
+            let str = args[0];
+            let idx = args[1];
+            debug_assert_eq!(heap.tag(pgm, str), STR_TYPE_TAG);
+            debug_assert_eq!(heap.tag(pgm, idx), I32_TYPE_TAG);
+            let idx = heap[idx + 1] as i32;
+            let bytes = heap.str_bytes(str);
+            let idx =
+                resolve_index(idx, bytes.len() as u64).map_err(|idx| RunError::OutOfBounds {
+                    msg: format!(
+                        "Byte index {} out of bounds, byte length = {}",
+                        idx,
+                        bytes.len()
+                    ),
+                    loc: loc.clone(),
+                })?;
+            heap.allocate_i32(i32::from(bytes[idx as usize]))
+        }
+
+        BuiltinFun::StrIsValidUtf8 => {
+            debug_assert_eq!(args.len(), 1);
+            let str = args[0];
+            debug_assert_eq!(heap.tag(pgm, str), STR_TYPE_TAG);
+            pgm.bool_alloc(std::str::from_utf8(heap.str_bytes(str)).is_ok())
+        }
+
+        BuiltinFun::StrCharLen => {
+            debug_assert_eq!(args.len(), 1);
+            let str = args[0];
+            debug_assert_eq!(heap.tag(pgm, str), STR_TYPE_TAG);
+            let len = if heap.str_is_ascii(str) {
+                heap[str + 1] as usize
+            } else {
+                decode_chars(heap.str_bytes(str)).count()
+            };
+            heap.allocate_i32(len as i32)
+        }
+
+        BuiltinFun::StrCharAt => {
+            // Decodes the string from the start, so takes time linear in the length of the
+            // string, unless the string is ASCII. Use `chars` to iterate the characters.
+            debug_assert_eq!(args.len(), 2);
+            let str = args[0];
+            let idx = args[1];
+            debug_assert_eq!(heap.tag(pgm, str), STR_TYPE_TAG);
+            debug_assert_eq!(heap.tag(pgm, idx), I32_TYPE_TAG);
+            let idx = heap[idx + 1] as i32;
+            let ascii = heap.str_is_ascii(str);
+            let bytes = heap.str_bytes(str);
+            let char_len = if ascii {
+                bytes.len()
+            } else {
+                decode_chars(bytes).count()
+            };
+            let idx = resolve_index(idx, char_len as u64).map_err(|idx| RunError::OutOfBounds {
+                msg: format!(
+                    "Character index {} out of bounds, character length = {}",
+                    idx, char_len
+                ),
+                loc: loc.clone(),
+            })?;
+            let char = if ascii {
+                char::from(bytes[idx as usize])
+            } else {
+                decode_chars(bytes).nth(idx as usize).unwrap()
+            };
+            heap.allocate_char(char)
+        }
+
+        BuiltinFun::StrChars => {
+            debug_assert_eq!(args.len(), 1);
+            let str = args[0];
+            debug_assert_eq!(heap.tag(pgm, str), STR_TYPE_TAG);
+            let iter = heap.allocate(3);
+            heap[iter] = STR_CHARS_TYPE_TAG;
+            heap[iter + 1] = str;
+            heap[iter + 2] = 0; // byte index of the next character
+            iter
+        }
+
+        BuiltinFun::StrCharsIter => {
+            debug_assert_eq!(args.len(), 1);
+            debug_assert_eq!(heap.tag(pgm, args[0]), STR_CHARS_TYPE_TAG);
+            args[0]
+        }
+
+        BuiltinFun::StrCharsNext => {
+            debug_assert_eq!(args.len(), 1);
+            let iter = args[0];
+            debug_assert_eq!(heap.tag(pgm, iter), STR_CHARS_TYPE_TAG);
+            let bytes = heap.str_bytes(heap[iter + 1]);
+            let idx = heap[iter + 2] as usize;
+            match decode_char(&bytes[idx..]) {
+                None => none(pgm),
+                Some((char, len)) => {
+                    heap[iter + 2] = (idx + len) as u64;
+                    let char = heap.allocate_char(char);
+                    allocate_some(pgm, heap, char)
+                }
+            }
+        }
+
+        BuiltinFun::StrLen => {
             debug_assert_eq!(args.len(), 1);
+            let str = args[0];
+            debug_assert_eq!(heap.tag(pgm, str), STR_TYPE_TAG);
+            heap.allocate_i32(heap[str + 1] as i32)
+        }
 
-            let cap = args[0];
-            debug_assert_eq!(heap[cap], I32_TYPE_TAG);
-            let cap = heap[cap + 1];
-            heap.allocate_array(cap)
+        BuiltinFun::StrStartsWith | BuiltinFun::StrEndsWith => {
+            debug_assert_eq!(args.len(), 2);
+            let str = args[0];
+            debug_assert_eq!(heap.tag(pgm, str), STR_TYPE_TAG);
+            let str = heap.str_bytes(str);
+            let affix = str_arg_bytes(heap, args[1], "argument", loc)?;
+            pgm.bool_alloc(match fun {
+                BuiltinFun::StrStartsWith => str.starts_with(affix),
+                _ => str.ends_with(affix),
+            })
         }
 
-        BuiltinFun::ArrayLen => {
+        BuiltinFun::StrLines => {
+            // Same as Rust's `str::lines`: lines end with `\n` or `\r\n`, the line ending is
+            // not included in the line, and the last line ending is optional.
             debug_assert_eq!(args.len(), 1);
-
-            let array = args[0];
-            debug_assert_eq!(heap[array], ARRAY_TYPE_TAG);
-            let len = heap[array + 1];
-            heap.allocate_i32(len as i32)
+            let str = args[0];
+            debug_assert_eq!(heap.tag(pgm, str), STR_TYPE_TAG);
+            let lines = line_ranges(heap.str_bytes(str));
+            let array = heap.allocate_array(lines.len() as u64);
+            for (i, (start, end)) in lines.into_iter().enumerate() {
+                heap[array + 2 + i as u64] = heap.allocate_str_view(str, start, end).unwrap();
+            }
+            array
         }
 
-        BuiltinFun::ArraySet => {
+        BuiltinFun::StrReplace => {
             debug_assert_eq!(args.len(), 3);
-
-            let array = args[0];
-            debug_assert_eq!(heap[array], ARRAY_TYPE_TAG);
-
-            let idx = args[1];
-            debug_assert_eq!(heap[idx], I32_TYPE_TAG);
-
-            let elem = args[2];
-
-            let array_len = heap[array + 1];
-            let idx = heap[idx + 1];
-            assert!(idx < array_len);
-
-            heap[array + 2 + idx] = elem;
-            elem
+            let str = args[0];
+            debug_assert_eq!(heap.tag(pgm, str), STR_TYPE_TAG);
+            let replaced = replace_bytes(
+                heap.str_bytes(str),
+                str_arg_bytes(heap, args[1], "replaced string", loc)?,
+                str_arg_bytes(heap, args[2], "replacement", loc)?,
+            );
+            heap.allocate_str(&replaced)
         }
 
-        BuiltinFun::ArrayGet => {
+        BuiltinFun::StrRepeat => {
             debug_assert_eq!(args.len(), 2);
+            let str = args[0];
+            debug_assert_eq!(heap.tag(pgm, str), STR_TYPE_TAG);
+            debug_assert_eq!(heap.tag(pgm, args[1]), I32_TYPE_TAG);
+            let n = heap.i32_value(args[1]);
+            if n < 0 {
+                return Err(RunError::InvalidArgument {
+                    msg: format!("Str.repeat count is negative: {}", n),
+                    loc: loc.clone(),
+                }
+                .into());
+            }
+            let repeated = heap.str_bytes(str).repeat(n as usize);
+            heap.allocate_str(&repeated)
+        }
 
-            let array = args[0];
-            debug_assert_eq!(heap[array], ARRAY_TYPE_TAG);
+        BuiltinFun::StrBuilderNew => {
+            debug_assert_eq!(args.len(), 0);
+            heap.allocate_str_builder(16)
+        }
 
-            let idx = args[1];
-            debug_assert_eq!(heap[idx], I32_TYPE_TAG);
+        BuiltinFun::StrBuilderWithCapacity => {
+            debug_assert_eq!(args.len(), 1);
+            debug_assert_eq!(heap.tag(pgm, args[0]), I32_TYPE_TAG);
+            let cap = heap.i32_value(args[0]);
+            if cap < 0 {
+                return Err(RunError::InvalidArgument {
+                    msg: format!("StrBuilder.withCapacity capacity is negative: {}", cap),
+                    loc: loc.clone(),
+                }
+                .into());
+            }
+            heap.allocate_str_builder(cap as u64)
+        }
 
-            let array_len = heap[array + 1];
-            let idx = heap[idx + 1];
-            assert!(idx < array_len);
+        BuiltinFun::StrBuilderAppend => {
+            // Appends a `Str` or `StrView`.
+            debug_assert_eq!(args.len(), 2);
+            let builder = args[0];
+            debug_assert_eq!(heap.tag(pgm, builder), STR_BUILDER_TYPE_TAG);
+            let bytes = str_arg_bytes(heap, args[1], "StrBuilder.append argument", loc)?.to_vec();
+            heap.str_builder_append(builder, &bytes);
+            0
+        }
 
-            let value = heap[array + 2 + idx];
-            if value == 0 {
-                panic!("Reading uninitialized array element");
-            }
-            value
+        BuiltinFun::StrBuilderLen => {
+            // Length in bytes, like `Str.len`.
+            debug_assert_eq!(args.len(), 1);
+            let builder = args[0];
+            debug_assert_eq!(heap.tag(pgm, builder), STR_BUILDER_TYPE_TAG);
+            heap.allocate_i32(heap[builder + 1] as i32)
         }
 
-        BuiltinFun::StrLen => {
+        BuiltinFun::StrBuilderToStr => {
             debug_assert_eq!(args.len(), 1);
-            let str = args[0];
-            debug_assert_eq!(heap[str], STR_TYPE_TAG);
-            heap.allocate_i32(heap[str + 1] as i32)
+            let builder = args[0];
+            debug_assert_eq!(heap.tag(pgm, builder), STR_BUILDER_TYPE_TAG);
+            let bytes = heap.str_builder_bytes(builder).to_vec();
+            heap.allocate_str(&bytes)
         }
 
         BuiltinFun::StrEq => {
@@ -186,27 +1269,36 @@ pub fn call_builtin_fun<W: Write>(
             let str1 = args[0];
             let str2 = args[1];
 
-            debug_assert_eq!(heap[str1], STR_TYPE_TAG);
-            debug_assert_eq!(heap[str2], STR_TYPE_TAG);
+            debug_assert_eq!(heap.tag(pgm, str1), STR_TYPE_TAG);
+            debug_assert_eq!(heap.tag(pgm, str2), STR_TYPE_TAG);
 
             let str1_len = heap[str1 + 1];
             let str2_len = heap[str2 + 1];
 
             if str1_len != str2_len {
-                return pgm.bool_alloc(false);
+                return Ok(pgm.bool_alloc(false));
             }
 
             let len_words = str1_len.div_ceil(8);
 
             for i in 0..len_words {
                 if heap[str1 + 2 + i] != heap[str2 + 2 + i] {
-                    return pgm.bool_alloc(false);
+                    return Ok(pgm.bool_alloc(false));
                 }
             }
 
             pgm.bool_alloc(true)
         }
 
+        BuiltinFun::StrCmp => {
+            // Compares the bytes, which orders valid UTF-8 strings by code points.
+            debug_assert_eq!(args.len(), 2);
+            let (str1, str2) = (args[0], args[1]);
+            debug_assert_eq!(heap.tag(pgm, str1), STR_TYPE_TAG);
+            debug_assert_eq!(heap.tag(pgm, str2), STR_TYPE_TAG);
+            ordering_value(pgm, heap.str_bytes(str1).cmp(heap.str_bytes(str2)))
+        }
+
         BuiltinFun::StrSubstr => {
             debug_assert_eq!(args.len(), 3);
 
@@ -215,27 +1307,121 @@ pub fn call_builtin_fun<W: Write>(
             let byte_start = args[1];
             let byte_end = args[2];
 
-            debug_assert_eq!(heap[str], STR_TYPE_TAG);
-            debug_assert_eq!(heap[byte_start], I32_TYPE_TAG);
-            debug_assert_eq!(heap[byte_end], I32_TYPE_TAG);
+            debug_assert_eq!(heap.tag(pgm, str), STR_TYPE_TAG);
+            debug_assert_eq!(heap.tag(pgm, byte_start), I32_TYPE_TAG);
+            debug_assert_eq!(heap.tag(pgm, byte_end), I32_TYPE_TAG);
 
             let str_len = heap[str + 1];
-            let byte_start = heap[byte_start + 1];
-            let byte_end = heap[byte_end + 1];
-
-            if byte_start > str_len {
-                panic!("String.substr start index out of bounds");
+            let byte_start = heap.i32_value(byte_start);
+            let byte_end = heap.i32_value(byte_end);
+
+            if byte_start < 0 || byte_start as u64 > str_len {
+                return Err(RunError::OutOfBounds {
+                    msg: format!(
+                        "Str.substr start byte index {} out of bounds, byte length = {}",
+                        byte_start, str_len
+                    ),
+                    loc: loc.clone(),
+                }
+                .into());
             }
 
-            if byte_end > str_len {
-                panic!("String.substr end index out of bounds");
+            if byte_end < 0 || byte_end as u64 > str_len {
+                return Err(RunError::OutOfBounds {
+                    msg: format!(
+                        "Str.substr end byte index {} out of bounds, byte length = {}",
+                        byte_end, str_len
+                    ),
+                    loc: loc.clone(),
+                }
+                .into());
             }
 
             if byte_start > byte_end {
-                panic!("String.substr start index larger than end index");
+                return Err(RunError::OutOfBounds {
+                    msg: format!(
+                        "Str.substr start index {} larger than end index {}",
+                        byte_start, byte_end
+                    ),
+                    loc: loc.clone(),
+                }
+                .into());
             }
 
-            heap.allocate_str_view(str, byte_start, byte_end)
+            // Bounds are checked above.
+            heap.allocate_str_view(str, byte_start as u64, byte_end as u64)
+                .unwrap()
+        }
+
+        BuiltinFun::BoolAnd | BuiltinFun::BoolOr | BuiltinFun::BoolEq => {
+            debug_assert_eq!(args.len(), 2);
+
+            let b1 = args[0];
+            let b2 = args[1];
+
+            debug_assert!(b1 == pgm.true_alloc || b1 == pgm.false_alloc);
+            debug_assert!(b2 == pgm.true_alloc || b2 == pgm.false_alloc);
+
+            let b1 = b1 == pgm.true_alloc;
+            let b2 = b2 == pgm.true_alloc;
+
+            pgm.bool_alloc(match fun {
+                BuiltinFun::BoolAnd => b1 && b2,
+                BuiltinFun::BoolOr => b1 || b2,
+                BuiltinFun::BoolEq => b1 == b2,
+                _ => unreachable!(),
+            })
+        }
+
+        BuiltinFun::BoolToStr => {
+            debug_assert_eq!(args.len(), 1);
+
+            let b = args[0];
+            debug_assert!(b == pgm.true_alloc || b == pgm.false_alloc);
+
+            heap.allocate_str(if b == pgm.true_alloc {
+                b"True"
+            } else {
+                b"False"
+            })
+        }
+
+        BuiltinFun::I32BitAnd
+        | BuiltinFun::I32BitOr
+        | BuiltinFun::I32BitXor
+        | BuiltinFun::I32Shl
+        | BuiltinFun::I32Shr => {
+            debug_assert_eq!(args.len(), 2);
+
+            let i1 = args[0];
+            let i2 = args[1];
+
+            debug_assert_eq!(heap.tag(pgm, i1), I32_TYPE_TAG);
+            debug_assert_eq!(heap.tag(pgm, i2), I32_TYPE_TAG);
+
+            let i1 = heap[i1 + 1] as i32;
+            let i2 = heap[i2 + 1] as i32;
+
+            // Shift amounts are masked to the lower 5 bits. `shr` is an arithmetic shift.
+            let result = match fun {
+                BuiltinFun::I32BitAnd => i1 & i2,
+                BuiltinFun::I32BitOr => i1 | i2,
+                BuiltinFun::I32BitXor => i1 ^ i2,
+                BuiltinFun::I32Shl => i1.wrapping_shl(i2 as u32),
+                BuiltinFun::I32Shr => i1.wrapping_shr(i2 as u32),
+                _ => unreachable!(),
+            };
+
+            heap.allocate_i32(result)
+        }
+
+        BuiltinFun::I32BitNot => {
+            debug_assert_eq!(args.len(), 1);
+
+            let i = args[0];
+            debug_assert_eq!(heap.tag(pgm, i), I32_TYPE_TAG);
+
+            heap.allocate_i32(!(heap[i + 1] as i32))
         }
 
         BuiltinFun::I32Add => {
@@ -244,13 +1430,41 @@ pub fn call_builtin_fun<W: Write>(
             let i1 = args[0];
             let i2 = args[1];
 
-            debug_assert_eq!(heap[i1], I32_TYPE_TAG);
-            debug_assert_eq!(heap[i2], I32_TYPE_TAG);
+            debug_assert_eq!(heap.tag(pgm, i1), I32_TYPE_TAG);
+            debug_assert_eq!(heap.tag(pgm, i2), I32_TYPE_TAG);
 
             let i1 = heap[i1 + 1];
             let i2 = heap[i2 + 1];
 
-            heap.allocate_i32((i1 as i32) + (i2 as i32))
+            let result = (i1 as i32)
+                .checked_add(i2 as i32)
+                .ok_or_else(|| RunError::IntOverflow { loc: loc.clone() })?;
+            heap.allocate_i32(result)
+        }
+
+        BuiltinFun::I32AddChecked | BuiltinFun::I32AddSaturating | BuiltinFun::I32AddWrapping => {
+            debug_assert_eq!(args.len(), 2);
+
+            let i1 = args[0];
+            let i2 = args[1];
+
+            debug_assert_eq!(heap.tag(pgm, i1), I32_TYPE_TAG);
+            debug_assert_eq!(heap.tag(pgm, i2), I32_TYPE_TAG);
+
+            let i1 = heap[i1 + 1] as i32;
+            let i2 = heap[i2 + 1] as i32;
+
+            match fun {
+                BuiltinFun::I32AddChecked => match i1.checked_add(i2) {
+                    Some(result) => {
+                        let result = heap.allocate_i32(result);
+                        allocate_some(pgm, heap, result)
+                    }
+                    None => none(pgm),
+                },
+                BuiltinFun::I32AddSaturating => heap.allocate_i32(i1.saturating_add(i2)),
+                _ => heap.allocate_i32(i1.wrapping_add(i2)),
+            }
         }
 
         BuiltinFun::I32Sub => {
@@ -259,13 +1473,43 @@ pub fn call_builtin_fun<W: Write>(
             let i1 = args[0];
             let i2 = args[1];
 
-            debug_assert_eq!(heap[i1], I32_TYPE_TAG);
-            debug_assert_eq!(heap[i2], I32_TYPE_TAG);
+            debug_assert_eq!(heap.tag(pgm, i1), I32_TYPE_TAG);
+            debug_assert_eq!(heap.tag(pgm, i2), I32_TYPE_TAG);
 
             let i1 = heap[i1 + 1];
             let i2 = heap[i2 + 1];
 
-            heap.allocate_i32((i1 as i32) - (i2 as i32))
+            let result = (i1 as i32)
+                .checked_sub(i2 as i32)
+                .ok_or_else(|| RunError::IntOverflow { loc: loc.clone() })?;
+            heap.allocate_i32(result)
+        }
+
+        BuiltinFun::I32Div | BuiltinFun::I32Mod => {
+            debug_assert_eq!(args.len(), 2);
+
+            let i1 = args[0];
+            let i2 = args[1];
+
+            debug_assert_eq!(heap.tag(pgm, i1), I32_TYPE_TAG);
+            debug_assert_eq!(heap.tag(pgm, i2), I32_TYPE_TAG);
+
+            let i1 = heap[i1 + 1] as i32;
+            let i2 = heap[i2 + 1] as i32;
+
+            if i2 == 0 {
+                return Err(RunError::DivByZero { loc: loc.clone() }.into());
+            }
+
+            // Division rounds toward zero, the result of modulo has the sign of the dividend.
+            // `I32.MIN / -1` wraps.
+            let result = if *fun == BuiltinFun::I32Div {
+                i1.wrapping_div(i2)
+            } else {
+                i1.wrapping_rem(i2)
+            };
+
+            heap.allocate_i32(result)
         }
 
         BuiltinFun::I32Mul => {
@@ -274,13 +1518,16 @@ pub fn call_builtin_fun<W: Write>(
             let i1 = args[0];
             let i2 = args[1];
 
-            debug_assert_eq!(heap[i1], I32_TYPE_TAG);
-            debug_assert_eq!(heap[i2], I32_TYPE_TAG);
+            debug_assert_eq!(heap.tag(pgm, i1), I32_TYPE_TAG);
+            debug_assert_eq!(heap.tag(pgm, i2), I32_TYPE_TAG);
 
             let i1 = heap[i1 + 1];
             let i2 = heap[i2 + 1];
 
-            heap.allocate_i32((i1 as i32) * (i2 as i32))
+            let result = (i1 as i32)
+                .checked_mul(i2 as i32)
+                .ok_or_else(|| RunError::IntOverflow { loc: loc.clone() })?;
+            heap.allocate_i32(result)
         }
 
         BuiltinFun::I32Cmp => {
@@ -289,23 +1536,13 @@ pub fn call_builtin_fun<W: Write>(
             let i1 = args[0];
             let i2 = args[1];
 
-            debug_assert_eq!(heap[i1], I32_TYPE_TAG);
-            debug_assert_eq!(heap[i2], I32_TYPE_TAG);
-
-            let ordering_ty_con = pgm.ty_cons.get("Ordering").unwrap_or_else(|| {
-                panic!("__cmp was called, but the Ordering type is not defined")
-            });
+            debug_assert_eq!(heap.tag(pgm, i1), I32_TYPE_TAG);
+            debug_assert_eq!(heap.tag(pgm, i2), I32_TYPE_TAG);
 
             let i1 = heap[i1 + 1];
             let i2 = heap[i2 + 1];
 
-            let constr_name = match i1.cmp(&i2) {
-                Ordering::Less => "Less",
-                Ordering::Equal => "Equal",
-                Ordering::Greater => "Greater",
-            };
-
-            heap.allocate_tag(ordering_ty_con.get_constr_with_tag(constr_name).0)
+            ordering_value(pgm, (i1 as i32).cmp(&(i2 as i32)))
         }
 
         BuiltinFun::I32Eq => {
@@ -314,8 +1551,8 @@ pub fn call_builtin_fun<W: Write>(
             let i1 = args[0];
             let i2 = args[1];
 
-            debug_assert_eq!(heap[i1], I32_TYPE_TAG, "{}", LocDisplay(loc));
-            debug_assert_eq!(heap[i2], I32_TYPE_TAG, "{}", LocDisplay(loc));
+            debug_assert_eq!(heap.tag(pgm, i1), I32_TYPE_TAG, "{}", LocDisplay(loc));
+            debug_assert_eq!(heap.tag(pgm, i2), I32_TYPE_TAG, "{}", LocDisplay(loc));
 
             let i1 = heap[i1 + 1];
             let i2 = heap[i2 + 1];
@@ -323,12 +1560,76 @@ pub fn call_builtin_fun<W: Write>(
             pgm.bool_alloc(i1 == i2)
         }
 
+        BuiltinFun::CharCmp => {
+            debug_assert_eq!(args.len(), 2);
+            let c1 = heap.char_value(args[0]);
+            let c2 = heap.char_value(args[1]);
+            ordering_value(pgm, c1.cmp(&c2))
+        }
+
+        BuiltinFun::CharEq => {
+            debug_assert_eq!(args.len(), 2);
+            pgm.bool_alloc(heap.char_value(args[0]) == heap.char_value(args[1]))
+        }
+
+        BuiltinFun::CharToStr => {
+            debug_assert_eq!(args.len(), 1);
+            let char = heap.char_value(args[0]);
+            heap.allocate_str(char.to_string().as_bytes())
+        }
+
+        BuiltinFun::F64Add | BuiltinFun::F64Sub | BuiltinFun::F64Mul | BuiltinFun::F64Div => {
+            debug_assert_eq!(args.len(), 2);
+            let f1 = heap.f64_value(args[0]);
+            let f2 = heap.f64_value(args[1]);
+            // Division by zero follows IEEE 754: gives infinity or NaN.
+            let result = match fun {
+                BuiltinFun::F64Add => f1 + f2,
+                BuiltinFun::F64Sub => f1 - f2,
+                BuiltinFun::F64Mul => f1 * f2,
+                _ => f1 / f2,
+            };
+            heap.allocate_f64(result)
+        }
+
+        BuiltinFun::F64Cmp => {
+            debug_assert_eq!(args.len(), 2);
+            let f1 = heap.f64_value(args[0]);
+            let f2 = heap.f64_value(args[1]);
+            ordering_value(pgm, f1.total_cmp(&f2))
+        }
+
+        BuiltinFun::F64Eq => {
+            debug_assert_eq!(args.len(), 2);
+            let f1 = heap.f64_value(args[0]);
+            let f2 = heap.f64_value(args[1]);
+            pgm.bool_alloc(f1.total_cmp(&f2) == Ordering::Equal)
+        }
+
+        BuiltinFun::F64ToI32 => {
+            // Truncates towards zero. Values out of range saturate to the `I32` bounds, NaN is 0.
+            debug_assert_eq!(args.len(), 1);
+            let f = heap.f64_value(args[0]);
+            heap.allocate_i32(f as i32)
+        }
+
+        BuiltinFun::F64ToStr => {
+            debug_assert_eq!(args.len(), 1);
+            let f = heap.f64_value(args[0]);
+            heap.allocate_str(format!("{:?}", f).as_bytes())
+        }
+
+        BuiltinFun::I32ToF64 => {
+            debug_assert_eq!(args.len(), 1);
+            let i = args[0];
+            debug_assert_eq!(heap.tag(pgm, i), I32_TYPE_TAG);
+            heap.allocate_f64(f64::from(heap[i + 1] as i32))
+        }
+
         BuiltinFun::I32ToStr => {
             debug_assert_eq!(args.len(), 1);
-            let obj = args[0];
-            debug_assert_eq!(heap[obj], I32_TYPE_TAG);
-            let i = heap[obj + 1];
-            heap.allocate_str(format!("{}", i as i32).as_bytes())
+            let i = heap.i32_value(args[0]);
+            heap.allocate_str(i.to_string().as_bytes())
         }
 
         BuiltinFun::StrViewEq => {
@@ -337,8 +1638,8 @@ pub fn call_builtin_fun<W: Write>(
             let s1 = args[0];
             let s2 = args[1];
 
-            debug_assert_eq!(heap[s1], STR_VIEW_TYPE_TAG, "{:?}", loc);
-            debug_assert_eq!(heap[s2], STR_VIEW_TYPE_TAG, "{:?}", loc);
+            debug_assert_eq!(heap.tag(pgm, s1), STR_VIEW_TYPE_TAG, "{:?}", loc);
+            debug_assert_eq!(heap.tag(pgm, s2), STR_VIEW_TYPE_TAG, "{:?}", loc);
 
             let s1_start = heap[s1 + 1];
             let s1_end = heap[s1 + 2];
@@ -347,7 +1648,7 @@ pub fn call_builtin_fun<W: Write>(
             let s2_end = heap[s2 + 2];
 
             if s1_end - s1_start != s2_end - s2_start {
-                return pgm.bool_alloc(false);
+                return Ok(pgm.bool_alloc(false));
             }
 
             let s1_payload_byte_addr = {
@@ -375,44 +1676,58 @@ pub fn call_builtin_fun<W: Write>(
             let start = args[1];
             let end = args[2];
 
-            debug_assert_eq!(heap[s], STR_VIEW_TYPE_TAG, "{:?}", loc);
-            debug_assert_eq!(heap[start], I32_TYPE_TAG, "{:?}", loc);
-            debug_assert_eq!(heap[end], I32_TYPE_TAG, "{:?}", loc);
+            debug_assert_eq!(heap.tag(pgm, s), STR_VIEW_TYPE_TAG, "{:?}", loc);
+            debug_assert_eq!(heap.tag(pgm, start), I32_TYPE_TAG, "{:?}", loc);
+            debug_assert_eq!(heap.tag(pgm, end), I32_TYPE_TAG, "{:?}", loc);
 
-            let start = heap[start + 1];
-            let end = heap[end + 1];
+            let start = heap.i32_value(start);
+            let end = heap.i32_value(end);
 
             let view_len = heap[s + 2] - heap[s + 1];
 
-            if start > view_len {
-                panic!(
-                    "StrView.substr start index {} is larger than view length {}",
-                    start, view_len
-                );
+            if start >= 0 && start as u64 > view_len {
+                return Err(RunError::OutOfBounds {
+                    msg: format!(
+                        "StrView.substr start index {} is larger than view length {}",
+                        start, view_len
+                    ),
+                    loc: loc.clone(),
+                }
+                .into());
             }
 
-            if end > view_len {
-                panic!(
-                    "{}: StrView.substr({}, {}) out of bounds, view length = {}",
-                    LocDisplay(loc),
-                    start,
-                    end,
-                    view_len
-                );
+            if start < 0 || end < 0 || end as u64 > view_len {
+                return Err(RunError::OutOfBounds {
+                    msg: format!(
+                        "StrView.substr({}, {}) out of bounds, view length = {}",
+                        start, end, view_len
+                    ),
+                    loc: loc.clone(),
+                }
+                .into());
             }
 
             if start > end {
-                panic!("StrView.substr start index larger than end index");
+                return Err(RunError::OutOfBounds {
+                    msg: format!(
+                        "StrView.substr start index {} larger than end index {}",
+                        start, end
+                    ),
+                    loc: loc.clone(),
+                }
+                .into());
             }
 
-            heap.allocate_str_view(heap[s + 3], start + heap[s + 1], end + heap[s + 1])
+            // Bounds are checked above.
+            heap.allocate_str_view_from_str_view(s, start as u64, end as u64)
+                .unwrap()
         }
 
         BuiltinFun::StrViewLen => {
             debug_assert_eq!(args.len(), 1);
 
             let s = args[0];
-            debug_assert_eq!(heap[s], STR_VIEW_TYPE_TAG, "{:?}", loc);
+            debug_assert_eq!(heap.tag(pgm, s), STR_VIEW_TYPE_TAG, "{:?}", loc);
 
             let len = heap[s + 2] - heap[s + 1];
             heap.allocate_i32(len as i32)
@@ -424,8 +1739,8 @@ pub fn call_builtin_fun<W: Write>(
             let s1 = args[0];
             let s2 = args[1];
 
-            debug_assert_eq!(heap[s1], STR_VIEW_TYPE_TAG);
-            debug_assert_eq!(heap[s2], STR_TYPE_TAG);
+            debug_assert_eq!(heap.tag(pgm, s1), STR_VIEW_TYPE_TAG);
+            debug_assert_eq!(heap.tag(pgm, s2), STR_TYPE_TAG);
 
             let s1_start = heap[s1 + 1];
             let s1_end = heap[s1 + 2];
@@ -433,7 +1748,7 @@ pub fn call_builtin_fun<W: Write>(
             let s2_len = heap[s2 + 1];
 
             if s1_len < s2_len {
-                return pgm.bool_alloc(false);
+                return Ok(pgm.bool_alloc(false));
             }
 
             let s1_payload_byte_addr = {
@@ -457,7 +1772,7 @@ pub fn call_builtin_fun<W: Write>(
         BuiltinFun::StrViewIsEmpty => {
             debug_assert_eq!(args.len(), 1);
             let s = args[0];
-            debug_assert_eq!(heap[s], STR_VIEW_TYPE_TAG);
+            debug_assert_eq!(heap.tag(pgm, s), STR_VIEW_TYPE_TAG);
 
             let s_start = heap[s + 1];
             let s_end = heap[s + 2];
@@ -468,9 +1783,451 @@ pub fn call_builtin_fun<W: Write>(
         BuiltinFun::StrViewToStr => {
             debug_assert_eq!(args.len(), 1);
             let s = args[0];
-            debug_assert_eq!(heap[s], STR_VIEW_TYPE_TAG);
+            debug_assert_eq!(heap.tag(pgm, s), STR_VIEW_TYPE_TAG);
             let str_view_bytes = heap.str_view_bytes(s).to_vec();
             heap.allocate_str(&str_view_bytes)
         }
+
+        BuiltinFun::TreeMapNew => {
+            debug_assert_eq!(args.len(), 0);
+            heap.allocate_tree_map()
+        }
+
+        BuiltinFun::TreeMapInsert => {
+            // Returns the old value of the key, or `None` when the key was not in the map.
+            debug_assert_eq!(args.len(), 3);
+            let map = args[0];
+            debug_assert_eq!(heap.tag(pgm, map), TREE_MAP_TYPE_TAG);
+            match tree_map_search(w, pgm, heap, map, args[1], loc)? {
+                Ok(idx) => {
+                    let values = heap[map + 3];
+                    let old = heap[values + 2 + idx];
+                    heap[values + 2 + idx] = args[2];
+                    allocate_some(pgm, heap, old)
+                }
+                Err(idx) => {
+                    let len = heap[map + 1];
+                    let (mut keys, mut values) = (heap[map + 2], heap[map + 3]);
+                    if len == heap[keys + 1] {
+                        let new_cap = (len * 2).max(4);
+                        let (new_keys, new_values) =
+                            (heap.allocate_array(new_cap), heap.allocate_array(new_cap));
+                        for i in 0..len {
+                            heap[new_keys + 2 + i] = heap[keys + 2 + i];
+                            heap[new_values + 2 + i] = heap[values + 2 + i];
+                        }
+                        heap[map + 2] = new_keys;
+                        heap[map + 3] = new_values;
+                        (keys, values) = (new_keys, new_values);
+                    }
+                    for i in (idx..len).rev() {
+                        heap[keys + 2 + i + 1] = heap[keys + 2 + i];
+                        heap[values + 2 + i + 1] = heap[values + 2 + i];
+                    }
+                    heap[keys + 2 + idx] = args[1];
+                    heap[values + 2 + idx] = args[2];
+                    heap[map + 1] = len + 1;
+                    none(pgm)
+                }
+            }
+        }
+
+        BuiltinFun::TreeMapGet => {
+            debug_assert_eq!(args.len(), 2);
+            let map = args[0];
+            debug_assert_eq!(heap.tag(pgm, map), TREE_MAP_TYPE_TAG);
+            match tree_map_search(w, pgm, heap, map, args[1], loc)? {
+                Ok(idx) => {
+                    let value = heap[heap[map + 3] + 2 + idx];
+                    allocate_some(pgm, heap, value)
+                }
+                Err(_) => none(pgm),
+            }
+        }
+
+        BuiltinFun::TreeMapRemove => {
+            // Returns the removed value, or `None` when the key was not in the map.
+            debug_assert_eq!(args.len(), 2);
+            let map = args[0];
+            debug_assert_eq!(heap.tag(pgm, map), TREE_MAP_TYPE_TAG);
+            match tree_map_search(w, pgm, heap, map, args[1], loc)? {
+                Err(_) => none(pgm),
+                Ok(idx) => {
+                    let len = heap[map + 1];
+                    let (keys, values) = (heap[map + 2], heap[map + 3]);
+                    let old = heap[values + 2 + idx];
+                    for i in idx..len - 1 {
+                        heap[keys + 2 + i] = heap[keys + 2 + i + 1];
+                        heap[values + 2 + i] = heap[values + 2 + i + 1];
+                    }
+                    heap[keys + 2 + len - 1] = 0;
+                    heap[values + 2 + len - 1] = 0;
+                    heap[map + 1] = len - 1;
+                    allocate_some(pgm, heap, old)
+                }
+            }
+        }
+
+        BuiltinFun::TreeMapLen => {
+            debug_assert_eq!(args.len(), 1);
+            let map = args[0];
+            debug_assert_eq!(heap.tag(pgm, map), TREE_MAP_TYPE_TAG);
+            heap.allocate_i32(heap[map + 1] as i32)
+        }
+
+        BuiltinFun::TreeMapIter => {
+            // Iterates a copy of the keys, like `Set.iter`.
+            debug_assert_eq!(args.len(), 1);
+            let map = args[0];
+            debug_assert_eq!(heap.tag(pgm, map), TREE_MAP_TYPE_TAG);
+            let array = heap.tree_map_keys_array(map);
+            let iter = heap.allocate(3);
+            heap[iter] = ARRAY_ITER_TYPE_TAG;
+            heap[iter + 1] = array;
+            heap[iter + 2] = 0; // index of the next element
+            iter
+        }
+    })
+}
+
+/// Pseudo-random number generator of the `random()` built-in: xorshift64* seeded with SplitMix64.
+///
+/// The sequence only depends on the seed, so runs with the same `RunOptions::random_seed` (and
+/// the same `randomSeed` calls) produce the same numbers on all platforms. Not suitable for
+/// cryptography.
+#[derive(Debug)]
+pub struct Rng {
+    /// Never 0, xorshift gets stuck at 0.
+    state: Cell<u64>,
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Rng::new(0)
+    }
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        let rng = Rng {
+            state: Cell::new(1),
+        };
+        rng.seed(seed);
+        rng
+    }
+
+    pub fn seed(&self, seed: u64) {
+        // SplitMix64 step, to spread the bits of small seeds. Only maps one seed to 0.
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        self.state.set(if z == 0 { 1 } else { z });
+    }
+
+    pub fn next_u64(&self) -> u64 {
+        let mut x = self.state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state.set(x);
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Uniformly distributed over `i32::MIN ..= i32::MAX`. Uses the high bits, the low bits of
+    /// xorshift64* are weaker.
+    pub fn next_i32(&self) -> i32 {
+        (self.next_u64() >> 32) as u32 as i32
+    }
+}
+
+/// Name of the type of a value, for `typeName`. Records are named by their shape, e.g. `(x, y)` or
+/// `(_, _)`.
+fn type_name(pgm: &Pgm, heap: &Heap, value: u64) -> String {
+    if value == 0 {
+        return "()".to_string();
+    }
+    match heap.tag(pgm, value) {
+        CONSTR_TYPE_TAG | TOP_FUN_TYPE_TAG | ASSOC_FUN_TYPE_TAG => "Fn".to_string(),
+        tag => match &pgm.cons_by_tag[tag as usize].info {
+            ConInfo::Named { ty_name, .. } => ty_name.to_string(),
+            ConInfo::Record {
+                shape: RecordShape::NamedFields { fields },
+            } => format!("({})", fields.join(", ")),
+            ConInfo::Record {
+                shape: RecordShape::UnnamedFields { arity },
+            } => format!("({})", vec!["_"; *arity as usize].join(", ")),
+        },
+    }
+}
+
+/// Index argument of `Array.get` and `Array.set`. Unlike array indexing expressions, negative
+/// indices are out of bounds.
+fn array_get_set_idx(heap: &Heap, array: u64, idx: u64, loc: &Loc) -> Result<u64, RunError> {
+    let array_len = heap[array + 1];
+    let idx = heap.i32_value(idx);
+    if idx < 0 || idx as u64 >= array_len {
+        return Err(RunError::OutOfBounds {
+            msg: format!("OOB array access, len = {}, index = {}", array_len, idx),
+            loc: loc.clone(),
+        });
+    }
+    Ok(idx as u64)
+}
+
+fn allocate_some(pgm: &Pgm, heap: &mut Heap, value: u64) -> u64 {
+    let some = heap.allocate(2);
+    heap[some] = constr_tag(pgm, "Option", "Some");
+    heap[some + 1] = value;
+    some
+}
+
+fn none(pgm: &Pgm) -> u64 {
+    pgm.cons_by_tag[constr_tag(pgm, "Option", "None") as usize]
+        .alloc
+        .unwrap()
+}
+
+/// Index of the first element of `array` equal to `value`, compared with `__eq`.
+fn array_index_of<W: Write>(
+    w: &mut W,
+    pgm: &Pgm,
+    heap: &mut Heap,
+    array: u64,
+    value: u64,
+    loc: &Loc,
+) -> Result<Option<u64>, EvalError> {
+    let len = heap[array + 1];
+    for i in 0..len {
+        let elem = array_elem(heap, array, i, loc)?;
+        if eq(w, pgm, heap, elem, value, loc)? {
+            return Ok(Some(i));
+        }
+    }
+    Ok(None)
+}
+
+/// Position of `value` in the elements of `set`, compared with `__eq`. Only the elements with
+/// the same hash as `value` are compared.
+fn set_position_of<W: Write>(
+    w: &mut W,
+    pgm: &Pgm,
+    heap: &mut Heap,
+    set: u64,
+    value: u64,
+    hash: u64,
+    loc: &Loc,
+) -> Result<Option<u64>, EvalError> {
+    for pos in heap.set_positions(set, hash) {
+        let elem = heap.set_elem(set, pos);
+        if eq(w, pgm, heap, elem, value, loc)? {
+            return Ok(Some(pos));
+        }
+    }
+    Ok(None)
+}
+
+/// Hash of a value, consistent with `eq`: values equal with `__eq` have the same hash.
+///
+/// Values of types with a user-defined `__eq` are hashed with the type's `__hash` method, which
+/// returns an `I32`. Without a `__hash` method all values of the type have the same hash, as
+/// `__eq` can consider any two values of the type equal.
+fn value_hash<W: Write>(
+    w: &mut W,
+    pgm: &Pgm,
+    heap: &mut Heap,
+    value: u64,
+    loc: &Loc,
+) -> Result<u64, EvalError> {
+    let mut hasher = DefaultHasher::new();
+    let user_eq = if value == 0 {
+        None
+    } else {
+        let methods = &pgm.associated_funs[heap.tag(pgm, value) as usize];
+        match methods.get("__eq") {
+            Some(
+                eq_fun @ Fun {
+                    kind: FunKind::Source(_),
+                    ..
+                },
+            ) => Some((eq_fun.idx, methods.contains_key("__hash"))),
+            _ => None,
+        }
+    };
+    match user_eq {
+        None => memo::hash_value(heap, value, &mut hasher),
+        Some((eq_fun_idx, has_hash)) => {
+            // The types with a user-defined `__eq` are told apart by the `__eq` function.
+            eq_fun_idx.hash(&mut hasher);
+            if has_hash {
+                let hash = call_method(w, pgm, heap, value, &"__hash".into(), vec![], loc)?;
+                heap.i32_value(hash).hash(&mut hasher);
+            }
+        }
+    }
+    Ok(hasher.finish())
+}
+
+/// Binary search for `key` in the keys of `map`, compared with `__cmp`. Like
+/// `slice::binary_search`, returns the index of the key as `Ok` when it's in the map, otherwise
+/// the index to insert it at as `Err`.
+fn tree_map_search<W: Write>(
+    w: &mut W,
+    pgm: &Pgm,
+    heap: &mut Heap,
+    map: u64,
+    key: u64,
+    loc: &Loc,
+) -> Result<Result<u64, u64>, EvalError> {
+    let (mut lo, mut hi) = (0, heap[map + 1]);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        let mid_key = heap[heap[map + 2] + 2 + mid];
+        match cmp(w, pgm, heap, mid_key, key, loc)? {
+            Ordering::Less => lo = mid + 1,
+            Ordering::Greater => hi = mid,
+            Ordering::Equal => return Ok(Ok(mid)),
+        }
+    }
+    Ok(Err(lo))
+}
+
+/// Stable merge sort with a comparison function that can fail.
+///
+/// `slice::sort_by` can't be used as the comparison calls back into the interpreter, which can
+/// return an error.
+fn merge_sort(
+    mut elems: Vec<u64>,
+    cmp: &mut dyn FnMut(u64, u64) -> Result<Ordering, EvalError>,
+) -> Result<Vec<u64>, EvalError> {
+    if elems.len() <= 1 {
+        return Ok(elems);
+    }
+
+    let right = elems.split_off(elems.len() / 2);
+    let left = merge_sort(elems, cmp)?;
+    let right = merge_sort(right, cmp)?;
+
+    let mut merged: Vec<u64> = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        // Take from the left on equal elements to keep the sort stable.
+        if cmp(*r, *l)? == Ordering::Less {
+            merged.push(right.next().unwrap());
+        } else {
+            merged.push(left.next().unwrap());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+/// Decode the first character of UTF-8 `bytes`, returning the character and its length in bytes.
+///
+/// Invalid UTF-8 is decoded as the replacement character `U+FFFD`, one byte at a time.
+fn decode_char(bytes: &[u8]) -> Option<(char, usize)> {
+    let first = *bytes.first()?;
+    if first.is_ascii() {
+        return Some((char::from(first), 1));
+    }
+    for len in 1..=bytes.len().min(4) {
+        if let Ok(str) = std::str::from_utf8(&bytes[..len]) {
+            return Some((str.chars().next().unwrap(), len));
+        }
+    }
+    Some((char::REPLACEMENT_CHARACTER, 1))
+}
+
+/// Characters of a string, with invalid UTF-8 sequences decoded as U+FFFD like `decode_char`.
+fn decode_chars(mut bytes: &[u8]) -> impl Iterator<Item = char> + '_ {
+    std::iter::from_fn(move || {
+        let (char, len) = decode_char(bytes)?;
+        bytes = &bytes[len..];
+        Some(char)
+    })
+}
+
+/// Bytes of a `Str` or `StrView` argument. `what` describes the argument in the error message
+/// when the value is not a string.
+fn str_arg_bytes<'a>(
+    heap: &'a Heap,
+    value: u64,
+    what: &str,
+    loc: &Loc,
+) -> Result<&'a [u8], RunError> {
+    match heap.header(value) {
+        STR_TYPE_TAG => Ok(heap.str_bytes(value)),
+        STR_VIEW_TYPE_TAG => Ok(heap.str_view_bytes(value)),
+        _ => Err(RunError::TypeError {
+            msg: format!("{} is not a string", what),
+            loc: loc.clone(),
+        }),
+    }
+}
+
+/// Byte ranges of the lines of a string, for `Str.lines`.
+fn line_ranges(str: &[u8]) -> Vec<(u64, u64)> {
+    let mut lines = vec![];
+    let mut start = 0;
+    for (i, byte) in str.iter().enumerate() {
+        if *byte == b'\n' {
+            let end = if i > start && str[i - 1] == b'\r' {
+                i - 1
+            } else {
+                i
+            };
+            lines.push((start as u64, end as u64));
+            start = i + 1;
+        }
+    }
+    if start < str.len() {
+        lines.push((start as u64, str.len() as u64));
+    }
+    lines
+}
+
+/// Replaces the occurrences of `from` in `str` with `to`. Occurrences are found from left to
+/// right and don't overlap: replacing `aa` in `aaa` replaces the first two `a`s.
+///
+/// An empty `from` matches at the start, after each character, so `to` is inserted between the
+/// characters and at both ends.
+fn replace_bytes(str: &[u8], from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut out: Vec<u8> = Vec::with_capacity(str.len());
+
+    if from.is_empty() {
+        let mut rest = str;
+        out.extend_from_slice(to);
+        while let Some((_, char_len)) = decode_char(rest) {
+            out.extend_from_slice(&rest[..char_len]);
+            out.extend_from_slice(to);
+            rest = &rest[char_len..];
+        }
+        return out;
+    }
+
+    let mut i = 0;
+    while i < str.len() {
+        if str[i..].starts_with(from) {
+            out.extend_from_slice(to);
+            i += from.len();
+        } else {
+            out.push(str[i]);
+            i += 1;
+        }
+    }
+    out
+}
+
+/// Read an initialized element of an array.
+fn array_elem(heap: &Heap, array: u64, idx: u64, loc: &Loc) -> Result<u64, RunError> {
+    let value = heap[array + 2 + idx];
+    if value == 0 {
+        return Err(RunError::UninitializedArrayElement {
+            idx,
+            loc: loc.clone(),
+        });
     }
+    Ok(value)
 }
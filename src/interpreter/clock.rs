@@ -0,0 +1,74 @@
+//! Time source of the `now()` built-in.
+
+use std::cell::Cell;
+use std::time::Instant;
+
+/// Where `now()` gets the time from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Clock {
+    /// Milliseconds since the program started, measured with a monotonic clock.
+    #[default]
+    Monotonic,
+
+    /// Starts at 0 and advances by `step_ms` milliseconds after each `now()` call, for
+    /// reproducible output in tests.
+    Fake { step_ms: i32 },
+
+    /// `now()` fails with `RunError::ClockDisabled`.
+    Disabled,
+}
+
+/// State of the clock of a program run.
+#[derive(Debug)]
+pub struct RunClock {
+    clock: Clock,
+
+    /// Start of the run, for `Clock::Monotonic`.
+    start: Option<Instant>,
+
+    /// Next time returned, for `Clock::Fake`.
+    fake_now: Cell<i32>,
+}
+
+/// A disabled clock. `run` replaces it with the clock in `RunOptions`.
+impl Default for RunClock {
+    fn default() -> Self {
+        RunClock::new(Clock::Disabled)
+    }
+}
+
+impl RunClock {
+    pub fn new(clock: Clock) -> Self {
+        RunClock {
+            clock,
+            // Only read the system clock when needed, it's not available on all platforms.
+            start: match clock {
+                Clock::Monotonic => Some(Instant::now()),
+                Clock::Fake { .. } | Clock::Disabled => None,
+            },
+            fake_now: Cell::new(0),
+        }
+    }
+
+    /// Restart the clock: `Clock::Monotonic` measures from now, `Clock::Fake` starts at 0 again.
+    pub fn reset(&mut self) {
+        *self = RunClock::new(self.clock);
+    }
+
+    /// Milliseconds since the start of the run, or `None` if the clock is disabled. Saturates at
+    /// `i32::MAX`, about 24 days.
+    pub fn now(&self) -> Option<i32> {
+        match self.clock {
+            Clock::Monotonic => {
+                let elapsed = self.start.unwrap().elapsed().as_millis();
+                Some(i32::try_from(elapsed).unwrap_or(i32::MAX))
+            }
+            Clock::Fake { step_ms } => {
+                let now = self.fake_now.get();
+                self.fake_now.set(now.saturating_add(step_ms));
+                Some(now)
+            }
+            Clock::Disabled => None,
+        }
+    }
+}
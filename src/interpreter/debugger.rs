@@ -0,0 +1,104 @@
+//! Breakpoints and single stepping.
+
+use crate::interpreter::*;
+
+/// Pause before executing the statements that start at `line` (1-based) of `module`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub module: SmolStr,
+    pub line: u32,
+}
+
+/// How to continue after a pause.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugAction {
+    /// Run until the next breakpoint.
+    Continue,
+
+    /// Pause again before the next statement.
+    Step,
+}
+
+/// Interpreter state at a pause, passed to the debugger callback.
+pub struct PauseState<'a> {
+    pgm: &'a Pgm,
+    heap: &'a Heap,
+    locals: &'a Map<SmolStr, u64>,
+
+    /// Location of the statement about to be executed.
+    pub loc: &'a Loc,
+}
+
+impl PauseState<'_> {
+    /// Local variables of the current function, sorted by name, with values rendered in source
+    /// syntax.
+    pub fn locals(&self) -> Vec<(SmolStr, String)> {
+        let mut locals: Vec<(SmolStr, String)> = self
+            .locals
+            .iter()
+            .map(|(var, value)| (var.clone(), value_to_string(self.pgm, self.heap, *value)))
+            .collect();
+        locals.sort();
+        locals
+    }
+}
+
+/// Breakpoints and the callback to call when the program pauses.
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+
+    /// Whether to pause before the next statement.
+    stepping: bool,
+
+    callback: Box<dyn FnMut(&PauseState) -> DebugAction>,
+}
+
+impl std::fmt::Debug for Debugger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Debugger")
+            .field("breakpoints", &self.breakpoints)
+            .field("stepping", &self.stepping)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Debugger {
+    pub fn new(
+        breakpoints: Vec<Breakpoint>,
+        callback: impl FnMut(&PauseState) -> DebugAction + 'static,
+    ) -> Self {
+        Debugger {
+            breakpoints,
+            stepping: false,
+            callback: Box::new(callback),
+        }
+    }
+
+    /// Called before executing the statement at `loc`. Calls the callback when there's a
+    /// breakpoint at the statement or the previous pause was a step.
+    pub(super) fn before_stmt(
+        &mut self,
+        pgm: &Pgm,
+        heap: &Heap,
+        locals: &Map<SmolStr, u64>,
+        loc: &Loc,
+    ) {
+        let line = u32::from(loc.line_start) + 1;
+        let at_breakpoint = self
+            .breakpoints
+            .iter()
+            .any(|bp| bp.line == line && *bp.module == *loc.module);
+
+        if !(self.stepping || at_breakpoint) {
+            return;
+        }
+
+        let state = PauseState {
+            pgm,
+            heap,
+            locals,
+            loc,
+        };
+        self.stepping = (self.callback)(&state) == DebugAction::Step;
+    }
+}
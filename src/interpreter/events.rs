@@ -0,0 +1,67 @@
+//! Structured events reported while running a program, for embedding the interpreter in tools
+//! that need to tell the program output apart from the interpreter's own messages.
+
+use crate::diagnostics::Diagnostic;
+use crate::interpreter::LocDisplay;
+
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+/// An event reported by the interpreter.
+#[derive(Debug, Clone, Copy)]
+pub enum Event<'a> {
+    /// Output of the program. Only reported when the program writes to an `EventWriter`.
+    Output(&'a [u8]),
+
+    /// A warning about the program, see `RunOptions::print_warnings`.
+    Warning(&'a Diagnostic),
+
+    /// A line of the execution trace, see `RunOptions::trace`.
+    Trace(&'a str),
+
+    /// Heap statistics and allocation profile, see `RunOptions::print_heap_stats` and
+    /// `RunOptions::profile_allocations`.
+    Stats(&'a str),
+}
+
+/// Receives the events of a program run.
+pub trait EventSink: std::fmt::Debug {
+    fn event(&mut self, event: Event);
+}
+
+/// Writes the program output to stdout, and the other events to stderr. This is the sink used
+/// when `RunOptions::events` is not set.
+#[derive(Debug, Default)]
+pub struct StderrSink;
+
+impl EventSink for StderrSink {
+    fn event(&mut self, event: Event) {
+        match event {
+            Event::Output(bytes) => {
+                let _ = std::io::stdout().write_all(bytes);
+            }
+            Event::Warning(diagnostic) => match &diagnostic.loc {
+                Some(loc) => eprintln!("{}: warning: {}", LocDisplay(loc), diagnostic.msg),
+                None => eprintln!("warning: {}", diagnostic.msg),
+            },
+            Event::Trace(line) | Event::Stats(line) => eprintln!("{}", line),
+        }
+    }
+}
+
+/// A `Write` that reports the written bytes as `Event::Output`. Pass it as the output of `run`
+/// to receive the program output as events.
+#[derive(Debug)]
+pub struct EventWriter(pub Rc<RefCell<dyn EventSink>>);
+
+impl Write for EventWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().event(Event::Output(buf));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,131 @@
+//! Finds `match` expressions that obviously don't handle all constructors of a type.
+//!
+//! Only the top-level patterns of the alternatives are checked: when the alternatives have
+//! constructor patterns of a type, and none of them is a variable or wildcard, each constructor of
+//! the type needs an alternative. Alternatives with guards don't count, as the guard may fail.
+//! Fields of the constructors are not checked.
+
+use crate::ast::Visitor;
+use crate::diagnostics::Diagnostic;
+use crate::interpreter::*;
+
+pub fn non_exhaustive_matches(
+    pgm: &[L<ast::TopDecl>],
+    ty_cons: &Map<SmolStr, TyCon>,
+) -> Vec<Diagnostic> {
+    let mut checker = MatchChecker {
+        ty_cons,
+        diagnostics: vec![],
+    };
+
+    for decl in pgm {
+        match &decl.node {
+            ast::TopDecl::Type(_) => {}
+            ast::TopDecl::Fun(fun_decl) => checker.visit_fun_decl(&fun_decl.node),
+            ast::TopDecl::Const(const_decl) => checker.visit_expr(&const_decl.node.rhs),
+            ast::TopDecl::Import(_) => panic!("Import declaration in exhaustiveness check"),
+        }
+    }
+
+    checker.diagnostics
+}
+
+struct MatchChecker<'a> {
+    ty_cons: &'a Map<SmolStr, TyCon>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Visitor for MatchChecker<'_> {
+    fn visit_expr(&mut self, expr: &L<ast::Expr>) {
+        if let ast::Expr::Match(ast::MatchExpr { scrutinee: _, alts }) = &expr.node {
+            check_match(alts, &expr.loc, self.ty_cons, &mut self.diagnostics);
+        }
+        ast::walk_expr(self, expr);
+    }
+}
+
+fn check_match(
+    alts: &[ast::Alt],
+    loc: &Loc,
+    ty_cons: &Map<SmolStr, TyCon>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    // Type of the constructor patterns, and the constructors handled by unguarded alternatives.
+    let mut ty: Option<&SmolStr> = None;
+    let mut covered: Set<Option<SmolStr>> = Default::default();
+
+    for alt in alts {
+        let unguarded = alt.guard.is_none();
+        if unguarded && alt_covers_all(&alt.pattern.node) {
+            return;
+        }
+        collect_constrs(&alt.pattern.node, unguarded, &mut ty, &mut covered);
+    }
+
+    let ty = match ty {
+        Some(ty) => ty,
+        None => return,
+    };
+
+    let ty_con = match ty_cons.get(ty) {
+        Some(ty_con) => ty_con,
+        // Undefined types are reported when the pattern is matched.
+        None => return,
+    };
+
+    // Product type constructors don't have a name in patterns.
+    if ty_con.value_constrs.len() == 1 && !covered.is_empty() {
+        return;
+    }
+
+    let missing: Vec<String> = ty_con
+        .value_constrs
+        .iter()
+        .filter(|constr| !covered.contains(&constr.name))
+        .map(|constr| format!("{}.{}", ty, constr.name.as_ref().unwrap()))
+        .collect();
+
+    if !missing.is_empty() {
+        diagnostics.push(Diagnostic {
+            loc: Some(loc.clone()),
+            msg: format!(
+                "non-exhaustive match, missing constructors: {}",
+                missing.join(", ")
+            ),
+        });
+    }
+}
+
+/// Whether the pattern matches all values.
+fn alt_covers_all(pat: &ast::Pat) -> bool {
+    match pat {
+        ast::Pat::Var(_) | ast::Pat::Ignore => true,
+        ast::Pat::Or(pat1, pat2) => alt_covers_all(&pat1.node) || alt_covers_all(&pat2.node),
+        ast::Pat::Constr(_) | ast::Pat::Record(_) | ast::Pat::Str(_) | ast::Pat::StrPfx(_) => false,
+    }
+}
+
+fn collect_constrs<'a>(
+    pat: &'a ast::Pat,
+    unguarded: bool,
+    ty: &mut Option<&'a SmolStr>,
+    covered: &mut Set<Option<SmolStr>>,
+) {
+    match pat {
+        ast::Pat::Constr(ast::ConstrPattern { constr, fields: _ }) => {
+            ty.get_or_insert(&constr.type_);
+            if unguarded {
+                covered.insert(constr.constr.clone());
+            }
+        }
+        ast::Pat::Or(pat1, pat2) => {
+            collect_constrs(&pat1.node, unguarded, ty, covered);
+            collect_constrs(&pat2.node, unguarded, ty, covered);
+        }
+        ast::Pat::Var(_)
+        | ast::Pat::Ignore
+        | ast::Pat::Record(_)
+        | ast::Pat::Str(_)
+        | ast::Pat::StrPfx(_) => {}
+    }
+}
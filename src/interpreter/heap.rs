@@ -8,44 +8,253 @@ use bytemuck::cast_slice;
 pub struct Heap {
     pub values: Box<[u64]>,
     hp: usize,
+    num_allocations: u64,
+
+    /// Allocation counts per expression, when allocation profiling is enabled.
+    alloc_sites: Option<AllocSites>,
+
+    /// Hash indices of the sets, by set address. See `allocate_set`.
+    set_indices: Map<u64, SetIndex>,
+
+    /// Bit `i` is set when an object starts at address `i`. Only maintained in debug builds, to
+    /// check that tags are read from object headers.
+    #[cfg(debug_assertions)]
+    object_starts: Vec<u64>,
+}
+
+/// Positions of the elements of a set in its element array, by element hash.
+///
+/// Hashes are computed by the interpreter, consistent with `__eq`, so an element equal to a value
+/// can only be at the positions with the value's hash.
+#[derive(Debug, Default, Clone)]
+struct SetIndex {
+    /// Hashes of the elements in the element array, by position. `None` at the positions of
+    /// removed elements.
+    hashes: Vec<Option<u64>>,
+
+    /// Positions of the elements with a hash.
+    positions: Map<u64, Vec<u64>>,
 }
 
+#[derive(Debug, Default)]
+struct AllocSites {
+    /// Location of the expression being evaluated.
+    current: Option<Loc>,
+
+    counts: Map<Loc, u64>,
+}
+
+/// Allocation statistics of a heap, returned by `Heap::stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    /// Number of objects allocated.
+    pub allocations: u64,
+
+    /// Total size of the allocated objects.
+    pub bytes_allocated: u64,
+
+    /// Largest size of the live objects.
+    pub peak_live_bytes: u64,
+
+    /// Number of garbage collections.
+    pub collections: u64,
+}
+
+impl std::fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "allocations:     {}", self.allocations)?;
+        writeln!(f, "bytes allocated: {}", self.bytes_allocated)?;
+        writeln!(f, "peak live bytes: {}", self.peak_live_bytes)?;
+        write!(f, "collections:     {}", self.collections)
+    }
+}
+
+// Address 0 is never allocated, and it's the value of unit and uninitialized array elements.
+// Reading or writing it is a bug in the interpreter, or a program using the unit value as an
+// object. Addresses after the last allocation are not allocated yet, accessing them is a bug in the
+// interpreter. Only checked in debug builds to keep indexing cheap in release builds.
+
 impl std::ops::Index<u64> for Heap {
     type Output = u64;
 
     fn index(&self, index: u64) -> &Self::Output {
+        debug_assert_ne!(
+            index, 0,
+            "Dereferencing heap address 0 (unit or uninitialized value)"
+        );
+        debug_assert!(
+            (index as usize) < self.hp,
+            "Dereferencing unallocated heap address {}",
+            index
+        );
         &self.values[index as usize]
     }
 }
 
 impl std::ops::IndexMut<u64> for Heap {
     fn index_mut(&mut self, index: u64) -> &mut Self::Output {
+        debug_assert_ne!(
+            index, 0,
+            "Dereferencing heap address 0 (unit or uninitialized value)"
+        );
+        debug_assert!(
+            (index as usize) < self.hp,
+            "Dereferencing unallocated heap address {}",
+            index
+        );
         &mut self.values[index as usize]
     }
 }
 
+impl Default for Heap {
+    fn default() -> Self {
+        Heap::new()
+    }
+}
+
 impl Heap {
+    /// Create a heap with the default initial size. The heap grows as needed.
     pub fn new() -> Self {
+        Heap::with_capacity(INITIAL_HEAP_SIZE_WORDS)
+    }
+
+    /// Create a heap with the given initial size, in words. The heap grows as needed.
+    pub fn with_capacity(capacity_words: usize) -> Self {
         // Heap pointer starts from 1. Address 0 is used as "null" or "uninitialized" marker in
         // arrays.
         Heap {
-            values: vec![0; INITIAL_HEAP_SIZE_WORDS].into_boxed_slice(),
+            values: vec![0; capacity_words.max(1)].into_boxed_slice(),
             hp: 1,
+            num_allocations: 0,
+            alloc_sites: None,
+            set_indices: Default::default(),
+            #[cfg(debug_assertions)]
+            object_starts: vec![],
+        }
+    }
+
+    /// Start counting allocations per expression location.
+    pub fn enable_alloc_profiling(&mut self) {
+        self.alloc_sites = Some(Default::default());
+    }
+
+    pub fn alloc_profiling_enabled(&self) -> bool {
+        self.alloc_sites.is_some()
+    }
+
+    /// Set the location of the expression being evaluated, for allocation profiling. Returns the
+    /// previous location.
+    pub fn set_alloc_site(&mut self, loc: Option<Loc>) -> Option<Loc> {
+        match &mut self.alloc_sites {
+            Some(sites) => std::mem::replace(&mut sites.current, loc),
+            None => None,
+        }
+    }
+
+    /// Allocation counts per expression location, highest count first.
+    pub fn alloc_profile(&self) -> Vec<(Loc, u64)> {
+        let mut counts: Vec<(Loc, u64)> = match &self.alloc_sites {
+            Some(sites) => sites
+                .counts
+                .iter()
+                .map(|(loc, count)| (loc.clone(), *count))
+                .collect(),
+            None => vec![],
+        };
+        counts.sort_by(|(loc1, count1), (loc2, count2)| {
+            count2.cmp(count1).then_with(|| {
+                (&loc1.module, loc1.byte_offset_start).cmp(&(&loc2.module, loc2.byte_offset_start))
+            })
+        });
+        counts
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        // Without a GC every allocated object stays live, so the live size only grows.
+        let bytes_allocated = (self.hp as u64 - 1) * 8;
+        HeapStats {
+            allocations: self.num_allocations,
+            bytes_allocated,
+            peak_live_bytes: bytes_allocated,
+            collections: 0,
         }
     }
 
     pub fn allocate(&mut self, size: usize) -> u64 {
         if self.hp + size > self.values.len() {
-            let mut new_values: Box<[u64]> = vec![0; self.values.len() * 2].into_boxed_slice();
+            let mut new_len = self.values.len() * 2;
+            while self.hp + size > new_len {
+                new_len *= 2;
+            }
+            let mut new_values: Box<[u64]> = vec![0; new_len].into_boxed_slice();
             new_values[0..self.hp].copy_from_slice(&self.values[0..self.hp]);
             self.values = new_values;
         }
 
         let hp = self.hp;
         self.hp += size;
+        self.num_allocations += 1;
+
+        if let Some(AllocSites {
+            current: Some(loc),
+            counts,
+        }) = &mut self.alloc_sites
+        {
+            *counts.entry(loc.clone()).or_default() += 1;
+        }
+
+        #[cfg(debug_assertions)]
+        {
+            let word = hp / 64;
+            if word >= self.object_starts.len() {
+                self.object_starts.resize(word + 1, 0);
+            }
+            self.object_starts[word] |= 1 << (hp % 64);
+        }
+
         hp as u64
     }
 
+    /// Header word of the object at `obj`, i.e. its tag. Use `tag` when the program is
+    /// available, to also check the tag.
+    ///
+    /// In debug builds, panics when `obj` is not the address of an object (e.g. it's the address
+    /// of a field).
+    pub fn header(&self, obj: u64) -> u64 {
+        let header = self[obj];
+
+        #[cfg(debug_assertions)]
+        {
+            let obj = obj as usize;
+            assert!(
+                self.object_starts[obj / 64] & (1 << (obj % 64)) != 0,
+                "Heap address {} is not the address of an object",
+                obj
+            );
+        }
+
+        header
+    }
+
+    /// Tag of the object at `obj`.
+    ///
+    /// In debug builds, panics when `obj` is not the address of an object (e.g. it's the address
+    /// of a field), or the tag is not a known tag.
+    #[cfg_attr(not(debug_assertions), allow(unused_variables))]
+    pub fn tag(&self, pgm: &Pgm, obj: u64) -> u64 {
+        let tag = self.header(obj);
+
+        #[cfg(debug_assertions)]
+        assert!(
+            (tag as usize) < pgm.cons_by_tag.len(),
+            "Object at heap address {} has unknown tag {}",
+            obj,
+            tag
+        );
+
+        tag
+    }
+
     // TODO: These should be allocated once and reused.
     pub fn allocate_tag(&mut self, tag: u64) -> u64 {
         let alloc = self.allocate(1);
@@ -53,21 +262,46 @@ impl Heap {
         alloc
     }
 
+    /// Allocate a `Str` with the given bytes. The bytes are not validated, use `allocate_utf8_str`
+    /// when the bytes may not be valid UTF-8 and the string should be.
+    ///
+    /// A `Str` is the tag, the length in bytes, the bytes padded to a word, and a word that is `1`
+    /// when the bytes are ASCII, for the ASCII fast paths of the character built-ins.
     pub fn allocate_str(&mut self, string: &[u8]) -> u64 {
         let size_words = string.len().div_ceil(8);
-        let alloc = self.allocate(size_words + 2);
+        let alloc = self.allocate(size_words + 3);
         self[alloc] = STR_TYPE_TAG;
         self[alloc + 1] = string.len() as u64;
 
         let bytes_start_word = (alloc as usize) + 2;
         let bytes_end_word = bytes_start_word + size_words;
 
-        let bytes: &mut [u8] = cast_slice_mut(&mut self.values[bytes_start_word..=bytes_end_word]);
+        // Bytes after the string in the last word are zero, so strings with the same bytes have
+        // the same words, and can be compared word by word.
+        let words = &mut self.values[bytes_start_word..bytes_end_word];
+        if let Some(last_word) = words.last_mut() {
+            *last_word = 0;
+        }
+        let bytes: &mut [u8] = cast_slice_mut(words);
         bytes[..string.len()].copy_from_slice(string);
 
+        self[bytes_end_word as u64] = u64::from(string.is_ascii());
+
         alloc
     }
 
+    /// Whether the bytes of the `Str` at `str_addr` are all ASCII. Each byte of an ASCII string is
+    /// a character.
+    pub fn str_is_ascii(&self, str_addr: u64) -> bool {
+        self[str_addr + 2 + self[str_addr + 1].div_ceil(8)] == 1
+    }
+
+    /// Allocate a `Str` with the given bytes, replacing invalid UTF-8 sequences with U+FFFD.
+    pub fn allocate_utf8_str(&mut self, string: &[u8]) -> u64 {
+        let string = String::from_utf8_lossy(string);
+        self.allocate_str(string.as_bytes())
+    }
+
     pub fn str_bytes(&self, str_addr: u64) -> &[u8] {
         let str_len_bytes = self[str_addr + 1];
         let str_payload_byte_addr = (str_addr + 2) * 8;
@@ -83,6 +317,8 @@ impl Heap {
         &str_bytes[byte_start as usize..byte_end as usize]
     }
 
+    /// The value is stored zero-extended, e.g. `-1` is stored as `0xFFFF_FFFF`, so the value
+    /// should be read with `as i32` (or `i32_value`), not `as i64` or as an unsigned number.
     pub fn allocate_i32(&mut self, i: i32) -> u64 {
         let alloc = self.allocate(2);
         self[alloc] = I32_TYPE_TAG;
@@ -90,6 +326,36 @@ impl Heap {
         alloc
     }
 
+    pub fn i32_value(&self, i: u64) -> i32 {
+        debug_assert_eq!(self.header(i), I32_TYPE_TAG);
+        self[i + 1] as i32
+    }
+
+    /// NaNs are stored as the positive quiet NaN `f64::NAN`, so all NaNs are equal.
+    pub fn allocate_f64(&mut self, f: f64) -> u64 {
+        let alloc = self.allocate(2);
+        self[alloc] = F64_TYPE_TAG;
+        self[alloc + 1] = if f.is_nan() { f64::NAN } else { f }.to_bits();
+        alloc
+    }
+
+    pub fn f64_value(&self, f: u64) -> f64 {
+        debug_assert_eq!(self.header(f), F64_TYPE_TAG);
+        f64::from_bits(self[f + 1])
+    }
+
+    pub fn allocate_char(&mut self, char: char) -> u64 {
+        let alloc = self.allocate(2);
+        self[alloc] = CHAR_TYPE_TAG;
+        self[alloc + 1] = u64::from(u32::from(char));
+        alloc
+    }
+
+    pub fn char_value(&self, char: u64) -> char {
+        debug_assert_eq!(self.header(char), CHAR_TYPE_TAG);
+        char::from_u32(self[char + 1] as u32).unwrap()
+    }
+
     pub fn allocate_constr(&mut self, type_tag: u64) -> u64 {
         let alloc = self.allocate(2);
         self[alloc] = CONSTR_TYPE_TAG;
@@ -104,46 +370,50 @@ impl Heap {
         alloc
     }
 
-    pub fn allocate_str_view(&mut self, string: u64, start_byte: u64, end_byte: u64) -> u64 {
-        debug_assert!(
-            start_byte <= end_byte,
-            "start_byte={}, end_byte={}",
-            start_byte,
-            end_byte
-        );
+    /// Allocate a view of the bytes `start_byte..end_byte` of `string`. Returns `None` when the
+    /// range is not within the string, as the view would read heap words after the string.
+    pub fn allocate_str_view(
+        &mut self,
+        string: u64,
+        start_byte: u64,
+        end_byte: u64,
+    ) -> Option<u64> {
+        debug_assert_eq!(self.header(string), STR_TYPE_TAG);
+        if start_byte > end_byte || end_byte > self[string + 1] {
+            return None;
+        }
         let alloc = self.allocate(4);
         self[alloc] = STR_VIEW_TYPE_TAG;
         self[alloc + 1] = start_byte;
         self[alloc + 2] = end_byte;
         self[alloc + 3] = string;
-        alloc
+        Some(alloc)
     }
 
+    /// Allocate a view of the bytes `start_byte..end_byte` of `str_view`, relative to the start of
+    /// the view. Returns `None` when the range is not within the view.
     pub fn allocate_str_view_from_str_view(
         &mut self,
         str_view: u64,
         start_byte: u64,
         end_byte: u64,
-    ) -> u64 {
-        debug_assert!(
-            start_byte <= end_byte,
-            "start_byte={}, end_byte={}",
-            start_byte,
-            end_byte
-        );
+    ) -> Option<u64> {
+        debug_assert_eq!(self.header(str_view), STR_VIEW_TYPE_TAG);
 
         let str = self[str_view + 3];
         let str_view_start = self[str_view + 1];
         let str_view_end = self[str_view + 2];
 
-        assert!(str_view_start + end_byte <= str_view_end);
+        if start_byte > end_byte || str_view_start + end_byte > str_view_end {
+            return None;
+        }
 
         let alloc = self.allocate(4);
         self[alloc] = STR_VIEW_TYPE_TAG;
         self[alloc + 1] = str_view_start + start_byte;
         self[alloc + 2] = str_view_start + end_byte;
         self[alloc + 3] = str;
-        alloc
+        Some(alloc)
     }
 
     pub fn allocate_array(&mut self, cap: u64) -> u64 {
@@ -155,4 +425,407 @@ impl Heap {
         }
         alloc
     }
+
+    /// Allocate an empty set. The set object holds the number of elements and an array of the
+    /// elements in insertion order. The array grows as elements are added. Removed elements leave
+    /// a gap in the array, until more than half of the used slots are gaps and the elements are
+    /// moved to the start of the array. Slots after the elements are not initialized.
+    ///
+    /// Elements are found through a hash index kept outside of the heap, so the interpreter only
+    /// compares a value with the elements with the same hash.
+    ///
+    /// Iterating and printing a set follow the insertion order, which doesn't depend on object
+    /// addresses, so programs that iterate sets give the same output in every run.
+    pub fn allocate_set(&mut self) -> u64 {
+        let elems = self.allocate_array(4);
+        let alloc = self.allocate(3);
+        self[alloc] = SET_TYPE_TAG;
+        self[alloc + 1] = 0;
+        self[alloc + 2] = elems;
+        self.set_indices.insert(alloc, SetIndex::default());
+        alloc
+    }
+
+    /// Positions of the elements of a set with the given hash, in insertion order.
+    pub fn set_positions(&self, set: u64, hash: u64) -> Vec<u64> {
+        self.set_indices[&set]
+            .positions
+            .get(&hash)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Element of a set at a position returned by `set_positions`.
+    pub fn set_elem(&self, set: u64, pos: u64) -> u64 {
+        self[self[set + 2] + 2 + pos]
+    }
+
+    /// Elements of a set, in insertion order.
+    pub fn set_elems(&self, set: u64) -> Vec<u64> {
+        debug_assert_eq!(self.header(set), SET_TYPE_TAG);
+        let elems = self[set + 2];
+        self.set_indices[&set]
+            .hashes
+            .iter()
+            .enumerate()
+            .filter(|(_, hash)| hash.is_some())
+            .map(|(pos, _)| self[elems + 2 + pos as u64])
+            .collect()
+    }
+
+    /// Add an element with the given hash to the end of a set. The caller checks that the set
+    /// doesn't have an element equal to `elem`.
+    pub fn set_add(&mut self, set: u64, elem: u64, hash: u64) {
+        debug_assert_eq!(self.header(set), SET_TYPE_TAG);
+        let index = self.set_indices.get_mut(&set).unwrap();
+        let pos = index.hashes.len() as u64;
+        index.hashes.push(Some(hash));
+        index.positions.entry(hash).or_default().push(pos);
+
+        let mut elems = self[set + 2];
+        let cap = self[elems + 1];
+        if pos == cap {
+            let new_elems = self.allocate_array((cap * 2).max(4));
+            for i in 0..cap {
+                self[new_elems + 2 + i] = self[elems + 2 + i];
+            }
+            self[set + 2] = new_elems;
+            elems = new_elems;
+        }
+        self[elems + 2 + pos] = elem;
+        self[set + 1] += 1;
+    }
+
+    /// Remove the element at a position returned by `set_positions`.
+    pub fn set_remove(&mut self, set: u64, pos: u64) {
+        debug_assert_eq!(self.header(set), SET_TYPE_TAG);
+        let mut index = self.set_indices.remove(&set).unwrap();
+        let hash = index.hashes[pos as usize].take().unwrap();
+        let positions = index.positions.get_mut(&hash).unwrap();
+        positions.retain(|other| *other != pos);
+        if positions.is_empty() {
+            index.positions.remove(&hash);
+        }
+
+        let elems = self[set + 2];
+        self[elems + 2 + pos] = 0;
+        let len = self[set + 1] - 1;
+        self[set + 1] = len;
+
+        // Move the elements to the start of the array when more than half of the slots are gaps.
+        let used = index.hashes.len() as u64;
+        if used > 2 * len {
+            index.positions.clear();
+            let mut new_pos = 0;
+            for (pos, hash) in std::mem::take(&mut index.hashes).into_iter().enumerate() {
+                if let Some(hash) = hash {
+                    self[elems + 2 + new_pos] = self[elems + 2 + pos as u64];
+                    index.positions.entry(hash).or_default().push(new_pos);
+                    index.hashes.push(Some(hash));
+                    new_pos += 1;
+                }
+            }
+            for pos in new_pos..used {
+                self[elems + 2 + pos] = 0;
+            }
+        }
+
+        self.set_indices.insert(set, index);
+    }
+
+    /// Allocate a new array with the elements of a set, in insertion order.
+    pub fn set_elems_array(&mut self, set: u64) -> u64 {
+        let elems = self.set_elems(set);
+        let array = self.allocate_array(elems.len() as u64);
+        for (i, elem) in elems.into_iter().enumerate() {
+            self[array + 2 + i as u64] = elem;
+        }
+        array
+    }
+
+    /// Allocate an empty tree map. The map object holds the number of entries and two arrays of
+    /// the keys and values, sorted by key. The arrays grow as entries are added, slots after the
+    /// entries are not initialized.
+    pub fn allocate_tree_map(&mut self) -> u64 {
+        let keys = self.allocate_array(4);
+        let values = self.allocate_array(4);
+        let alloc = self.allocate(4);
+        self[alloc] = TREE_MAP_TYPE_TAG;
+        self[alloc + 1] = 0;
+        self[alloc + 2] = keys;
+        self[alloc + 3] = values;
+        alloc
+    }
+
+    /// Allocate a new array with the keys of a tree map, in ascending order.
+    pub fn tree_map_keys_array(&mut self, map: u64) -> u64 {
+        debug_assert_eq!(self.header(map), TREE_MAP_TYPE_TAG);
+        let len = self[map + 1];
+        let array = self.allocate_array(len);
+        let keys = self[map + 2];
+        for i in 0..len {
+            self[array + 2 + i] = self[keys + 2 + i];
+        }
+        array
+    }
+
+    /// Allocate an empty string builder with room for `cap` bytes. The builder object holds the
+    /// number of bytes appended and a `Str` used as the buffer, with the capacity as its length.
+    /// The buffer is replaced with a larger one when it's full.
+    pub fn allocate_str_builder(&mut self, cap: u64) -> u64 {
+        let buf = self.allocate_str(&vec![0; cap as usize]);
+        let alloc = self.allocate(3);
+        self[alloc] = STR_BUILDER_TYPE_TAG;
+        self[alloc + 1] = 0;
+        self[alloc + 2] = buf;
+        alloc
+    }
+
+    /// Bytes appended to a string builder.
+    pub fn str_builder_bytes(&self, builder: u64) -> &[u8] {
+        debug_assert_eq!(self.header(builder), STR_BUILDER_TYPE_TAG);
+        &self.str_bytes(self[builder + 2])[..self[builder + 1] as usize]
+    }
+
+    /// Append `bytes` to a string builder, growing the buffer to at least twice its size when the
+    /// bytes don't fit.
+    pub fn str_builder_append(&mut self, builder: u64, bytes: &[u8]) {
+        debug_assert_eq!(self.header(builder), STR_BUILDER_TYPE_TAG);
+        let len = self[builder + 1] as usize;
+        let new_len = len + bytes.len();
+        let cap = self[self[builder + 2] + 1] as usize;
+        if new_len > cap {
+            let mut new_buf = self.str_builder_bytes(builder).to_vec();
+            new_buf.resize(new_len.max(cap * 2), 0);
+            self[builder + 2] = self.allocate_str(&new_buf);
+        }
+        let buf_payload_byte_addr = (self[builder + 2] as usize + 2) * 8;
+        let buf_bytes: &mut [u8] = cast_slice_mut(&mut self.values);
+        buf_bytes[buf_payload_byte_addr + len..buf_payload_byte_addr + new_len]
+            .copy_from_slice(bytes);
+        self[builder + 1] = new_len as u64;
+    }
+
+    pub fn allocate_range(&mut self, from: i32, to: i32, inclusive: bool, step: i32) -> u64 {
+        let alloc = self.allocate(5);
+        self[alloc] = RANGE_TYPE_TAG;
+        self[alloc + 1] = (from as u32) as u64;
+        self[alloc + 2] = (to as u32) as u64;
+        self[alloc + 3] = u64::from(inclusive);
+        self[alloc + 4] = (step as u32) as u64;
+        alloc
+    }
+
+    /// Returns `from`, `to`, `inclusive`, `step` of a range.
+    pub fn range_fields(&self, range: u64) -> (i32, i32, bool, i32) {
+        debug_assert_eq!(self.header(range), RANGE_TYPE_TAG);
+        (
+            self[range + 1] as i32,
+            self[range + 2] as i32,
+            self[range + 3] != 0,
+            self[range + 4] as i32,
+        )
+    }
+
+    /// Number of words of the object at `obj`: the tag and the payload.
+    pub fn object_size(&self, pgm: &Pgm, obj: u64) -> u64 {
+        match self.tag(pgm, obj) {
+            I32_TYPE_TAG | CONSTR_TYPE_TAG | TOP_FUN_TYPE_TAG | CHAR_TYPE_TAG | F64_TYPE_TAG => 2,
+            ASSOC_FUN_TYPE_TAG | SET_TYPE_TAG | STR_BUILDER_TYPE_TAG => 3,
+            ARRAY_ITER_TYPE_TAG | RANGE_ITER_TYPE_TAG | STR_CHARS_TYPE_TAG | SCANNER_TYPE_TAG => 3,
+            STR_VIEW_TYPE_TAG | TREE_MAP_TYPE_TAG => 4,
+            RANGE_TYPE_TAG => 5,
+            STR_TYPE_TAG => 3 + self[obj + 1].div_ceil(8),
+            ARRAY_TYPE_TAG => 2 + self[obj + 1],
+            tag => {
+                let num_fields = match pgm.get_tag_fields(tag) {
+                    Fields::Unnamed(arity) => u64::from(*arity),
+                    Fields::Named(names) => names.len() as u64,
+                };
+                1 + num_fields
+            }
+        }
+    }
+
+    /// Addresses of the words in the object at `obj` that hold references to other objects.
+    ///
+    /// Payload words of built-in objects (integer values, string lengths and bytes, string view
+    /// offsets, function indices) are not references and are not included. Array elements can be
+    /// `0` when not initialized, callers should skip those.
+    pub fn object_ref_slots(&self, pgm: &Pgm, obj: u64) -> std::ops::Range<u64> {
+        match self.tag(pgm, obj) {
+            I32_TYPE_TAG | STR_TYPE_TAG | CONSTR_TYPE_TAG | TOP_FUN_TYPE_TAG
+            | ASSOC_FUN_TYPE_TAG | RANGE_TYPE_TAG | CHAR_TYPE_TAG | F64_TYPE_TAG => obj..obj,
+            STR_VIEW_TYPE_TAG => obj + 3..obj + 4,
+            ARRAY_TYPE_TAG => obj + 2..obj + 2 + self[obj + 1],
+            ARRAY_ITER_TYPE_TAG | RANGE_ITER_TYPE_TAG | STR_CHARS_TYPE_TAG | SCANNER_TYPE_TAG => {
+                obj + 1..obj + 2
+            }
+            SET_TYPE_TAG | STR_BUILDER_TYPE_TAG => obj + 2..obj + 3,
+            TREE_MAP_TYPE_TAG => obj + 2..obj + 4,
+            tag => {
+                let num_fields = match pgm.get_tag_fields(tag) {
+                    Fields::Unnamed(arity) => u64::from(*arity),
+                    Fields::Named(names) => names.len() as u64,
+                };
+                obj + 1..obj + 1 + num_fields
+            }
+        }
+    }
+
+    /// Copies the objects reachable from `root` to new allocations, returns the copy of `root`.
+    ///
+    /// Objects without references (integers, strings, nullary constructors, functions) are
+    /// immutable and shared with the original. Other objects are copied once, so shared objects
+    /// and cycles in the original are shared objects and cycles in the copy.
+    pub fn clone_object(&mut self, pgm: &Pgm, root: u64) -> u64 {
+        let mut copies: Map<u64, u64> = Default::default();
+        let mut work: Vec<u64> = vec![];
+        let root_copy = self.clone_shallow(pgm, root, &mut copies, &mut work);
+
+        // Objects in `work` are copies with references to the original objects.
+        while let Some(copy) = work.pop() {
+            for slot in self.object_ref_slots(pgm, copy) {
+                let field = self[slot];
+                self[slot] = self.clone_shallow(pgm, field, &mut copies, &mut work);
+            }
+        }
+
+        root_copy
+    }
+
+    /// Copy of `obj` with the same references as `obj`, or `obj` when it doesn't need copying.
+    /// New copies are added to `work` to update their references.
+    fn clone_shallow(
+        &mut self,
+        pgm: &Pgm,
+        obj: u64,
+        copies: &mut Map<u64, u64>,
+        work: &mut Vec<u64>,
+    ) -> u64 {
+        // `0` is unit, or an uninitialized array element.
+        if obj == 0 || self.object_ref_slots(pgm, obj).is_empty() {
+            return obj;
+        }
+        if let Some(copy) = copies.get(&obj) {
+            return *copy;
+        }
+        let size = self.object_size(pgm, obj);
+        let copy = self.allocate(size as usize);
+        for i in 0..size {
+            self[copy + i] = self[obj + i];
+        }
+        // The buffer of a string builder is updated in place, so the copy needs its own.
+        if self.header(obj) == STR_BUILDER_TYPE_TAG {
+            let buf = self.str_bytes(self[obj + 2]).to_vec();
+            self[copy + 2] = self.allocate_str(&buf);
+        }
+        // The elements of a copied set are copies with the same hashes, at the same positions.
+        if self.header(obj) == SET_TYPE_TAG {
+            let index = self.set_indices[&obj].clone();
+            self.set_indices.insert(copy, index);
+        }
+        copies.insert(obj, copy);
+        work.push(copy);
+        copy
+    }
+
+    /// Renders the objects reachable from `roots` as text, one object per line followed by its
+    /// outgoing references. Objects are listed in the order they are reached.
+    pub fn dump(&self, pgm: &Pgm, roots: &[u64]) -> String {
+        use std::fmt::Write;
+
+        let mut s = String::new();
+        let mut visited: Set<u64> = Default::default();
+        let mut work: std::collections::VecDeque<u64> = roots.iter().copied().collect();
+
+        while let Some(obj) = work.pop_front() {
+            if !visited.insert(obj) {
+                continue;
+            }
+
+            let tag = self.tag(pgm, obj);
+            let con = &pgm.cons_by_tag[tag as usize];
+
+            write!(&mut s, "@{} ", obj).unwrap();
+            match tag {
+                I32_TYPE_TAG => write!(&mut s, "I32 {}", self[obj + 1] as i32).unwrap(),
+                STR_TYPE_TAG => write!(
+                    &mut s,
+                    "Str {:?}",
+                    String::from_utf8_lossy(self.str_bytes(obj))
+                )
+                .unwrap(),
+                STR_VIEW_TYPE_TAG => {
+                    write!(&mut s, "StrView {}..{}", self[obj + 1], self[obj + 2]).unwrap()
+                }
+                ARRAY_TYPE_TAG => write!(&mut s, "Array len={}", self[obj + 1]).unwrap(),
+                ARRAY_ITER_TYPE_TAG => write!(&mut s, "ArrayIter idx={}", self[obj + 2]).unwrap(),
+                RANGE_TYPE_TAG => {
+                    let (from, to, inclusive, step) = self.range_fields(obj);
+                    write!(
+                        &mut s,
+                        "Range {}{}{} step {}",
+                        from,
+                        if inclusive { "..=" } else { ".." },
+                        to,
+                        step
+                    )
+                    .unwrap()
+                }
+                RANGE_ITER_TYPE_TAG => {
+                    write!(&mut s, "RangeIter next={}", self[obj + 2] as i64).unwrap()
+                }
+                F64_TYPE_TAG => write!(&mut s, "F64 {:?}", self.f64_value(obj)).unwrap(),
+                CHAR_TYPE_TAG => write!(&mut s, "Char {:?}", self.char_value(obj)).unwrap(),
+                STR_CHARS_TYPE_TAG => write!(&mut s, "StrChars idx={}", self[obj + 2]).unwrap(),
+                SET_TYPE_TAG => write!(&mut s, "Set len={}", self[obj + 1]).unwrap(),
+                SCANNER_TYPE_TAG => write!(&mut s, "Scanner idx={}", self[obj + 2]).unwrap(),
+                TREE_MAP_TYPE_TAG => write!(&mut s, "TreeMap len={}", self[obj + 1]).unwrap(),
+                STR_BUILDER_TYPE_TAG => write!(&mut s, "StrBuilder len={}", self[obj + 1]).unwrap(),
+                CONSTR_TYPE_TAG => write!(&mut s, "#CONSTR tag={}", self[obj + 1]).unwrap(),
+                TOP_FUN_TYPE_TAG => write!(&mut s, "#TOP_FUN idx={}", self[obj + 1]).unwrap(),
+                ASSOC_FUN_TYPE_TAG => write!(
+                    &mut s,
+                    "#ASSOC_FUN tag={} idx={}",
+                    self[obj + 1],
+                    self[obj + 2]
+                )
+                .unwrap(),
+                _ => match &con.info {
+                    ConInfo::Named {
+                        ty_name,
+                        con_name: Some(con_name),
+                    } => write!(&mut s, "{}.{}", ty_name, con_name).unwrap(),
+                    ConInfo::Named {
+                        ty_name,
+                        con_name: None,
+                    } => write!(&mut s, "{}", ty_name).unwrap(),
+                    ConInfo::Record { .. } => write!(&mut s, "Record").unwrap(),
+                },
+            }
+            writeln!(&mut s).unwrap();
+
+            let field_names: Option<&[SmolStr]> = match (tag, &con.fields) {
+                (ARRAY_TYPE_TAG, _) => None,
+                (_, Fields::Named(names)) => Some(names),
+                (_, Fields::Unnamed(_)) => None,
+            };
+
+            for (field_idx, slot) in self.object_ref_slots(pgm, obj).enumerate() {
+                let field_value = self[slot];
+                if field_value == 0 {
+                    continue;
+                }
+                match field_names {
+                    Some(names) => writeln!(&mut s, "    {} -> @{}", names[field_idx], field_value),
+                    None => writeln!(&mut s, "    {} -> @{}", field_idx, field_value),
+                }
+                .unwrap();
+                work.push_back(field_value);
+            }
+        }
+
+        s
+    }
 }
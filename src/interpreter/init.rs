@@ -59,6 +59,86 @@ pub fn collect_types(pgm: &[L<ast::TopDecl>]) -> (Map<SmolStr, TyCon>, u64) {
         },
     );
 
+    ty_cons.insert(
+        SmolStr::new("ArrayIter"),
+        TyCon {
+            value_constrs: vec![],
+            type_tag: ARRAY_ITER_TYPE_TAG,
+        },
+    );
+
+    ty_cons.insert(
+        SmolStr::new("Range"),
+        TyCon {
+            value_constrs: vec![],
+            type_tag: RANGE_TYPE_TAG,
+        },
+    );
+
+    ty_cons.insert(
+        SmolStr::new("RangeIter"),
+        TyCon {
+            value_constrs: vec![],
+            type_tag: RANGE_ITER_TYPE_TAG,
+        },
+    );
+
+    ty_cons.insert(
+        SmolStr::new("Char"),
+        TyCon {
+            value_constrs: vec![],
+            type_tag: CHAR_TYPE_TAG,
+        },
+    );
+
+    ty_cons.insert(
+        SmolStr::new("StrChars"),
+        TyCon {
+            value_constrs: vec![],
+            type_tag: STR_CHARS_TYPE_TAG,
+        },
+    );
+
+    ty_cons.insert(
+        SmolStr::new("F64"),
+        TyCon {
+            value_constrs: vec![],
+            type_tag: F64_TYPE_TAG,
+        },
+    );
+
+    ty_cons.insert(
+        SmolStr::new("Set"),
+        TyCon {
+            value_constrs: vec![],
+            type_tag: SET_TYPE_TAG,
+        },
+    );
+
+    ty_cons.insert(
+        SmolStr::new("Scanner"),
+        TyCon {
+            value_constrs: vec![],
+            type_tag: SCANNER_TYPE_TAG,
+        },
+    );
+
+    ty_cons.insert(
+        SmolStr::new("TreeMap"),
+        TyCon {
+            value_constrs: vec![],
+            type_tag: TREE_MAP_TYPE_TAG,
+        },
+    );
+
+    ty_cons.insert(
+        SmolStr::new("StrBuilder"),
+        TyCon {
+            value_constrs: vec![],
+            type_tag: STR_BUILDER_TYPE_TAG,
+        },
+    );
+
     let mut next_type_tag = FIRST_TYPE_TAG;
 
     fn convert_constr_fields(fields: &ast::ConstructorFields) -> Fields {
@@ -84,7 +164,7 @@ pub fn collect_types(pgm: &[L<ast::TopDecl>]) -> (Map<SmolStr, TyCon>, u64) {
             rhs,
         } = match &decl.node {
             ast::TopDecl::Type(ty_decl) => &ty_decl.node,
-            ast::TopDecl::Fun(_) => continue,
+            ast::TopDecl::Fun(_) | ast::TopDecl::Const(_) => continue,
             ast::TopDecl::Import(_) => panic!("Import declaration in the interpreter"),
         };
 
@@ -136,6 +216,55 @@ pub fn collect_types(pgm: &[L<ast::TopDecl>]) -> (Map<SmolStr, TyCon>, u64) {
     (ty_cons, next_type_tag)
 }
 
+/// Associated constants of the built-in types: type, constant name, and value.
+pub const BUILTIN_CONSTS: [(&str, &str, i32); 2] =
+    [("I32", "MAX", i32::MAX), ("I32", "MIN", i32::MIN)];
+
+/// Collect associated constants: the built-in constants and the program's constants, not
+/// initialized yet, and the program's constant declarations in declaration order.
+#[allow(clippy::type_complexity)]
+pub fn collect_consts(
+    pgm: &[L<ast::TopDecl>],
+) -> (
+    Map<SmolStr, Map<SmolStr, Option<u64>>>,
+    Vec<L<ast::ConstDecl>>,
+) {
+    let mut consts: Map<SmolStr, Map<SmolStr, Option<u64>>> = Default::default();
+
+    for (ty, name, _) in BUILTIN_CONSTS {
+        consts
+            .entry(SmolStr::new(ty))
+            .or_default()
+            .insert(SmolStr::new(name), None);
+    }
+
+    let mut const_decls: Vec<L<ast::ConstDecl>> = vec![];
+
+    for decl in pgm {
+        let const_decl = match &decl.node {
+            ast::TopDecl::Type(_) | ast::TopDecl::Fun(_) => continue,
+            ast::TopDecl::Const(const_decl) => const_decl,
+            ast::TopDecl::Import(_) => panic!("Import declaration in the interpreter"),
+        };
+
+        let old = consts
+            .entry(const_decl.node.type_name.clone())
+            .or_default()
+            .insert(const_decl.node.name.clone(), None);
+        assert!(
+            old.is_none(),
+            "{}: Constant {}.{} is defined more than once",
+            LocDisplay(&const_decl.loc),
+            const_decl.node.type_name,
+            const_decl.node.name
+        );
+
+        const_decls.push(const_decl.clone());
+    }
+
+    (consts, const_decls)
+}
+
 pub fn collect_funs(
     pgm: Vec<L<ast::TopDecl>>,
 ) -> (Map<SmolStr, Fun>, Map<SmolStr, Map<SmolStr, Fun>>) {
@@ -159,6 +288,17 @@ pub fn collect_funs(
         "printStr" => BuiltinFun::PrintStr,
         "printStrView" => BuiltinFun::PrintStrView,
         "panic" => BuiltinFun::Panic,
+        "dumpHeap" => BuiltinFun::DumpHeap,
+        "assert" => BuiltinFun::Assert,
+        "structuralEq" => BuiltinFun::StructuralEq,
+        "format" => BuiltinFun::Format,
+        "now" => BuiltinFun::Now,
+        "random" => BuiltinFun::Random,
+        "randomSeed" => BuiltinFun::RandomSeed,
+        "sizeOf" => BuiltinFun::SizeOf,
+        "typeName" => BuiltinFun::TypeName,
+        "clone" => BuiltinFun::Clone,
+        "refEq" => BuiltinFun::RefEq,
     };
 
     macro_rules! builtin_associated_funs {
@@ -184,15 +324,77 @@ pub fn collect_funs(
         "Str" => {
             "len" => BuiltinFun::StrLen,
             "__eq" => BuiltinFun::StrEq,
+            "__cmp" => BuiltinFun::StrCmp,
             "substr" => BuiltinFun::StrSubstr,
+            "toStr" => BuiltinFun::StrToStr,
+            "chars" => BuiltinFun::StrChars,
+            "byteLen" => BuiltinFun::StrByteLen,
+            "isEmpty" => BuiltinFun::StrIsEmpty,
+            "byteAt" => BuiltinFun::StrByteAt,
+            "charLen" => BuiltinFun::StrCharLen,
+            "charAt" => BuiltinFun::StrCharAt,
+            "isValidUtf8" => BuiltinFun::StrIsValidUtf8,
+            "startsWith" => BuiltinFun::StrStartsWith,
+            "endsWith" => BuiltinFun::StrEndsWith,
+            "replace" => BuiltinFun::StrReplace,
+            "lines" => BuiltinFun::StrLines,
+            "repeat" => BuiltinFun::StrRepeat,
+        },
+        // String builders append to a buffer that doubles in size when full, so building a string
+        // piece by piece takes linear time, unlike repeated interpolation.
+        "StrBuilder" => {
+            "new" => BuiltinFun::StrBuilderNew,
+            "withCapacity" => BuiltinFun::StrBuilderWithCapacity,
+            "append" => BuiltinFun::StrBuilderAppend,
+            "len" => BuiltinFun::StrBuilderLen,
+            "toStr" => BuiltinFun::StrBuilderToStr,
+        },
+        "StrChars" => {
+            "iter" => BuiltinFun::StrCharsIter,
+            "next" => BuiltinFun::StrCharsNext,
+        },
+        "Char" => {
+            "__eq" => BuiltinFun::CharEq,
+            "__cmp" => BuiltinFun::CharCmp,
+            "toStr" => BuiltinFun::CharToStr,
         },
         "I32" => {
+            // `+`, `-` and `*` return an error on overflow. `add` variants for other overflow
+            // behavior: `addChecked` returns `Option`, `addSaturating` clamps to the `I32`
+            // bounds, `addWrapping` wraps around.
             "__add" => BuiltinFun::I32Add,
+            "addChecked" => BuiltinFun::I32AddChecked,
+            "addSaturating" => BuiltinFun::I32AddSaturating,
+            "addWrapping" => BuiltinFun::I32AddWrapping,
             "__cmp" => BuiltinFun::I32Cmp,
             "__mul" => BuiltinFun::I32Mul,
+            "__div" => BuiltinFun::I32Div,
+            "__mod" => BuiltinFun::I32Mod,
+            // Bitwise operations. Named differently than `__and` and `__or` as those are for the
+            // boolean operators `&&` and `||`.
+            "bitAnd" => BuiltinFun::I32BitAnd,
+            "bitOr" => BuiltinFun::I32BitOr,
+            "bitXor" => BuiltinFun::I32BitXor,
+            "bitNot" => BuiltinFun::I32BitNot,
+            "shl" => BuiltinFun::I32Shl,
+            "shr" => BuiltinFun::I32Shr,
             "__sub" => BuiltinFun::I32Sub,
             "__eq" => BuiltinFun::I32Eq,
             "toStr" => BuiltinFun::I32ToStr,
+            "toF64" => BuiltinFun::I32ToF64,
+        },
+        // `F64` equality and ordering use the IEEE 754 total order, so `__eq` and `__cmp` agree.
+        // NaNs are normalized when allocated, so all NaNs are equal and greater than infinity.
+        // `-0.0` is less than `0.0`.
+        "F64" => {
+            "__add" => BuiltinFun::F64Add,
+            "__sub" => BuiltinFun::F64Sub,
+            "__mul" => BuiltinFun::F64Mul,
+            "__div" => BuiltinFun::F64Div,
+            "__eq" => BuiltinFun::F64Eq,
+            "__cmp" => BuiltinFun::F64Cmp,
+            "toStr" => BuiltinFun::F64ToStr,
+            "toI32" => BuiltinFun::F64ToI32,
         },
         "StrView" => {
             "__eq" => BuiltinFun::StrViewEq,
@@ -204,9 +406,91 @@ pub fn collect_funs(
         },
         "Array" => {
             "new" => BuiltinFun::ArrayNew,
+            "fill" => BuiltinFun::ArrayFill,
             "len" => BuiltinFun::ArrayLen,
             "set" => BuiltinFun::ArraySet,
             "get" => BuiltinFun::ArrayGet,
+            "map" => BuiltinFun::ArrayMap,
+            "filter" => BuiltinFun::ArrayFilter,
+            "fold" => BuiltinFun::ArrayFold,
+            "forEach" => BuiltinFun::ArrayForEach,
+            "forEachIndexed" => BuiltinFun::ArrayForEachIndexed,
+            "forEachWhile" => BuiltinFun::ArrayForEachWhile,
+            "iter" => BuiltinFun::ArrayIter,
+            "sort" => BuiltinFun::ArraySort,
+            "sortBy" => BuiltinFun::ArraySortBy,
+            "contains" => BuiltinFun::ArrayContains,
+            "indexOf" => BuiltinFun::ArrayIndexOf,
+            "concat" => BuiltinFun::ArrayConcat,
+            "reverse" => BuiltinFun::ArrayReverse,
+            "join" => BuiltinFun::ArrayJoin,
+        },
+        // Elements of a `Set` are kept in insertion order, which is also the iteration order.
+        // Membership is checked with `__eq`, on the elements with the same hash. Elements of types
+        // with a user-defined `__eq` are hashed with their `__hash` method.
+        "Set" => {
+            "new" => BuiltinFun::SetNew,
+            "add" => BuiltinFun::SetAdd,
+            "contains" => BuiltinFun::SetContains,
+            "remove" => BuiltinFun::SetRemove,
+            "len" => BuiltinFun::SetLen,
+            "iter" => BuiltinFun::SetIter,
+        },
+        // Scanners keep a cursor into a string, for tokenizing without allocating a `StrView`
+        // per step. Positions are in bytes.
+        "Scanner" => {
+            "new" => BuiltinFun::ScannerNew,
+            "peek" => BuiltinFun::ScannerPeek,
+            "next" => BuiltinFun::ScannerNext,
+            "advance" => BuiltinFun::ScannerAdvance,
+            "startsWith" => BuiltinFun::ScannerStartsWith,
+            "remaining" => BuiltinFun::ScannerRemaining,
+            "pos" => BuiltinFun::ScannerPos,
+            "iter" => BuiltinFun::ScannerIter,
+        },
+        // Entries of a `TreeMap` are kept sorted by key, compared with `__cmp`. Lookups are
+        // binary searches, iteration yields the keys in ascending order.
+        "TreeMap" => {
+            "new" => BuiltinFun::TreeMapNew,
+            "insert" => BuiltinFun::TreeMapInsert,
+            "get" => BuiltinFun::TreeMapGet,
+            "remove" => BuiltinFun::TreeMapRemove,
+            "len" => BuiltinFun::TreeMapLen,
+            "iter" => BuiltinFun::TreeMapIter,
+        },
+        "ArrayIter" => {
+            "next" => BuiltinFun::ArrayIterNext,
+        },
+        "Range" => {
+            "step" => BuiltinFun::RangeStep,
+            "iter" => BuiltinFun::RangeIter,
+        },
+        "RangeIter" => {
+            "next" => BuiltinFun::RangeIterNext,
+        },
+        "Bool" => {
+            "__and" => BuiltinFun::BoolAnd,
+            "__or" => BuiltinFun::BoolOr,
+            "__eq" => BuiltinFun::BoolEq,
+            "toStr" => BuiltinFun::BoolToStr,
+        },
+        "Option" => {
+            "map" => BuiltinFun::OptionMap,
+            "unwrap" => BuiltinFun::OptionUnwrap,
+            "unwrapOr" => BuiltinFun::OptionUnwrapOr,
+            "andThen" => BuiltinFun::OptionAndThen,
+            "orElse" => BuiltinFun::OptionOrElse,
+            "getOrElse" => BuiltinFun::OptionGetOrElse,
+        },
+        "Ordering" => {
+            "reverse" => BuiltinFun::OrderingReverse,
+            "then" => BuiltinFun::OrderingThen,
+        },
+        "Result" => {
+            "map" => BuiltinFun::ResultMap,
+            "unwrap" => BuiltinFun::ResultUnwrap,
+            "unwrapOr" => BuiltinFun::ResultUnwrapOr,
+            "andThen" => BuiltinFun::ResultAndThen,
         },
     };
 
@@ -214,7 +498,7 @@ pub fn collect_funs(
 
     for decl in pgm {
         let fun_decl: ast::FunDecl = match decl.node {
-            ast::TopDecl::Type(_) => continue,
+            ast::TopDecl::Type(_) | ast::TopDecl::Const(_) => continue,
             ast::TopDecl::Fun(fun_decl) => fun_decl.node,
             ast::TopDecl::Import(_) => panic!("Import declaration in the interpreter"),
         };
@@ -248,7 +532,14 @@ pub fn collect_funs(
             }
         };
 
-        assert!(old.is_none());
+        // Source functions can override builtins, but not other source functions.
+        assert!(!matches!(
+            old,
+            Some(Fun {
+                kind: FunKind::Source(_),
+                ..
+            })
+        ));
     }
 
     (top_level_funs, associated_funs)
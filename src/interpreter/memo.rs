@@ -0,0 +1,101 @@
+//! Result caches of memoized functions (`memo fn`).
+//!
+//! The interpreter can't check that a function is pure, so only the functions declared with `memo`
+//! are cached. Arguments are compared with structural equality, so a memoized function shouldn't
+//! mutate its arguments or results, and callers shouldn't mutate them after the call.
+//!
+//! Caches are not evicted: a result stays in the cache until the program exits.
+
+use crate::interpreter::*;
+
+use std::hash::{Hash, Hasher};
+
+#[derive(Debug, Default)]
+pub struct MemoCache {
+    /// Maps functions (type name for associated functions, function name) to hashes of arguments
+    /// to the calls with the hashes.
+    funs: Map<(Option<SmolStr>, SmolStr), Map<u64, Vec<MemoEntry>>>,
+}
+
+#[derive(Debug)]
+struct MemoEntry {
+    /// Arguments of the call, `None` for omitted arguments.
+    args: Vec<Option<u64>>,
+    result: u64,
+}
+
+impl MemoCache {
+    pub fn get(
+        &self,
+        pgm: &Pgm,
+        heap: &Heap,
+        fun: &ast::FunDecl,
+        args: &[Option<u64>],
+    ) -> Option<u64> {
+        let calls = self
+            .funs
+            .get(&(fun.type_name.clone(), fun.name.clone()))?
+            .get(&hash_args(heap, args))?;
+        calls
+            .iter()
+            .find(|entry| {
+                entry
+                    .args
+                    .iter()
+                    .zip(args)
+                    .all(|(arg1, arg2)| match (arg1, arg2) {
+                        (Some(arg1), Some(arg2)) => structural_eq(pgm, heap, *arg1, *arg2),
+                        (None, None) => true,
+                        _ => false,
+                    })
+            })
+            .map(|entry| entry.result)
+    }
+
+    pub fn insert(&mut self, heap: &Heap, fun: &ast::FunDecl, args: Vec<Option<u64>>, result: u64) {
+        self.funs
+            .entry((fun.type_name.clone(), fun.name.clone()))
+            .or_default()
+            .entry(hash_args(heap, &args))
+            .or_default()
+            .push(MemoEntry { args, result });
+    }
+}
+
+/// Hash of the arguments, consistent with `structural_eq`: only the tags and the immediate values
+/// (integers, characters, string contents) are hashed, fields of objects are compared when
+/// looking up the calls with the hash.
+fn hash_args(heap: &Heap, args: &[Option<u64>]) -> u64 {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    for arg in args {
+        arg.is_some().hash(&mut hasher);
+        if let Some(arg) = arg {
+            hash_value(heap, *arg, &mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+/// Hash of a value, consistent with `structural_eq`, and with `eq` for the values of types without
+/// a user-defined `__eq`.
+pub fn hash_value<H: Hasher>(heap: &Heap, value: u64, hasher: &mut H) {
+    // Unit.
+    if value == 0 {
+        0u64.hash(hasher);
+        return;
+    }
+
+    let tag = heap.header(value);
+    match tag {
+        // `Str` and `StrView` with the same contents are equal, so the tag is not hashed.
+        STR_TYPE_TAG => heap.str_bytes(value).hash(hasher),
+        STR_VIEW_TYPE_TAG => heap.str_view_bytes(value).hash(hasher),
+
+        I32_TYPE_TAG | CHAR_TYPE_TAG | F64_TYPE_TAG | CONSTR_TYPE_TAG | TOP_FUN_TYPE_TAG => {
+            tag.hash(hasher);
+            heap[value + 1].hash(hasher);
+        }
+
+        _ => tag.hash(hasher),
+    }
+}
@@ -0,0 +1,4119 @@
+use crate::ast;
+use crate::lexer::lex;
+use crate::parser::TopDeclsParser;
+use crate::scanner::scan;
+
+use indoc::indoc;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+fn parse(module: &str, pgm: &str) -> ast::Module {
+    TopDeclsParser::new()
+        .parse(&module.into(), scan(lex(pgm)))
+        .unwrap()
+}
+
+/// Runs the program with the prelude and returns the standard output. Panics if the program
+/// returns an error.
+fn run(pgm: &str, input: &str) -> String {
+    let (out, result) = try_run(pgm, input);
+    if let Err(err) = result {
+        panic!("{}", err);
+    }
+    out
+}
+
+/// Runs the program with the prelude and returns the standard output and the result.
+fn try_run(pgm: &str, input: &str) -> (String, Result<(), super::RunError>) {
+    let mut module = parse("Prelude", include_str!("../../examples/Prelude.fir"));
+    module.extend(parse("Test", pgm));
+    let mut out: Vec<u8> = vec![];
+    let result = super::run(&mut out, module, input, &Default::default());
+    (String::from_utf8(out).unwrap(), result)
+}
+
+#[test]
+fn tail_call_self_recursion() {
+    let pgm = indoc! {"
+        fn count(n: I32, acc: I32): I32 =
+            if n == 0:
+                return acc
+            return count(n - 1, acc + 1)
+
+        fn main(input: Str) =
+            printStr(count(1000000, 0).toStr())
+    "};
+    assert_eq!(run(pgm, ""), "1000000\n");
+}
+
+#[test]
+fn tail_call_evaluates_args_before_rebinding() {
+    // Arguments refer to the current parameters, which must not be overwritten until all of the
+    // arguments are evaluated.
+    let pgm = indoc! {"
+        fn swap(n: I32, a: I32, b: I32): I32 =
+            if n == 0:
+                return a * 10 + b
+            return swap(n - 1, b, a)
+
+        fn main(input: Str) =
+            printStr(swap(3, 1, 2).toStr())
+    "};
+    assert_eq!(run(pgm, ""), "21\n");
+}
+
+#[test]
+fn dump_heap() {
+    let pgm = indoc! {"
+        type Pair:
+            first: I32
+            second: Str
+
+        fn main(input: Str) =
+            let pair = Pair(first = 1, second = \"a\")
+            dumpHeap(Option.Some(pair))
+    "};
+    // Addresses depend on allocation order of the prelude, replace them with `@_`.
+    let out: String = run(pgm, "")
+        .split('@')
+        .enumerate()
+        .map(|(i, part)| {
+            if i == 0 {
+                part.to_string()
+            } else {
+                format!(
+                    "@_{}",
+                    part.trim_start_matches(|c: char| c.is_ascii_digit())
+                )
+            }
+        })
+        .collect();
+    assert_eq!(
+        out,
+        indoc! {"
+            @_ Option.Some
+                0 -> @_
+            @_ Pair.Pair
+                first -> @_
+                second -> @_
+            @_ I32 1
+            @_ Str \"a\"
+        "}
+    );
+}
+
+#[test]
+fn array_map_filter_fold() {
+    let pgm = indoc! {"
+        fn double(i: I32): I32 =
+            i * 2
+
+        fn isBig(i: I32): Bool =
+            i > 2
+
+        fn add(acc: I32, i: I32): I32 =
+            acc + i
+
+        fn printArray(array: Array[I32]) =
+            for i in 0 .. array.len():
+                printStr(array.get(i).toStr())
+
+        fn main(input: Str) =
+            let array = Array.new(3)
+            array.set(0, 1)
+            array.set(1, 2)
+            array.set(2, 3)
+
+            let doubled = array.map(double)
+            printArray(doubled)
+
+            printStr(\"---\")
+            printArray(doubled.filter(isBig))
+
+            printStr(\"---\")
+            printStr(array.fold(10, add).toStr())
+
+            # Source array is not modified.
+            printStr(\"---\")
+            printArray(array)
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            2
+            4
+            6
+            ---
+            4
+            6
+            ---
+            16
+            ---
+            1
+            2
+            3
+        "}
+    );
+}
+
+#[test]
+fn to_str_fallback() {
+    let pgm = indoc! {"
+        type Point:
+            x: I32
+            y: I32
+
+        type Shape:
+            Circle(I32)
+            Rect:
+                corner: Point
+                label: Str
+            Empty
+
+        type Celsius:
+            degrees: I32
+
+        fn Celsius.toStr(self): Str =
+            \"$(self.degrees)C\"
+
+        fn main(input: Str) =
+            let point = Point(x = 1, y = 2)
+            printStr(\"$(point)\")
+            let circle = Shape.Circle(3)
+            printStr(\"$(circle)\")
+            let rect = Shape.Rect(corner = point, label = \"a\")
+            printStr(\"$(rect)\")
+            printStr(\"$(Shape.Empty)\")
+            let record = (a = 1, b = \"b\")
+            printStr(\"$(record)\")
+            printStr(\"$(Celsius(degrees = 20))\")
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            Point(x = 1, y = 2)
+            Shape.Circle(3)
+            Shape.Rect(corner = Point(x = 1, y = 2), label = \"a\")
+            Shape.Empty
+            (a = 1, b = \"b\")
+            20C
+        "}
+    );
+}
+
+#[test]
+fn named_args() {
+    let pgm = indoc! {"
+        type Point:
+            x: I32
+            y: I32
+
+        fn sub(x: I32, y: I32): I32 =
+            x - y
+
+        fn Point.offset(self, dx: I32, dy: I32): Point =
+            Point(x = self.x + dx, y = self.y + dy)
+
+        fn Point.make(x: I32, y: I32): Point =
+            Point(x = x, y = y)
+
+        fn countDown(n: I32, acc: I32): I32 =
+            if n == 0:
+                return acc
+            return countDown(acc = acc + n, n = n - 1)
+
+        fn main(input: Str) =
+            printStr(sub(y = 1, x = 10).toStr())
+            let p = Point.make(y = 2, x = 1).offset(dy = 10, dx = 20)
+            printStr(\"$(p)\")
+            printStr(countDown(acc = 0, n = 4).toStr())
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            9
+            Point(x = 21, y = 12)
+            10
+        "}
+    );
+}
+
+#[test]
+fn named_args_errors() {
+    let pgm = indoc! {"
+        fn sub(x: I32, y: I32): I32 =
+            x - y
+
+        fn main(input: Str) =
+            sub(1, x = 2)
+    "};
+    let (_, result) = try_run(pgm, "");
+    let err = result.unwrap_err();
+    assert!(
+        matches!(err, super::RunError::MixedArgs { .. }),
+        "{:?}",
+        err
+    );
+    assert_eq!(
+        err.to_string(),
+        "Test:5:5: named and positional arguments cannot be mixed"
+    );
+
+    let pgm = indoc! {"
+        fn sub(x: I32, y: I32): I32 =
+            x - y
+
+        fn main(input: Str) =
+            sub(x = 1, z = 2)
+    "};
+    let (_, result) = try_run(pgm, "");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:5:5: sub does not have parameter z"
+    );
+
+    let pgm = indoc! {"
+        fn sub(x: I32, y: I32): I32 =
+            x - y
+
+        fn main(input: Str) =
+            sub(x = 1, x = 2)
+    "};
+    let (_, result) = try_run(pgm, "");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:5:5: argument x of sub is given more than once"
+    );
+
+    let pgm = indoc! {"
+        type Point:
+            x: I32
+
+        fn Point.offset(self, dx: I32, dy: I32 = 0): Point =
+            Point(x = self.x + dx)
+
+        fn main(input: Str) =
+            Point(x = 1).offset(dy = 2)
+    "};
+    let (_, result) = try_run(pgm, "");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:8:5: argument dx of Point.offset is not passed"
+    );
+
+    let pgm = indoc! {"
+        fn sub(x: I32, y: I32): I32 =
+            x - y
+
+        fn main(input: Str) =
+            sub(1)
+    "};
+    let (_, result) = try_run(pgm, "");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:5:5: argument y of sub is not passed"
+    );
+
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            printStr(s = input)
+    "};
+    let (_, result) = try_run(pgm, "");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:2:5: built-in functions cannot be called with named arguments"
+    );
+}
+
+#[test]
+fn default_params() {
+    let pgm = indoc! {"
+        fn range(start: I32, end: I32 = start + 10, step: I32 = 1): Str =
+            \"$(start) $(end) $(step)\"
+
+        fn Str.pad(self, width: I32 = 4): I32 =
+            width - self.len()
+
+        fn main(input: Str) =
+            printStr(range(1))
+            printStr(range(1, 2))
+            printStr(range(1, 2, 3))
+            printStr(range(start = 5, step = 2))
+            printStr(range(step = 3, start = 0))
+            printStr(\"ab\".pad().toStr())
+            printStr(\"ab\".pad(width = 10).toStr())
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            1 11 1
+            1 2 1
+            1 2 3
+            5 15 2
+            0 10 3
+            2
+            8
+        "}
+    );
+}
+
+#[test]
+fn default_params_unit_arg() {
+    // Unit arguments are passed, not replaced with the defaults.
+    let pgm = indoc! {"
+        fn nothing() =
+            while Bool.False:
+                ()
+
+        fn f(u: (), n: I32 = 1): I32 =
+            n
+
+        fn g(u: () = printStr(\"g default\")): I32 =
+            2
+
+        memo fn h(u: () = printStr(\"h default\")): I32 =
+            3
+
+        fn main(input: Str) =
+            printStr(f(nothing()).toStr())
+            printStr(f(u = nothing()).toStr())
+            printStr(g(nothing()).toStr())
+            printStr(g().toStr())
+            printStr(h(nothing()).toStr())
+            printStr(h().toStr())
+            printStr(h(nothing()).toStr())
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            1
+            1
+            2
+            g default
+            2
+            3
+            h default
+            3
+            3
+        "}
+    );
+}
+
+#[test]
+fn option_result_methods() {
+    let pgm = indoc! {"
+        fn double(i: I32): I32 =
+            i * 2
+
+        fn main(input: Str) =
+            let some: Option[I32] = Option.Some(1)
+            let none: Option[I32] = Option.None
+            printStr(some.unwrap().toStr())
+            printStr(some.unwrapOr(5).toStr())
+            printStr(none.unwrapOr(5).toStr())
+            printStr(\"$(some.map(double))\")
+            printStr(\"$(none.map(double))\")
+
+            let ok: Result[Str, I32] = Result.Ok(1)
+            let err: Result[Str, I32] = Result.Err(\"oops\")
+            printStr(ok.unwrap().toStr())
+            printStr(err.unwrapOr(5).toStr())
+            printStr(\"$(ok.map(double))\")
+            printStr(\"$(err.map(double))\")
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            1
+            1
+            5
+            Option.Some(2)
+            Option.None
+            1
+            5
+            Result.Ok(2)
+            Result.Err(\"oops\")
+        "}
+    );
+}
+
+#[test]
+fn option_result_combinators() {
+    let pgm = indoc! {r#"
+        fn halve(i: I32): Option[I32] =
+            printStr("halve($(i))")
+            if i % 2 == 0:
+                Option.Some(i / 2)
+            else:
+                Option.None
+
+        fn checkedHalve(i: I32): Result[Str, I32] =
+            printStr("checkedHalve($(i))")
+            if i % 2 == 0:
+                Result.Ok(i / 2)
+            else:
+                Result.Err("$(i) is odd")
+
+        fn fallback(): Option[I32] =
+            printStr("fallback")
+            Option.Some(0)
+
+        fn default(): I32 =
+            printStr("default")
+            0
+
+        fn main(input: Str) =
+            let some: Option[I32] = Option.Some(12)
+            printStr("$(some.andThen(halve).andThen(halve))")
+            printStr("$(some.andThen(halve).andThen(halve).andThen(halve).andThen(halve))")
+            printStr("$(some.orElse(fallback)) $(some.getOrElse(default))")
+            let none = some.andThen(halve).andThen(halve).andThen(halve)
+            printStr("$(none.orElse(fallback))")
+            printStr("$(none.getOrElse(default))")
+
+            let ok: Result[Str, I32] = Result.Ok(12)
+            printStr("$(ok.andThen(checkedHalve).andThen(checkedHalve))")
+            printStr("$(ok.andThen(checkedHalve).andThen(checkedHalve).andThen(checkedHalve).andThen(checkedHalve))")
+    "#};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {r#"
+            halve(12)
+            halve(6)
+            Option.Some(3)
+            halve(12)
+            halve(6)
+            halve(3)
+            Option.None
+            Option.Some(12) 12
+            halve(12)
+            halve(6)
+            halve(3)
+            fallback
+            Option.Some(0)
+            default
+            0
+            checkedHalve(12)
+            checkedHalve(6)
+            Result.Ok(3)
+            checkedHalve(12)
+            checkedHalve(6)
+            checkedHalve(3)
+            Result.Err("3 is odd")
+        "#}
+    );
+}
+
+#[test]
+fn option_unwrap_none() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let none: Option[I32] = Option.None
+            printStr(\"hi\")
+            printStr(\"hi\")
+            none.unwrap()
+    "};
+    let (_, result) = try_run(pgm, "");
+    let err = result.unwrap_err();
+    assert!(matches!(err, super::RunError::InvalidArgument { .. }));
+    assert_eq!(
+        err.to_string(),
+        "Test:5:5: Option.unwrap called on Option.None"
+    );
+}
+
+#[test]
+fn for_iter_protocol() {
+    let pgm = indoc! {"
+        type Countdown:
+            n: I32
+
+        type CountdownIter:
+            n: Array[I32]
+
+        fn Countdown.iter(self): CountdownIter =
+            let n = Array.new(1)
+            n.set(0, self.n)
+            CountdownIter(n = n)
+
+        fn CountdownIter.next(self): Option[I32] =
+            let n = self.n.get(0)
+            if n == 0:
+                return Option.None
+            self.n.set(0, n - 1)
+            Option.Some(n)
+
+        fn main(input: Str) =
+            for i in Countdown(n = 3):
+                printStr(i.toStr())
+
+            let array = Array.new(2)
+            array.set(0, \"a\")
+            array.set(1, \"b\")
+            for s in array:
+                printStr(s)
+
+            let iter = array.iter()
+            printStr(\"$(iter.next())\")
+            printStr(\"$(iter.next())\")
+            printStr(\"$(iter.next())\")
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            3
+            2
+            1
+            a
+            b
+            Option.Some(\"a\")
+            Option.Some(\"b\")
+            Option.None
+        "}
+    );
+}
+
+#[test]
+fn range_step() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            for i in (0 .. 10).step(3):
+                printStr(i.toStr())
+            printStr(\"---\")
+            for i in (6 .. 0).step(0 - 2):
+                printStr(i.toStr())
+            printStr(\"---\")
+            let iter = (3 .. 0).step(0 - 1).iter()
+            printStr(\"$(iter.next())\")
+            printStr(\"$(iter.next())\")
+            printStr(\"$(iter.next())\")
+            printStr(\"$(iter.next())\")
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            0
+            3
+            6
+            9
+            ---
+            6
+            4
+            2
+            ---
+            Option.Some(3)
+            Option.Some(2)
+            Option.Some(1)
+            Option.None
+        "}
+    );
+}
+
+#[test]
+fn range_step_zero() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            for i in (0 .. 10).step(0):
+                printStr(i.toStr())
+    "};
+    let (_, result) = try_run(pgm, "");
+    let err = result.unwrap_err();
+    assert!(matches!(err, super::RunError::InvalidArgument { .. }));
+    assert_eq!(err.to_string(), "Test:2:14: Range step cannot be 0");
+}
+
+#[test]
+fn loc_display() {
+    let loc = ast::Loc {
+        module: "Test".into(),
+        line_start: 0,
+        col_start: 4,
+        byte_offset_start: 4,
+        line_end: 1,
+        col_end: 2,
+        byte_offset_end: 12,
+    };
+    assert_eq!(super::LocDisplay(&loc).to_string(), "Test:1:5");
+    assert_eq!(format!("{:#}", super::LocDisplay(&loc)), "Test:1:5-2:3");
+    assert_eq!(super::LocDisplay(&loc).without_module().to_string(), "1:5");
+    assert_eq!(
+        format!("{:#}", super::LocDisplay(&loc).without_module()),
+        "1:5-2:3"
+    );
+}
+
+#[test]
+fn user_panic() {
+    let pgm = indoc! {"
+        fn check(i: I32) =
+            if i > 2:
+                panic(\"too big: $(i)\")
+            printStr(i.toStr())
+
+        fn main(input: Str) =
+            for i in 0 .. 5:
+                check(i)
+    "};
+    let (out, result) = try_run(pgm, "");
+    assert_eq!(out, "0\n1\n2\n");
+    let err = result.unwrap_err();
+    assert!(matches!(&err, super::RunError::UserPanic { msg, .. } if msg == "too big: 3"));
+    assert_eq!(err.to_string(), "Test:3:9: PANIC: too big: 3");
+
+    // The message can be a `StrView`.
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            match input:
+                \"a\" rest: panic(rest)
+                _: ()
+    "};
+    let (_, result) = try_run(pgm, "abc");
+    assert_eq!(result.unwrap_err().to_string(), "Test:3:19: PANIC: bc");
+
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            panic(1)
+    "};
+    let (_, result) = try_run(pgm, "");
+    let err = result.unwrap_err();
+    assert!(matches!(&err, super::RunError::TypeError { .. }));
+    assert_eq!(
+        err.to_string(),
+        "Test:2:5: type error: panic message is not a string"
+    );
+}
+
+#[test]
+fn assert() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            assert(1 == 1)
+            assert(1 == 1, \"unused\")
+            printStr(\"ok\")
+            assert(input == \"a\")
+    "};
+    assert_eq!(run(pgm, "a"), "ok\n");
+    let (out, result) = try_run(pgm, "b");
+    assert_eq!(out, "ok\n");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:5:5: assertion failed"
+    );
+
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            assert(input == \"a\", \"input is not a\")
+    "};
+    let (_, result) = try_run(pgm, "b");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:2:5: assertion failed: input is not a"
+    );
+}
+
+#[test]
+fn throw_catch() {
+    let pgm = indoc! {"
+        fn parseDigit(s: Str): I32 =
+            if s == \"0\":
+                return 0
+            if s == \"1\":
+                return 1
+            throw \"not a digit: $(s)\"
+
+        fn parseBits(a: Str, b: Str): I32 =
+            parseDigit(a) * 2 + parseDigit(b)
+
+        fn tryParseBits(a: Str, b: Str): Str =
+            try:
+                let i = parseBits(a, b)
+                i.toStr()
+            catch err:
+                err
+
+        fn main(input: Str) =
+            printStr(tryParseBits(\"1\", \"0\"))
+            printStr(tryParseBits(\"1\", \"x\"))
+            try:
+                throw Option.Some(1)
+                printStr(\"unreachable\")
+            catch value:
+                printStr(\"$(value)\")
+            parseBits(\"0\", \"y\")
+    "};
+    let (out, result) = try_run(pgm, "");
+    assert_eq!(
+        out,
+        indoc! {"
+            2
+            not a digit: x
+            Option.Some(1)
+        "}
+    );
+    let err = result.unwrap_err();
+    assert!(
+        matches!(&err, super::RunError::UncaughtThrow { value, .. } if value == "\"not a digit: y\"")
+    );
+    assert_eq!(
+        err.to_string(),
+        "Test:6:11: uncaught throw: \"not a digit: y\""
+    );
+}
+
+#[test]
+fn throw_catch_tail_call() {
+    // `return thrower()` is a tail call, the `try` still needs to catch the throw.
+    let pgm = indoc! {"
+        fn thrower(): I32 =
+            throw 42
+
+        fn tryThrower(): I32 =
+            try:
+                return thrower()
+            catch err:
+                err + 1
+
+        fn main(input: Str) =
+            printStr(tryThrower().toStr())
+    "};
+    assert_eq!(run(pgm, ""), "43\n");
+}
+
+#[test]
+fn tuple_return_destructure() {
+    let pgm = indoc! {"
+        fn divMod(a: I32, b: I32): (I32, I32) =
+            let q = 0
+            while a >= b:
+                a = a - b
+                q = q + 1
+            return (q, a)
+
+        fn nested(): (Str, (I32, I32)) =
+            (\"a\", divMod(7, 2))
+
+        fn swap(pair: (I32, I32)): (I32, I32) =
+            let (a, b) = pair
+            (b, a)
+
+        fn main(input: Str) =
+            let (q, r) = divMod(17, 5)
+            printStr(\"$(q) $(r)\")
+
+            let (s, (q, r)) = nested()
+            printStr(\"$(s) $(q) $(r)\")
+
+            let (x, _) = swap(divMod(9, 4))
+            printStr(x.toStr())
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            3 2
+            a 3 1
+            1
+        "}
+    );
+}
+
+#[test]
+fn div_mod() {
+    let pgm = indoc! {"
+        fn show(a: I32, b: I32) =
+            let q = a / b
+            let r = a % b
+            printStr(\"$(q) $(r)\")
+
+        fn main(input: Str) =
+            show(7, 2)
+            show(0 - 7, 2)
+            show(7, 0 - 2)
+            show(0 - 7, 0 - 2)
+            printStr((1 + 10 / 3 * 2 % 4).toStr())
+            show(1, 0)
+    "};
+    let (out, result) = try_run(pgm, "");
+    assert_eq!(
+        out,
+        indoc! {"
+            3 1
+            -3 -1
+            -3 1
+            3 -1
+            3
+        "}
+    );
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:2:13: division by zero"
+    );
+}
+
+#[test]
+fn bitwise() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            printStr(12.bitAnd(10).toStr())
+            printStr(12.bitOr(10).toStr())
+            printStr(12.bitXor(10).toStr())
+            printStr(0.bitNot().toStr())
+            printStr(1.shl(4).toStr())
+            printStr(1.shl(33).toStr())
+            printStr((0 - 16).shr(2).toStr())
+            printStr(256.shr(36).toStr())
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            8
+            14
+            6
+            -1
+            16
+            2
+            -4
+            16
+        "}
+    );
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "Dereferencing heap address 0")]
+fn unit_dereference() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let unit = printStr(input)
+            unit.len()
+    "};
+    run(pgm, "");
+}
+
+#[test]
+fn bool_methods() {
+    let pgm = indoc! {"
+        fn loud(b: Bool): Bool =
+            printStr(\"evaluated\")
+            b
+
+        fn main(input: Str) =
+            let t = 1 == 1
+            let f = 1 == 2
+            printStr(\"$(t) $(f)\")
+            printStr(\"$(t == t) $(t == f) $(t != f)\")
+            printStr(\"$(t && f) $(t || f)\")
+            printStr((f && loud(t)).toStr())
+            printStr((t || loud(f)).toStr())
+            printStr((t && loud(f)).toStr())
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            True False
+            True False True
+            False True
+            False
+            True
+            evaluated
+            False
+        "}
+    );
+}
+
+#[test]
+fn break_continue() {
+    let pgm = indoc! {"
+        fn findFirstBig(array: Array[I32]): I32 =
+            let i = 0
+            while i < array.len():
+                let elem = array.get(i)
+                i = i + 1
+                if elem < 10:
+                    continue
+                break elem
+
+        fn main(input: Str) =
+            let array = Array.new(4)
+            array.set(0, 1)
+            array.set(1, 20)
+            array.set(2, 3)
+            array.set(3, 40)
+            printStr(findFirstBig(array).toStr())
+
+            for i in 0 .. 10:
+                if i % 2 == 0:
+                    continue
+                if i > 6:
+                    break
+                printStr(i.toStr())
+
+            let sum = 0
+            for elem in array:
+                if elem > 30:
+                    break
+                sum = sum + elem
+            printStr(sum.toStr())
+
+            # `while` is a statement, so a loop produces a value as the last statement of a
+            # function body or an `if` or `match` branch.
+            let i = 0
+            let found = if array.len() > 0:
+                while i < array.len():
+                    let elem = array.get(i)
+                    i = i + 1
+                    if elem > 30:
+                        break elem
+            else:
+                0
+            printStr(found.toStr())
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            20
+            1
+            3
+            5
+            24
+            40
+        "}
+    );
+}
+
+#[test]
+fn array_literal() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let array = [1, 2, 3]
+            printStr(array.len().toStr())
+            printStr(array[0].toStr())
+            printStr(array[2].toStr())
+
+            let empty: Array[I32] = []
+            printStr(empty.len().toStr())
+
+            let points = [(x = 1, y = 2), (x = 3, y = 4)]
+            printStr(points[1].x.toStr())
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            3
+            1
+            3
+            0
+            3
+        "}
+    );
+}
+
+#[test]
+fn structural_eq() {
+    let pgm = indoc! {"
+        type Point:
+            x: I32
+            y: I32
+
+        fn main(input: Str) =
+            printStr((Point(x = 1, y = 2) == Point(x = 1, y = 2)).toStr())
+            printStr((Point(x = 1, y = 2) == Point(x = 1, y = 3)).toStr())
+            printStr(((a = 1, b = [1, 2]) == (a = 1, b = [1, 2])).toStr())
+            printStr(structuralEq([1, 2], [1, 2, 3]).toStr())
+            printStr(structuralEq(\"abc\", \"xabcx\".substr(1, 4)).toStr())
+
+            let a = Array.new(1)
+            a.set(0, a)
+            let b = Array.new(1)
+            b.set(0, b)
+            printStr(structuralEq(a, b).toStr())
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            True
+            False
+            True
+            False
+            True
+            True
+        "}
+    );
+}
+
+#[test]
+fn record_default_cmp() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            printStr(((1, 2) < (1, 3)).toStr())
+            printStr(((2, 0) > (1, 9)).toStr())
+            printStr(((1, 2) <= (1, 2)).toStr())
+            printStr(((1, (2, 3)) < (1, (2, 1))).toStr())
+
+            # Named fields are compared in sorted-name order.
+            printStr(((b = 1, a = 2) < (a = 1, b = 5)).toStr())
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            True
+            True
+            True
+            False
+            False
+        "}
+    );
+}
+
+#[test]
+fn array_sort() {
+    let pgm = indoc! {"
+        fn cmpKeys(a: (key: I32, value: I32), b: (key: I32, value: I32)): Ordering =
+            a.key.__cmp(b.key)
+
+        fn main(input: Str) =
+            let array = [3, 0 - 1, 2, 10, 0]
+            array.sort()
+            printStr(\"$(array)\")
+
+            # Sort is stable: records with equal keys stay in the original order.
+            let records = [(key = 2, value = 1), (key = 1, value = 2), (key = 2, value = 3), (key = 1, value = 4)]
+            records.sortBy(cmpKeys)
+            printStr(\"$(records)\")
+
+            records.sort()
+            printStr(\"$(records)\")
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            [-1, 0, 2, 3, 10]
+            [(key = 1, value = 2), (key = 1, value = 4), (key = 2, value = 1), (key = 2, value = 3)]
+            [(key = 1, value = 2), (key = 1, value = 4), (key = 2, value = 1), (key = 2, value = 3)]
+        "}
+    );
+}
+
+#[test]
+fn constr_arity_error() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            Option.Some(1, 2)
+    "};
+    let (_, result) = try_run(pgm, "");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:2:5: constructor Option.Some takes 1 arguments, but 2 were given"
+    );
+
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let some = Option.Some
+            some()
+    "};
+    let (_, result) = try_run(pgm, "");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:3:5: constructor Option.Some takes 1 arguments, but 0 were given"
+    );
+}
+
+#[test]
+fn named_field_errors() {
+    let pgm = indoc! {"
+        type Point:
+            x: I32
+            y: I32
+
+        fn main(input: Str) =
+            Point(x = 1)
+    "};
+    let (_, result) = try_run(pgm, "");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:6:5: Point is missing field y"
+    );
+
+    let pgm = indoc! {"
+        type Point:
+            x: I32
+            y: I32
+
+        fn main(input: Str) =
+            Point(x = 1, y = 2, z = 3)
+    "};
+    let (_, result) = try_run(pgm, "");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:6:5: Point does not have field z"
+    );
+
+    let pgm = indoc! {"
+        type Point:
+            x: I32
+            y: I32
+
+        fn main(input: Str) =
+            Point(x = 1, x = 2)
+    "};
+    let (_, result) = try_run(pgm, "");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:6:5: field x of Point is given more than once"
+    );
+
+    let pgm = indoc! {"
+        type Point:
+            x: I32
+            y: I32
+
+        fn main(input: Str) =
+            Point(1, 2)
+    "};
+    let (_, result) = try_run(pgm, "");
+    let err = result.unwrap_err();
+    assert!(matches!(&err, super::RunError::PositionalFields { constr, .. } if constr == "Point"));
+    assert_eq!(
+        err.to_string(),
+        "Test:6:5: Point has named fields, but was given positional arguments"
+    );
+
+    let pgm = indoc! {"
+        type Pair:
+            Pair(I32, I32)
+
+        fn main(input: Str) =
+            Pair.Pair(a = 1, b = 2)
+    "};
+    let (_, result) = try_run(pgm, "");
+    let err = result.unwrap_err();
+    assert!(matches!(&err, super::RunError::UnknownField { field, .. } if field == "a"));
+    assert_eq!(err.to_string(), "Test:5:5: Pair does not have field a");
+
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            (a = 1, b = 2, a = 3)
+    "};
+    let (_, result) = try_run(pgm, "");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:2:5: field a of record is given more than once"
+    );
+
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            (a = 1, 2)
+    "};
+    let (_, result) = try_run(pgm, "");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:2:5: record has both named and unnamed fields"
+    );
+
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            (1, b = 2)
+    "};
+    let (_, result) = try_run(pgm, "");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:2:5: record has both named and unnamed fields"
+    );
+}
+
+#[test]
+fn str_chars() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let n = 0
+            for char in input.chars():
+                printStr(char.toStr())
+                n = n + 1
+            printStr(n.toStr())
+            printStr(input.len().toStr())
+    "};
+    assert_eq!(
+        run(pgm, "héllo"),
+        indoc! {"
+            h
+            é
+            l
+            l
+            o
+            5
+            6
+        "}
+    );
+}
+
+#[test]
+fn str_byte_and_char_len() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            printStr(input.byteLen().toStr())
+            printStr(input.charLen().toStr())
+            printStr(input.byteAt(0).toStr())
+            printStr(input.byteAt(1).toStr())
+            printStr(input.byteAt(2).toStr())
+    "};
+    assert_eq!(
+        run(pgm, "héllo"),
+        indoc! {"
+            6
+            5
+            104
+            195
+            169
+        "}
+    );
+}
+
+#[test]
+fn str_pfx_pat_bind_prefix() {
+    let pgm = indoc! {"
+        fn keyword(s: StrView): StrView =
+            match s:
+                \"let\" as kw rest: kw
+                \"fn\" as kw rest: kw
+                _: \"none\".substr(0, 4)
+
+        fn main(input: Str) =
+            match input:
+                \"fn\" as kw rest:
+                    printStrView(kw)
+                    printStrView(rest)
+                    printStrView(keyword(rest))
+                    printStrView(keyword(rest.substr(1, rest.len())))
+                _: printStr(\"no match\")
+    "};
+    assert_eq!(
+        run(pgm, "fn let x"),
+        indoc! {"
+            fn
+             let x
+            none
+            let
+        "}
+    );
+}
+
+#[test]
+fn format() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            printStr(format(\"{} of {}\", [1, 10]))
+            printStr(format(input, [(x = 1, y = 2), 3]))
+            printStr(format(\"{{}} {}\", [\"a\"]))
+    "};
+    assert_eq!(
+        run(pgm, "{}: {}"),
+        indoc! {"
+            1 of 10
+            (x = 1, y = 2): 3
+            {} a
+        "}
+    );
+
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            printStr(format(input, [1]))
+    "};
+    let (_, result) = try_run(pgm, "{} {}");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:2:14: format error: template has more placeholders than the 1 arguments"
+    );
+    let (_, result) = try_run(pgm, "");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:2:14: format error: template has 0 placeholders, but 1 arguments were given"
+    );
+    let (_, result) = try_run(pgm, "{");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:2:14: format error: unmatched '{' in template, use '{{' for a literal brace"
+    );
+}
+
+#[test]
+fn interpolation_format_spec() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let n = 42
+            printStr(\"[$(n:5)]\")
+            printStr(\"[$(n:05)]\")
+            printStr(\"[$(n:hex)]\")
+            printStr(\"[$(n:04hex)]\")
+            printStr(\"[$(n:bin)]\")
+            printStr(\"[$(0 - n:05)]\")
+            printStr(\"[$(input:5)]\")
+            printStr(\"[$(n:1)]\")
+    "};
+    assert_eq!(
+        run(pgm, "ab"),
+        indoc! {"
+            [   42]
+            [00042]
+            [2a]
+            [002a]
+            [101010]
+            [-0042]
+            [   ab]
+            [42]
+        "}
+    );
+
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            printStr(\"$(input:hex)\")
+    "};
+    let (_, result) = try_run(pgm, "ab");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:1:1: format error: radix format spec used with non-I32 value \"ab\""
+    );
+}
+
+#[test]
+fn heap_stats() {
+    let mut heap = super::heap::Heap::new();
+    assert_eq!(heap.stats().allocations, 0);
+    assert_eq!(heap.stats().bytes_allocated, 0);
+
+    heap.allocate_i32(1);
+    heap.allocate_str(b"abc");
+
+    let stats = heap.stats();
+    assert_eq!(stats.allocations, 2);
+    // I32: tag and value. Str: tag, length, one word for the bytes, and the ASCII flag.
+    assert_eq!(stats.bytes_allocated, 6 * 8);
+    assert_eq!(stats.peak_live_bytes, 6 * 8);
+    assert_eq!(stats.collections, 0);
+}
+
+#[test]
+fn alloc_profile() {
+    let loc = |line: u16| ast::Loc {
+        module: "Test".into(),
+        line_start: line,
+        col_start: 0,
+        byte_offset_start: u32::from(line) * 10,
+        line_end: line,
+        col_end: 5,
+        byte_offset_end: u32::from(line) * 10 + 5,
+    };
+
+    let mut heap = super::heap::Heap::new();
+
+    // Not counted when profiling is disabled.
+    heap.set_alloc_site(Some(loc(0)));
+    heap.allocate_i32(0);
+
+    heap.enable_alloc_profiling();
+    heap.set_alloc_site(Some(loc(1)));
+    heap.allocate_i32(1);
+    let parent = heap.set_alloc_site(Some(loc(2)));
+    heap.allocate_i32(2);
+    heap.allocate_i32(2);
+    heap.set_alloc_site(parent);
+    heap.allocate_i32(1);
+    heap.allocate_i32(1);
+    heap.set_alloc_site(None);
+    heap.allocate_i32(3);
+
+    assert_eq!(heap.alloc_profile(), vec![(loc(1), 3), (loc(2), 2)]);
+}
+
+#[test]
+fn array_contains_index_of() {
+    let pgm = indoc! {"
+        type Point:
+            x: I32
+            y: I32
+
+        # Points are compared by `x` only.
+        fn Point.__eq(self, other: Point): Bool =
+            self.x == other.x
+
+        fn main(input: Str) =
+            let array = [3, 1, 2, 1]
+            printStr(array.contains(2).toStr())
+            printStr(array.contains(5).toStr())
+            printStr(array.indexOf(1).toStr())
+            printStr(array.indexOf(5).toStr())
+
+            let points = [Point(x = 1, y = 2), Point(x = 3, y = 4)]
+            printStr(points.contains(Point(x = 3, y = 0)).toStr())
+            printStr(points.indexOf(Point(x = 3, y = 0)).toStr())
+            printStr(points.indexOf(Point(x = 2, y = 2)).toStr())
+    "};
+    assert_eq!(
+        run(pgm, "é"),
+        indoc! {"
+            True
+            False
+            1
+            -1
+            True
+            1
+            -1
+        "}
+    );
+}
+
+#[test]
+fn array_concat_reverse() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let array = [1, 2, 3]
+            let empty: Array[I32] = []
+            printStr(\"$(array.concat([4, 5]))\")
+            printStr(\"$(array.concat(empty))\")
+            printStr(\"$(empty.concat(array))\")
+            printStr(\"$(empty.concat(empty))\")
+            printStr(\"$(array.reverse())\")
+            printStr(\"$(empty.reverse())\")
+            printStr(\"$(array)\")
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            [1, 2, 3, 4, 5]
+            [1, 2, 3]
+            [1, 2, 3]
+            []
+            [3, 2, 1]
+            []
+            [1, 2, 3]
+        "}
+    );
+}
+
+#[test]
+fn negative_index() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let array = [1, 2, 3]
+            printStr(array[-1].toStr())
+            printStr(array[-3].toStr())
+            printStr(array[0].toStr())
+            printStr(input.byteAt(-1).toStr())
+            printStr((-array[1]).toStr())
+            printStr((1 - -2).toStr())
+    "};
+    assert_eq!(
+        run(pgm, "ab"),
+        indoc! {"
+            3
+            1
+            1
+            98
+            -2
+            3
+        "}
+    );
+}
+
+#[test]
+fn negative_index_oob() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let array = [1, 2, 3]
+            array[-4]
+    "};
+    let (_, result) = try_run(pgm, "");
+    let err = result.unwrap_err();
+    assert!(matches!(err, super::RunError::OutOfBounds { .. }));
+    assert_eq!(
+        err.to_string(),
+        "Test:3:5: OOB array access, len = 3, index = -1"
+    );
+}
+
+#[test]
+fn f64() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let one = 1.toF64()
+            let three = 3.toF64()
+            let third = one / three
+            printStr(third.toStr())
+            printStr((one + three * three - one).toStr())
+            printStr((-7).toF64().__div(2.toF64()).toI32().toStr())
+            printStr((three / 2.toF64()).toI32().toStr())
+
+            let inf = one / 0.toF64()
+            let nan = inf - inf
+            printStr(inf.toStr())
+            printStr(nan.toStr())
+            printStr(inf.toI32().toStr())
+            printStr(nan.toI32().toStr())
+
+            # NaN is equal to itself, and greater than infinity.
+            printStr((nan == nan).toStr())
+            printStr((nan > inf).toStr())
+            printStr((one < three).toStr())
+            printStr(\"$([three, one])\")
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            0.3333333333333333
+            9.0
+            -3
+            1
+            inf
+            NaN
+            2147483647
+            0
+            True
+            True
+            True
+            [3.0, 1.0]
+        "}
+    );
+}
+
+#[test]
+fn i32_overflow() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let max = 2147483647
+            let min = -max - 1
+            printStr(\"$(max.addChecked(1))\")
+            printStr(\"$(max.addChecked(-1))\")
+            printStr(\"$(min.addChecked(-1))\")
+            printStr(max.addSaturating(1).toStr())
+            printStr(min.addSaturating(-1).toStr())
+            printStr(max.addWrapping(1).toStr())
+            printStr(min.addWrapping(-1).toStr())
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            Option.None
+            Option.Some(2147483646)
+            Option.None
+            2147483647
+            -2147483648
+            -2147483648
+            2147483647
+        "}
+    );
+
+    for expr in ["max + 1", "min - 1", "max * 2", "-min"] {
+        let pgm = format!(
+            "fn main(input: Str) =\n    let max = 2147483647\n    let min = -max - 1\n    {}\n",
+            expr
+        );
+        let (_, result) = try_run(&pgm, "");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Test:4:5: integer overflow"
+        );
+    }
+}
+
+#[test]
+fn print_named_fields() {
+    let pgm = indoc! {"
+        type Point:
+            y: I32
+            x: I32
+
+        fn main(input: Str) =
+            print((c = 3, a = 1, b = 2))
+            print(Point(x = 10, y = 20))
+            print((z = \"z\", y = Point(y = 1, x = 2)))
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            Test:6:5: (a = 1, b = 2, c = 3)
+            Test:7:5: Point.Point(y = 20, x = 10)
+            Test:8:5: (y = Point(y = 1, x = 2), z = \"z\")
+        "}
+    );
+}
+
+#[test]
+fn match_guards() {
+    let pgm = indoc! {"
+        fn classify(opt: Option[I32]): Str =
+            match opt:
+                Option.Some(n) if n < 0: \"negative\"
+                Option.Some(n) if n == 0: \"zero\"
+                Option.Some(n): \"positive $(n)\"
+                Option.None: \"none\"
+
+        fn main(input: Str) =
+            printStr(classify(Option.Some(-1)))
+            printStr(classify(Option.Some(0)))
+            printStr(classify(Option.Some(5)))
+            printStr(classify(Option.None))
+
+            # Bindings of an alternative with a failing guard are discarded.
+            let n = 100
+            match Option.Some(1):
+                Option.Some(n) if n > 10: printStr(\"unreachable\")
+                _: printStr(n.toStr())
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            negative
+            zero
+            positive 5
+            none
+            100
+        "}
+    );
+}
+
+#[test]
+fn small_initial_heap() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let array = Array.new(100)
+            for i in 0 .. 100:
+                array.set(i, i * 2)
+            printStr(array.fold(0, add).toStr())
+
+        fn add(a: I32, b: I32): I32 =
+            a + b
+    "};
+    let mut module = parse("Prelude", include_str!("../../examples/Prelude.fir"));
+    module.extend(parse("Test", pgm));
+    let mut out: Vec<u8> = vec![];
+    let options = super::RunOptions {
+        initial_heap_size_words: 1,
+        ..Default::default()
+    };
+    super::run(&mut out, module, "", &options).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "9900\n");
+}
+
+#[test]
+fn nested_field_assignment() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let r = (a = (b = (c = 1)))
+            r.a.b.c = 10
+            r.a.b.c += 5
+            r.a.b.c -= 1
+            print(r)
+
+            let outer = (inner = (x = 0))
+            getInner(outer).x += 3
+            print(outer)
+
+        fn getInner(r: (inner: (x: I32))): (x: I32) =
+            printStr(\"getInner\")
+            r.inner
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            Test:6:5: (a = (b = (c = 14)))
+            getInner
+            Test:10:5: (inner = (x = 3))
+        "}
+    );
+}
+
+#[test]
+fn array_element_assignment() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let array = [1, 2, 3]
+            array[0] = 10
+            array[-1] -= 1
+            array[next()] += 5
+            printStr(\"$(array)\")
+
+        fn next(): I32 =
+            printStr(\"next\")
+            1
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            next
+            [10, 7, 2]
+        "}
+    );
+}
+
+#[test]
+fn compound_assignment() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let x = 7
+            x *= 6
+            printStr(\"$(x)\")
+            x /= 5
+            printStr(\"$(x)\")
+            x %= 5
+            printStr(\"$(x)\")
+
+            let r = (a = (b = -7))
+            r.a.b *= 3
+            printStr(\"$(r.a.b)\")
+            r.a.b /= 2
+            printStr(\"$(r.a.b)\")
+            r.a.b %= 4
+            printStr(\"$(r.a.b)\")
+
+            let array = [10, 20]
+            array[1] *= 2
+            array[1] /= 3
+            array[0] %= 4
+            printStr(\"$(array)\")
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            42
+            8
+            3
+            -21
+            -10
+            -2
+            [2, 13]
+        "}
+    );
+
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let x = 1
+            x /= 0
+    "};
+    let (_, result) = try_run(pgm, "");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:3:5: division by zero"
+    );
+
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let r = (a = 1)
+            r.a %= 0
+    "};
+    let (_, result) = try_run(pgm, "");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:3:5: division by zero"
+    );
+}
+
+#[test]
+fn set() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let set = Set.new()
+            for i in [3, 1, 3, 2, 1]:
+                set.add(i)
+            printStr(\"$(set) $(set.len())\")
+            printStr(\"$(set.contains(2)) $(set.contains(4))\")
+            printStr(\"$(set.add(5)) $(set.add(5))\")
+            printStr(\"$(set.remove(1)) $(set.remove(1))\")
+
+            # Iteration is in insertion order, over the elements at the start of the loop.
+            for i in set:
+                set.remove(i)
+                printStr(i.toStr())
+            printStr(set.len().toStr())
+
+            # Membership uses `__eq`.
+            let points = Set.new()
+            points.add(Point(x = 1, y = 2))
+            printStr(points.add(Point(x = 1, y = 2)).toStr())
+            printStr(points.contains(Point(x = 1, y = 2)).toStr())
+
+            let set1 = Set.new()
+            set1.add(1)
+            set1.add(2)
+            let set2 = Set.new()
+            set2.add(2)
+            set2.add(1)
+            printStr(structuralEq(set1, set2).toStr())
+
+        type Point:
+            x: I32
+            y: I32
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            Set[3, 1, 2] 3
+            True False
+            True False
+            True False
+            3
+            2
+            5
+            0
+            False
+            True
+            True
+        "}
+    );
+}
+
+#[test]
+fn array_join() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            printStr([1, 2, 3].join(\", \"))
+            printStr([\"a\", \"b\"].join(\"\"))
+            printStr([\"single\"].join(\", \"))
+            printStr(\"[$(Array.new(0).join(\", \"))]\")
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            1, 2, 3
+            ab
+            single
+            []
+        "}
+    );
+}
+
+#[test]
+fn invalid_utf8_str() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let s = input
+            let invalid = s.substr(0, 1).toStr()
+            printStr(s.isValidUtf8().toStr())
+            printStr(invalid.isValidUtf8().toStr())
+            printStr(invalid.byteLen().toStr())
+
+            # Interpolation replaces invalid UTF-8 with U+FFFD.
+            let replaced = \"<$(invalid)>\"
+            printStr(replaced)
+            printStr(replaced.isValidUtf8().toStr())
+    "};
+    assert_eq!(
+        run(pgm, "é"),
+        indoc! {"
+            True
+            False
+            1
+            <\u{FFFD}>
+            True
+        "}
+    );
+}
+
+#[test]
+fn trace_does_not_change_output() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let x = double(21)
+            printStr(x.toStr())
+
+        fn double(i: I32): I32 =
+            i * 2
+    "};
+    let mut module = parse("Prelude", include_str!("../../examples/Prelude.fir"));
+    module.extend(parse("Test", pgm));
+    let mut out: Vec<u8> = vec![];
+    let options = super::RunOptions {
+        trace: true,
+        ..Default::default()
+    };
+    super::run(&mut out, module, "", &options).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "42\n");
+}
+
+#[test]
+fn breakpoints() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let sum = 0
+            for i in 1 .. 4:
+                sum = sum + i
+            printStr(sum.toStr())
+    "};
+    let mut module = parse("Prelude", include_str!("../../examples/Prelude.fir"));
+    module.extend(parse("Test", pgm));
+
+    // Step once after the first pause, then continue to the next breakpoint.
+    let pauses: Rc<RefCell<Vec<String>>> = Default::default();
+    let pauses_ = pauses.clone();
+    let debugger = super::Debugger::new(
+        vec![
+            super::Breakpoint {
+                module: "Test".into(),
+                line: 2,
+            },
+            super::Breakpoint {
+                module: "Test".into(),
+                line: 5,
+            },
+        ],
+        move |state| {
+            let locals: Vec<String> = state
+                .locals()
+                .into_iter()
+                .map(|(var, value)| format!("{} = {}", var, value))
+                .collect();
+            let mut pauses = pauses_.borrow_mut();
+            pauses.push(format!(
+                "{}: {}",
+                super::LocDisplay(state.loc),
+                locals.join(", ")
+            ));
+            if pauses.len() == 1 {
+                super::DebugAction::Step
+            } else {
+                super::DebugAction::Continue
+            }
+        },
+    );
+
+    let mut out: Vec<u8> = vec![];
+    let options = super::RunOptions {
+        debugger: Some(Rc::new(RefCell::new(debugger))),
+        ..Default::default()
+    };
+    super::run(&mut out, module, "", &options).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "6\n");
+    assert_eq!(
+        *pauses.borrow(),
+        vec![
+            "Test:2:5: input = \"\"",
+            "Test:3:5: input = \"\", sum = 0",
+            "Test:5:5: input = \"\", sum = 6",
+        ]
+    );
+}
+
+#[test]
+fn static_and_instance_calls() {
+    let pgm = indoc! {"
+        type Counter:
+            n: I32
+
+        fn Counter.zero(): Counter =
+            Counter(n = 0)
+
+        fn Counter.next(self): I32 =
+            self.n + 1
+
+        fn main(input: Str) =
+            printStr(Counter.zero().next().toStr())
+    "};
+    assert_eq!(run(pgm, ""), "1\n");
+}
+
+#[test]
+fn static_fun_called_as_method() {
+    let pgm = indoc! {"
+        type Counter:
+            n: I32
+
+        fn Counter.zero(): Counter =
+            Counter(n = 0)
+
+        fn main(input: Str) =
+            Counter.zero().zero()
+    "};
+    let (_, result) = try_run(pgm, "");
+    let err = result.unwrap_err();
+    assert!(matches!(&err, super::RunError::NotAMethod { fun, .. } if fun == "Counter.zero"));
+    assert_eq!(
+        err.to_string(),
+        "Test:8:5: Counter.zero doesn't have a self parameter and cannot be called as a method"
+    );
+}
+
+#[test]
+fn method_called_on_type() {
+    let pgm = indoc! {"
+        type Counter:
+            n: I32
+
+        fn Counter.next(self): I32 =
+            self.n + 1
+
+        fn main(input: Str) =
+            Counter.next(Counter(n = 0))
+    "};
+    let (_, result) = try_run(pgm, "");
+    let err = result.unwrap_err();
+    assert!(
+        matches!(&err, super::RunError::MethodWithoutReceiver { fun, .. } if fun == "Counter.next")
+    );
+    assert_eq!(
+        err.to_string(),
+        "Test:8:5: Counter.next has a self parameter and needs to be called as a method"
+    );
+}
+
+#[test]
+fn constr_as_value() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let some = Option.Some
+            printStr(\"$([1, 2, 3].map(Option.Some))\")
+            print(some(4))
+            print(apply(Point.Point, 5, 6))
+
+        fn apply[F](f: F, a: I32, b: I32): Point =
+            f(a, b)
+
+        type Point:
+            x: I32
+            y: I32
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            [Option.Some(1), Option.Some(2), Option.Some(3)]
+            Test:4:5: Option.Some(4)
+            Test:5:5: Point.Point(x = 5, y = 6)
+        "}
+    );
+}
+
+#[test]
+fn builtin_fun_as_value() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            [\"a\", \"b\"].map(printStr)
+            let f = printStr
+            f(\"c\")
+            callWith(printStr, \"d\")
+            printStr(\"$([[1], [1, 2]].map(structuralEq2))\")
+
+        fn callWith[F](f: F, s: Str) =
+            f(s)
+
+        fn structuralEq2(a: Array[I32]): Bool =
+            let eq = structuralEq
+            eq(a, [1, 2])
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            a
+            b
+            c
+            d
+            [Bool.False, Bool.True]
+        "}
+    );
+}
+
+#[test]
+fn constant_folding() {
+    // Same expressions with literals, which are folded, and with variables, which are not.
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let two = 2
+            let three = 3
+            let seven = 7
+            printStr(\"$(2 * 3 + 1) $(two * three + 1)\")
+            printStr(\"$(-7 / 2) $(-seven / two)\")
+            printStr(\"$(-7 % 2) $(-seven % two)\")
+            printStr(\"$(1 - 2 * -3) $(1 - two * -three)\")
+            printStr(\"$(-2147483647 - 1) $(-2147483647 - 1 + 0)\")
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            7 7
+            -3 -3
+            -1 -1
+            7 7
+            -2147483648 -2147483648
+        "}
+    );
+
+    // Expressions that fail are not folded and fail when evaluated.
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            printStr(\"before\")
+            let x = 2147483647 + 1
+    "};
+    let (out, result) = try_run(pgm, "");
+    assert_eq!(out, "before\n");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:3:13: integer overflow"
+    );
+
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            printStr(\"before\")
+            let x = 1 / (2 - 2)
+    "};
+    let (out, result) = try_run(pgm, "");
+    assert_eq!(out, "before\n");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:3:13: division by zero"
+    );
+}
+
+#[test]
+fn comparisons_do_not_allocate() {
+    let mut heap = super::heap::Heap::new();
+    let mut pgm = super::Pgm::new(parse("Prelude", include_str!("../../examples/Prelude.fir")));
+    pgm.init_heap(&mut heap);
+    let loc = ast::Loc {
+        module: "".into(),
+        line_start: 0,
+        col_start: 0,
+        byte_offset_start: 0,
+        line_end: 0,
+        col_end: 0,
+        byte_offset_end: 0,
+    };
+    let i1 = heap.allocate_i32(1);
+    let i2 = heap.allocate_i32(2);
+    let c1 = heap.allocate_char('a');
+    let c2 = heap.allocate_char('b');
+    let mut out: Vec<u8> = vec![];
+
+    let allocations = heap.stats().allocations;
+    assert_eq!(
+        super::cmp(&mut out, &pgm, &mut heap, i1, i2, &loc).unwrap(),
+        std::cmp::Ordering::Less
+    );
+    assert!(!super::eq(&mut out, &pgm, &mut heap, i1, i2, &loc).unwrap());
+    assert_eq!(
+        super::cmp(&mut out, &pgm, &mut heap, c2, c1, &loc).unwrap(),
+        std::cmp::Ordering::Greater
+    );
+    assert!(!super::eq(&mut out, &pgm, &mut heap, c1, c2, &loc).unwrap());
+    assert_eq!(heap.stats().allocations, allocations);
+
+    // Values are compared, not addresses, and `I32`s are compared as signed.
+    let c3 = heap.allocate_char('a');
+    let i3 = heap.allocate_i32(-1);
+    assert!(super::eq(&mut out, &pgm, &mut heap, c1, c3, &loc).unwrap());
+    assert_eq!(
+        super::cmp(&mut out, &pgm, &mut heap, i3, i1, &loc).unwrap(),
+        std::cmp::Ordering::Less
+    );
+}
+
+fn non_exhaustive_matches(pgm: &str) -> Vec<String> {
+    let mut module = parse("Prelude", include_str!("../../examples/Prelude.fir"));
+    module.extend(parse("Test", pgm));
+    let (ty_cons, _) = super::init::collect_types(&module);
+    super::exhaustiveness::non_exhaustive_matches(&module, &ty_cons)
+        .into_iter()
+        .map(|diagnostic| diagnostic.to_string())
+        .collect()
+}
+
+#[test]
+fn non_exhaustive_match() {
+    let pgm = indoc! {"
+        type Color:
+            Red
+            Green
+            Blue
+
+        fn main(input: Str) =
+            match Color.Red:
+                Color.Red: 1
+                Color.Green: 2
+
+            # Guarded alternatives don't cover the constructor.
+            match Option.Some(1):
+                Option.Some(x) if x > 0: 1
+                Option.None: 2
+
+            # Exhaustive.
+            match Color.Red:
+                Color.Red | Color.Green: 1
+                Color.Blue: 2
+
+            match Color.Red:
+                Color.Red: 1
+                _: 2
+
+            match Option.Some(1):
+                Option.Some(Option.Some(x)): 1
+                Option.Some(_): 1
+                Option.None: 2
+
+            match \"a\":
+                \"a\": 1
+    "};
+    assert_eq!(
+        non_exhaustive_matches(pgm),
+        vec![
+            "Test:7:5: non-exhaustive match, missing constructors: Color.Blue",
+            "Test:12:5: non-exhaustive match, missing constructors: Option.Some",
+        ]
+    );
+}
+
+#[test]
+fn array_for_each() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            [1, 2, 3].forEach(printI32)
+            printStr([1, 2, 3, 4].forEachWhile(printSmall).toStr())
+            printStr([1, 2].forEachWhile(printSmall).toStr())
+
+            # Exceptions stop the iteration.
+            try:
+                [1, 2, 3].forEach(throwTwo)
+            catch x:
+                printStr(\"caught $(x)\")
+
+        fn printI32(i: I32) =
+            printStr(i.toStr())
+
+        fn printSmall(i: I32): Bool =
+            if i > 2:
+                return Bool.False
+            printStr(\"small $(i)\")
+            Bool.True
+
+        fn throwTwo(i: I32) =
+            if i == 2:
+                throw i
+            printStr(\"no throw $(i)\")
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            1
+            2
+            3
+            small 1
+            small 2
+            False
+            small 1
+            small 2
+            True
+            no throw 1
+            caught 2
+        "}
+    );
+}
+
+#[test]
+fn array_for_each_indexed() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            [10, 20, 30].forEachIndexed(printPair)
+            let empty: Array[I32] = []
+            empty.forEachIndexed(printPair)
+
+        fn printPair(i: I32, elem: I32) =
+            print((i, elem))
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            Test:7:5: (0, 10)
+            Test:7:5: (1, 20)
+            Test:7:5: (2, 30)
+        "}
+    );
+}
+
+#[test]
+fn refutable_let() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let Option.Some(x) = Option.Some(1)
+            printStr(x.toStr())
+            let Option.Some(y): Option[I32] = Option.None
+            printStr(y.toStr())
+    "};
+    let (out, result) = try_run(pgm, "");
+    assert_eq!(out, "1\n");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:4:5: value Option.None does not match the pattern"
+    );
+}
+
+#[test]
+fn while_let() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let iter = [1, 2, 3, 4, 5].iter()
+            while let Option.Some(i) = iter.next():
+                if i == 2:
+                    continue
+                if i == 4:
+                    break
+                printStr(i.toStr())
+
+            let iter = (0 .. 3).iter()
+            while let Option.Some(i) = iter.next():
+                printStr(i.toStr())
+
+            printStr(firstEven([1, 3, 6, 7]).toStr())
+
+        fn firstEven(arr: Array[I32]): I32 =
+            let iter = arr.iter()
+            while let Option.Some(i) = iter.next():
+                if i % 2 == 0:
+                    return i
+            -1
+    "};
+    assert_eq!(run(pgm, ""), "1\n3\n0\n1\n2\n6\n");
+}
+
+#[test]
+fn memo_fun() {
+    // Without memoization `fib(25)` makes more than 200,000 calls.
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            printStr(fib(25).toStr())
+            printStr(square(3).toStr())
+            printStr(square(3).toStr())
+            printStr(greet(\"a\"))
+            printStr(greet(\"a\".substr(0, 1)))
+            printStr(greet(\"b\"))
+
+        memo fn fib(n: I32): I32 =
+            if n < 2:
+                return n
+            fib(n - 1) + fib(n - 2)
+
+        memo fn square(i: I32): I32 =
+            printStr(\"computing square($(i))\")
+            i * i
+
+        memo fn greet(name: Str): Str =
+            printStr(\"computing greet($(name))\")
+            \"Hi $(name)\"
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            75025
+            computing square(3)
+            9
+            9
+            computing greet(a)
+            Hi a
+            Hi a
+            computing greet(b)
+            Hi b
+        "}
+    );
+}
+
+#[test]
+fn event_sink() {
+    #[derive(Debug, Default)]
+    struct Events(Vec<String>);
+
+    impl super::EventSink for Events {
+        fn event(&mut self, event: super::Event) {
+            self.0.push(match event {
+                super::Event::Output(bytes) => {
+                    format!("output: {:?}", String::from_utf8_lossy(bytes))
+                }
+                super::Event::Warning(diagnostic) => format!("warning: {}", diagnostic.msg),
+                super::Event::Trace(line) => format!("trace: {}", line),
+                super::Event::Stats(_) => "stats".to_string(),
+            });
+        }
+    }
+
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let unused = 1
+            printStr(double(21).toStr())
+
+        fn double(i: I32): I32 =
+            i * 2
+    "};
+    let mut module = parse("Prelude", include_str!("../../examples/Prelude.fir"));
+    module.extend(parse("Test", pgm));
+    let events = Rc::new(RefCell::new(Events::default()));
+    let mut w = super::EventWriter(events.clone());
+    let options = super::RunOptions {
+        trace: true,
+        print_warnings: true,
+        events: Some(events.clone()),
+        ..Default::default()
+    };
+    super::run(&mut w, module, "", &options).unwrap();
+    assert_eq!(
+        events.borrow().0,
+        vec![
+            "warning: unused variable `unused`",
+            "trace: Test:2:5: enter main(\"\")",
+            "trace: Test:2:5: let",
+            "trace: Test:3:5: expr",
+            "trace: Test:3:14: enter double(21)",
+            "trace: Test:6:5: expr",
+            "trace: exit double = 42",
+            "output: \"42\"",
+            "output: \"\\n\"",
+            "trace: exit main = ()",
+        ]
+    );
+}
+
+#[test]
+fn compact_locations() {
+    #[derive(Debug, Default)]
+    struct Lines(Vec<String>);
+
+    impl super::EventSink for Lines {
+        fn event(&mut self, event: super::Event) {
+            match event {
+                super::Event::Trace(line) | super::Event::Stats(line) => {
+                    self.0.push(line.to_string())
+                }
+                super::Event::Output(_) | super::Event::Warning(_) => {}
+            }
+        }
+    }
+
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let x = Option.Some(1)
+            printStr(x.unwrap().toStr())
+    "};
+    let mut module = parse("Prelude", include_str!("../../examples/Prelude.fir"));
+    module.extend(parse("Test", pgm));
+    let events = Rc::new(RefCell::new(Lines::default()));
+    let options = super::RunOptions {
+        trace: true,
+        profile_allocations: true,
+        compact_locations: true,
+        events: Some(events.clone()),
+        ..Default::default()
+    };
+    super::run(&mut Vec::new(), module, "", &options).unwrap();
+    let lines = &events.borrow().0;
+    assert!(lines.contains(&"2:5: let".to_string()));
+    assert!(lines.contains(&"3:5: expr".to_string()));
+    assert!(lines.iter().all(|line| !line.contains("Test:")));
+    // Allocation sites show the expression spans.
+    let sites = &lines[lines
+        .iter()
+        .position(|line| line == "Allocation sites:")
+        .unwrap()
+        + 1..];
+    assert!(
+        sites.iter().any(|site| site.ends_with(" 2:13-2:27")),
+        "{:?}",
+        sites
+    );
+}
+
+#[test]
+fn pattern_type_error() {
+    // A constructor of the pattern's type that doesn't match is a normal match failure.
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            match Option.None:
+                Option.Some(x): printStr(\"some\")
+                Option.None: printStr(\"none\")
+    "};
+    assert_eq!(run(pgm, ""), "none\n");
+
+    // A value of another type is an error.
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            match 123:
+                Option.Some(x): printStr(\"some\")
+                _: printStr(\"other\")
+    "};
+    let (out, result) = try_run(pgm, "");
+    assert_eq!(out, "");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:3:9: type error: value 123 does not have the pattern's type Option"
+    );
+
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            match Option.Some(1):
+                Option(x): printStr(\"some\")
+                _: printStr(\"other\")
+    "};
+    let (_, result) = try_run(pgm, "");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:3:9: type error: type Option has more than one constructor, pattern needs to specify \
+        the constructor"
+    );
+}
+
+#[test]
+fn ordering_reverse_then() {
+    let pgm = indoc! {"
+        type Person:
+            id: I32
+            age: I32
+
+        fn main(input: Str) =
+            printStr(\"$(Ordering.Less.reverse())\")
+            printStr(\"$(Ordering.Equal.reverse())\")
+            printStr(\"$(Ordering.Greater.reverse())\")
+
+            let people = [
+                Person(id = 2, age = 30),
+                Person(id = 1, age = 30),
+                Person(id = 3, age = 20),
+            ]
+
+            # Sort by age descending, then by id.
+            people.sortBy(comparePeople)
+            for person in people:
+                printStr(\"$(person.id) $(person.age)\")
+
+        fn comparePeople(p1: Person, p2: Person): Ordering =
+            p1.age.__cmp(p2.age).reverse().then(p1.id.__cmp(p2.id))
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            Ordering.Greater
+            Ordering.Equal
+            Ordering.Less
+            1 30
+            2 30
+            3 20
+        "}
+    );
+}
+
+#[test]
+fn str_starts_ends_with_replace() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            printStr(\"src/main.fir\".startsWith(\"src/\").toStr())
+            printStr(\"src/main.fir\".startsWith(\"main\").toStr())
+            printStr(\"src/main.fir\".endsWith(\".fir\").toStr())
+            printStr(\"src/main.fir\".endsWith(\"src\").toStr())
+            printStr(\"a\".startsWith(\"\").toStr())
+            printStr(\"a\".endsWith(\"ab\").toStr())
+
+            printStr(\"src/main.fir\".replace(\"/\", \"::\"))
+            printStr(\"aaa\".replace(\"aa\", \"b\"))
+            printStr(\"abc\".replace(\"\", \"-\"))
+            printStr(\"abc\".replace(\"x\", \"y\"))
+            printStr(\"abc\".replace(\"abc\", \"\"))
+
+            # Source files need to be ASCII, so the multi-byte strings are substrings of input
+            # \"çéğ\".
+            printStr(input.startsWith(input.substr(0, 4)).toStr())
+            printStr(input.endsWith(input.substr(4, 6)).toStr())
+            printStr(input.replace(input.substr(2, 4), \"e\"))
+            printStr(input.replace(\"\", \"|\"))
+    "};
+    assert_eq!(
+        run(pgm, "çéğ"),
+        indoc! {"
+            True
+            False
+            True
+            False
+            True
+            False
+            src::main.fir
+            ba
+            -a-b-c-
+            abc
+
+            True
+            True
+            çeğ
+            |ç|é|ğ|
+        "}
+    );
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "Dereferencing unallocated heap address 3")]
+fn unallocated_heap_read() {
+    let mut heap = super::heap::Heap::new();
+    let i = heap.allocate_i32(1);
+    let _ = heap[i + 2];
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "is not the address of an object")]
+fn field_read_as_tag() {
+    let mut heap = super::heap::Heap::new();
+    let module = parse("Prelude", include_str!("../../examples/Prelude.fir"));
+    let mut pgm = super::Pgm::new(module);
+    pgm.init_heap(&mut heap);
+    let i = heap.allocate_i32(1);
+    assert_eq!(heap.tag(&pgm, i), super::I32_TYPE_TAG);
+    heap.tag(&pgm, i + 1);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "Heap address 3 is not the address of an object")]
+fn field_read_as_header() {
+    let mut heap = super::heap::Heap::new();
+    let s = heap.allocate_str(b"abc");
+    assert_eq!(heap.header(s), super::STR_TYPE_TAG);
+    heap.header(s + 2);
+}
+
+#[test]
+fn record_update() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let r = (x = 1, y = 2, z = 3)
+            let r2 = {r | y = 20}
+            printStr(\"$(r.x) $(r.y) $(r.z)\")
+            printStr(\"$(r2.x) $(r2.y) $(r2.z)\")
+
+            # Updates can be nested and update multiple fields.
+            let r3 = {{r2 | x = 10} | z = r2.z * 10, y = 200}
+            printStr(\"$(r3.x) $(r3.y) $(r3.z)\")
+    "};
+    assert_eq!(run(pgm, ""), "1 2 3\n1 20 3\n10 200 30\n");
+
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let r = (x = 1, y = 2)
+            {r | z = 3}
+    "};
+    let (_, result) = try_run(pgm, "");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:3:5: record does not have field z"
+    );
+
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            {(1, 2) | x = 3}
+    "};
+    let (_, result) = try_run(pgm, "");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:2:5: type error: updated value (1, 2) is not a record with named fields"
+    );
+}
+
+#[test]
+fn str_pfx_pat_at_end() {
+    // The prefix is the whole string, the rest is an empty view at the end of the string.
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            match input:
+                \"abc\" as pfx rest:
+                    printStr(\"[$(pfx.toStr())] [$(rest.toStr())] $(rest.isEmpty())\")
+                    match rest:
+                        \"\" as pfx2 rest2:
+                            printStr(\"[$(pfx2.toStr())] [$(rest2.toStr())]\")
+                        _: printStr(\"no match\")
+                _: printStr(\"no match\")
+
+            match input.substr(1, 3):
+                \"bc\" rest: printStr(\"[$(rest.toStr())]\")
+                _: printStr(\"no match\")
+    "};
+    assert_eq!(run(pgm, "abc"), "[abc] [] True\n[] []\n[]\n");
+}
+
+#[test]
+fn empty_str_pat() {
+    // `""` matches empty `Str`s and `StrView`s.
+    let pgm = indoc! {r#"
+        fn describe(s: Str) =
+            match s:
+                "": printStr("empty")
+                _: printStr("non-empty")
+
+        fn main(input: Str) =
+            describe(input)
+            describe(input.substr(0, 0))
+            match input:
+                "ab" rest:
+                    match rest:
+                        "": printStr("empty rest")
+                        _: printStr("rest = $(rest)")
+                _: printStr("no match")
+            match input.substr(0, 2):
+                "ab" rest:
+                    match rest:
+                        "": printStr("empty rest of view")
+                        _: printStr("rest = $(rest)")
+                _: printStr("no match")
+
+            # `isEmpty` for guards.
+            for s in [input, "", input.substr(2, 2).toStr()]:
+                match s:
+                    _ if s.isEmpty(): printStr("isEmpty")
+                    _: printStr("not isEmpty")
+    "#};
+    assert_eq!(
+        run(pgm, "abc"),
+        indoc! {"
+            non-empty
+            empty
+            rest = c
+            empty rest of view
+            not isEmpty
+            isEmpty
+            isEmpty
+        "}
+    );
+}
+
+#[test]
+fn str_view_bounds() {
+    let mut heap = super::heap::Heap::new();
+    let str = heap.allocate_str(b"abc");
+    assert!(heap.allocate_str_view(str, 3, 3).is_some());
+    assert!(heap.allocate_str_view(str, 2, 4).is_none());
+    assert!(heap.allocate_str_view(str, 2, 1).is_none());
+
+    let view = heap.allocate_str_view(str, 1, 3).unwrap();
+    let view2 = heap.allocate_str_view_from_str_view(view, 1, 2).unwrap();
+    assert_eq!(heap.str_view_bytes(view2), b"c");
+    assert!(heap.allocate_str_view_from_str_view(view, 2, 2).is_some());
+    assert!(heap.allocate_str_view_from_str_view(view, 0, 3).is_none());
+    assert!(heap.allocate_str_view_from_str_view(view, 1, 0).is_none());
+}
+
+#[test]
+fn now() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let start = now()
+            let i = 0
+            while i < 3:
+                i = i + 1
+            printStr(\"$(start) $(now() - start)\")
+    "};
+    let run_with_clock = |clock: super::Clock| {
+        let mut module = parse("Prelude", include_str!("../../examples/Prelude.fir"));
+        module.extend(parse("Test", pgm));
+        let mut out: Vec<u8> = vec![];
+        let options = super::RunOptions {
+            clock,
+            ..Default::default()
+        };
+        let result = super::run(&mut out, module, "", &options);
+        (String::from_utf8(out).unwrap(), result)
+    };
+
+    let (out, result) = run_with_clock(super::Clock::Fake { step_ms: 5 });
+    result.unwrap();
+    assert_eq!(out, "0 5\n");
+
+    let (out, result) = run_with_clock(super::Clock::Monotonic);
+    result.unwrap();
+    let elapsed: i32 = out.trim().split(' ').nth(1).unwrap().parse().unwrap();
+    assert!(elapsed >= 0);
+
+    let (_, result) = run_with_clock(super::Clock::Disabled);
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:2:17: now() is not available, clock is disabled"
+    );
+}
+
+#[test]
+fn random() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let a = random()
+            let b = random()
+            randomSeed(42)
+            let c = random()
+            randomSeed(42)
+            printStr(\"$(a) $(b) $(a == b) $(c == random())\")
+    "};
+    let run_with_seed = |random_seed: u64| {
+        let mut module = parse("Prelude", include_str!("../../examples/Prelude.fir"));
+        module.extend(parse("Test", pgm));
+        let mut out: Vec<u8> = vec![];
+        let options = super::RunOptions {
+            random_seed,
+            ..Default::default()
+        };
+        super::run(&mut out, module, "", &options).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+
+    let out = run_with_seed(1);
+    assert!(out.ends_with(" False True\n"), "{}", out);
+    assert_eq!(run_with_seed(1), out);
+    assert_ne!(run_with_seed(2), out);
+}
+
+#[test]
+fn arg_eval_order() {
+    // Arguments are evaluated left to right in source order in all call kinds, also when named
+    // arguments are passed in a different order than the parameters or fields.
+    let pgm = indoc! {"
+        type Point:
+            x: I32
+            y: I32
+
+        type Pair:
+            Pair(I32, I32)
+
+        fn arg(i: I32): I32 =
+            printStr(\"$(i)\")
+            i
+
+        fn sub(x: I32, y: I32): I32 =
+            x - y
+
+        fn Point.offset(self, dx: I32, dy: I32): Point =
+            Point(x = self.x + dx, y = self.y + dy)
+
+        fn Point.make(x: I32, y: I32): Point =
+            Point(x = x, y = y)
+
+        fn tail(a: I32, b: I32): I32 =
+            return sub(y = arg(a), x = arg(b))
+
+        fn main(input: Str) =
+            printStr(\"top\")
+            sub(arg(1), arg(2))
+            sub(y = arg(1), x = arg(2))
+            printStr(\"method\")
+            Point(x = arg(1), y = arg(2)).offset(dy = arg(3), dx = arg(4))
+            printStr(\"associated\")
+            Point.make(y = arg(1), x = arg(2))
+            printStr(\"constructor\")
+            Pair(arg(1), arg(2))
+            Point(y = arg(1), x = arg(2))
+            Point.Point(y = arg(1), x = arg(2))
+            printStr(\"closure\")
+            let f = sub
+            f(arg(1), arg(2))
+            let p = Point.Point
+            p(y = arg(1), x = arg(2))
+            printStr(\"record\")
+            (x = arg(1), y = arg(2))
+            (y = arg(1), x = arg(2))
+            printStr(\"tail\")
+            tail(1, 2)
+            printStr(\"binop\")
+            arg(1) + arg(2)
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            top
+            1
+            2
+            1
+            2
+            method
+            1
+            2
+            3
+            4
+            associated
+            1
+            2
+            constructor
+            1
+            2
+            1
+            2
+            1
+            2
+            closure
+            1
+            2
+            1
+            2
+            record
+            1
+            2
+            1
+            2
+            tail
+            1
+            2
+            binop
+            1
+            2
+        "}
+    );
+}
+
+#[test]
+fn associated_consts() {
+    let pgm = indoc! {"
+        type Point:
+            x: I32
+            y: I32
+
+        const Point.ORIGIN: Point = Point(x = 0, y = 0)
+
+        const Point.UNIT = Point.ORIGIN.offset(1)
+
+        const I32.BITS: I32 = if I32.MAX == 2147483647:
+            32
+        else:
+            64
+
+        fn Point.offset(self, d: I32): Point =
+            Point(x = self.x + d, y = self.y + d)
+
+        fn main(input: Str) =
+            printStr(\"$(I32.MAX) $(I32.MIN) $(I32.BITS)\")
+            printStr(\"$(Point.ORIGIN) $(Point.UNIT)\")
+            printStr(\"$(I32.MAX.addChecked(1)) $(I32.MIN.addSaturating(-1))\")
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            2147483647 -2147483648 32
+            Point(x = 0, y = 0) Point(x = 1, y = 1)
+            Option.None -2147483648
+        "}
+    );
+
+    // Constants are initialized in declaration order, before `main`.
+    let pgm = indoc! {"
+        const I32.DOUBLE_LIMIT = I32.LIMIT * 2
+        const I32.LIMIT = 10
+
+        fn main(input: Str) =
+            printStr(\"$(I32.DOUBLE_LIMIT)\")
+    "};
+    let (out, result) = try_run(pgm, "");
+    assert_eq!(out, "");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:1:26: constant I32.LIMIT is used before it's initialized"
+    );
+
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let x = I32.MAX + 1
+            printStr(\"$(x)\")
+    "};
+    assert_eq!(
+        try_run(pgm, "").1.unwrap_err().to_string(),
+        "Test:2:13: integer overflow"
+    );
+}
+
+#[test]
+fn to_debug_str() {
+    // String literals don't have escapes, the string with special characters is the input.
+    let pgm = indoc! {"
+        type Name:
+            first: Str
+            last: Str
+
+        fn Name.toStr(self): Str =
+            \"$(self.first) $(self.last)\"
+
+        fn main(input: Str) =
+            printStr(input.toStr())
+            printStr(input.toDebugStr())
+            printStr(\"$(input)\")
+            let name = Name(first = input, last = \"b\")
+            let r = (name = name, tags = [\"x\", \"y\"], count = 1)
+            printStr(name.toStr())
+            printStr(name.toDebugStr())
+            printStr(r.toStr())
+            printStr(r.toDebugStr())
+            printStr(1.toDebugStr())
+    "};
+    assert_eq!(
+        run(pgm, "a\t\"b\"\nc"),
+        indoc! {r#"
+            a	"b"
+            c
+            "a\t\"b\"\nc"
+            a	"b"
+            c
+            a	"b"
+            c b
+            Name(first = "a\t\"b\"\nc", last = "b")
+            (count = 1, name = Name(first = "a\t\"b\"\nc", last = "b"), tags = ["x", "y"])
+            (count = 1, name = Name(first = "a\t\"b\"\nc", last = "b"), tags = ["x", "y"])
+            1
+        "#}
+    );
+}
+
+#[test]
+fn eq_without_eq_method() {
+    let pgm = indoc! {"
+        type Point:
+            x: I32
+            y: I32
+
+        type Id:
+            value: I32
+            comment: Str
+
+        # Ids are equal when the values are equal, comments are ignored.
+        fn Id.__eq(self, other: Id): Bool =
+            self.value == other.value
+
+        fn main(input: Str) =
+            printStr(\"$([1, 2] == [1, 2]) $([1, 2] == [1, 3]) $([1, 2] == [1, 2, 3])\")
+            printStr(\"$([[1], []] == [[1], []]) $([1, 2] != [2, 1])\")
+            let r1 = (a = 1, b = \"x\")
+            let r2 = (b = \"x\", a = 1)
+            printStr(\"$(r1 == r2) $((1, 2) == (2, 1))\")
+            let p = Point(x = 1, y = 2)
+            printStr(\"$(p == Point(x = 1, y = 2)) $(p == Point(x = 2, y = 1))\")
+            let id1 = Id(value = 1, comment = \"a\")
+            let id2 = Id(value = 1, comment = \"b\")
+            printStr(\"$(id1 == id2) $(structuralEq(id1, id2))\")
+            printStr(\"$(nothing() == nothing()) $(nothing() != nothing())\")
+
+        fn nothing() =
+            while Bool.False:
+                ()
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            True False False
+            True True
+            True False
+            True False
+            True False
+            True False
+        "}
+    );
+}
+
+#[test]
+fn validate() {
+    let pgm = indoc! {"
+        type Point:
+            x: I32
+            y: I32
+
+        fn Line.len(self): I32 =
+            0
+
+        fn f(p: Point): I32 =
+            match p:
+                Point.Point(x, y): x + y
+                Point.Origin: 0
+
+        fn main(input: Str, args: Array[Str]) =
+            let p = Point3(x = 1, y = 2, z = 3)
+            let o = Option.Nothing
+            let b = I32.MAX + I32.BITS
+            for i in 0 .. 1:
+                continue 'outer
+    "};
+    let (out, result) = try_run(pgm, "");
+    assert_eq!(out, "");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        indoc! {"
+            Test:5:1: type Line is not defined
+            Test:11:9: type Point does not have a constructor named Origin
+            Test:13:1: main function should take 0 or 1 arguments, it takes 2
+            Test:14:13: type Point3 is not defined
+            Test:15:13: type Option does not have a constructor named Nothing
+            Test:16:23: type I32 does not have a constructor named BITS
+            Test:18:9: loop label 'outer is not defined"}
+    );
+
+    // Without the prelude.
+    let mut out: Vec<u8> = vec![];
+    let module = parse("Test", "fn f() =\n    ()\n");
+    let result = super::run(&mut out, module, "", &Default::default());
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        indoc! {"
+            type Bool is not defined, is the prelude imported?
+            type Option is not defined, is the prelude imported?
+            type Ordering is not defined, is the prelude imported?
+            main function not defined"}
+    );
+}
+
+#[test]
+fn validate_type_annotations() {
+    let pgm = indoc! {"
+        type Box[T]:
+            value: T
+
+        fn Box.get(self): T =
+            self.value
+
+        fn id[A](a: A): A =
+            a
+
+        fn f(a: Foo, b: Array[Bar]): Option[Baz] =
+            Option.None
+
+        fn main(input: Str) =
+            let x: Foo = 1
+            let y: (a: I32, b: Qux) = (a = 1, b = 2)
+            let z: T = 3
+    "};
+    let (out, result) = try_run(pgm, "");
+    assert_eq!(out, "");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        indoc! {"
+            Test:10:1: type Foo is not defined
+            Test:10:1: type Bar is not defined
+            Test:10:1: type Baz is not defined
+            Test:14:5: type Foo is not defined
+            Test:15:5: type Qux is not defined
+            Test:16:5: type T is not defined"}
+    );
+}
+
+#[test]
+fn main_arity() {
+    let pgm = indoc! {"
+        fn main() =
+            printStr(\"no input\")
+    "};
+    assert_eq!(run(pgm, "ignored"), "no input\n");
+
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            printStr(input)
+    "};
+    assert_eq!(run(pgm, "input"), "input\n");
+}
+
+#[test]
+fn size_of_type_name() {
+    let pgm = indoc! {"
+        type Point:
+            x: I32
+            y: I32
+
+        fn main(input: Str) =
+            let values = [
+                1,
+                \"\",
+                \"abcdefghi\",
+                (x = 1, y = 2),
+                (1, 2, 3),
+                Point(x = 1, y = 2),
+                Option.Some(1),
+                Bool.True,
+                [1, 2, 3],
+            ]
+            for value in values:
+                printStr(\"$(typeName(value)) $(sizeOf(value))\")
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            I32 2
+            Str 3
+            Str 5
+            (x, y) 3
+            (_, _, _) 4
+            Point 3
+            Option 2
+            Bool 1
+            Array 5
+        "}
+    );
+}
+
+#[test]
+fn array_new_fill() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let a = Array.new(3, 7)
+            printStr(\"$(a) $(a.len())\")
+            a.fill(0)
+            a.set(1, 5)
+            printStr(\"$(a)\")
+            let empty = Array.new(0, 1)
+            empty.fill(2)
+            printStr(\"$(empty)\")
+            let b = Array.new(2)
+            b.fill(\"x\")
+            printStr(\"$(b)\")
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            [7, 7, 7] 3
+            [0, 5, 0]
+            []
+            [\"x\", \"x\"]
+        "}
+    );
+
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let a = Array.new(-1, 0)
+    "};
+    assert_eq!(
+        try_run(pgm, "").1.unwrap_err().to_string(),
+        "Test:2:13: negative array length -1"
+    );
+}
+
+#[test]
+fn array_index_2d() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let table = [[1, 2, 3], [4, 5, 6]]
+            printStr(\"$(table[1][2]) $(table[0][-1]) $(table[-1][0])\")
+            table[1][0] = 40
+            table[0][1] += 18
+            printStr(\"$(table)\")
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            6 3 4
+            [[1, 20, 3], [40, 5, 6]]
+        "}
+    );
+
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let table = [[1, 2], 3]
+            let x = table[1][0]
+    "};
+    assert_eq!(
+        try_run(pgm, "").1.unwrap_err().to_string(),
+        "Test:3:13: type error: value 3 is not an array, it can't be indexed"
+    );
+}
+
+#[test]
+fn array_index_2d_oob() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let table = [[1, 2], [3, 4]]
+            let x = table[1][2]
+    "};
+    let (_, result) = try_run(pgm, "");
+    let err = result.unwrap_err();
+    assert!(matches!(err, super::RunError::OutOfBounds { .. }));
+    assert_eq!(
+        err.to_string(),
+        "Test:3:13: OOB array access, len = 2, index = 2, dimension = 2"
+    );
+}
+
+#[test]
+fn str_lines() {
+    // String literals don't have escapes, the strings with line endings are in the input.
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            for line in input.lines():
+                printStr(\"$(line.toDebugStr())\")
+            let empty = \"\"
+            printStr(\"$(empty.lines().len())\")
+    "};
+    assert_eq!(
+        run(pgm, "a\nb\r\n\nc\r\n"),
+        indoc! {r#"
+            "a"
+            "b"
+            ""
+            "c"
+            0
+        "#}
+    );
+    assert_eq!(
+        run(pgm, "a\n\n"),
+        indoc! {r#"
+            "a"
+            ""
+            0
+        "#}
+    );
+    // `\r` is only removed before `\n`.
+    assert_eq!(
+        run(pgm, "a\r\rb\r"),
+        indoc! {r#"
+            "a\r\rb\r"
+            0
+        "#}
+    );
+}
+
+#[test]
+fn str_char_at() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            for i in 0 .. input.charLen():
+                printStr(input.charAt(i).toStr())
+            printStr(input.charAt(-1).toStr())
+    "};
+    assert_eq!(
+        run(pgm, "aé€😀b"),
+        indoc! {"
+            a
+            é
+            €
+            😀
+            b
+            b
+        "}
+    );
+}
+
+#[test]
+fn str_char_at_oob() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            input.charAt(3)
+    "};
+    let (_, result) = try_run(pgm, "aéb");
+    let err = result.unwrap_err();
+    assert!(matches!(err, super::RunError::OutOfBounds { .. }));
+    assert_eq!(
+        err.to_string(),
+        "Test:2:5: Character index 3 out of bounds, character length = 3"
+    );
+}
+
+#[test]
+fn scanner() {
+    let pgm = indoc! {r#"
+        fn main(input: Str) =
+            let scanner = Scanner.new(input)
+            let key = "key"
+            if scanner.startsWith(key):
+                scanner.advance(key.len())
+            match scanner.next():
+                Option.Some(c): printStr("next = $(c.toStr())")
+                Option.None: printStr("empty")
+            match scanner.peek():
+                Option.Some(c): printStr("peek = $(c.toStr())")
+                Option.None: printStr("empty")
+            printStr("pos = $(scanner.pos())")
+            printStr("remaining = $(scanner.remaining())")
+            for c in scanner:
+                printStr(c.toStr())
+            printStr("pos = $(scanner.pos())")
+            match scanner.next():
+                Option.Some(c): printStr("next = $(c.toStr())")
+                Option.None: printStr("empty")
+    "#};
+    assert_eq!(
+        run(pgm, "key=é€b"),
+        indoc! {"
+            next = =
+            peek = é
+            pos = 4
+            remaining = é€b
+            é
+            €
+            b
+            pos = 10
+            empty
+        "}
+    );
+}
+
+#[test]
+fn scanner_advance_oob() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let scanner = Scanner.new(input)
+            scanner.advance(2)
+    "};
+    let (_, result) = try_run(pgm, "a");
+    let err = result.unwrap_err();
+    assert!(matches!(err, super::RunError::OutOfBounds { .. }));
+    assert_eq!(
+        err.to_string(),
+        "Test:3:5: Scanner.advance(2) out of bounds, position = 0, byte length = 1"
+    );
+}
+
+#[test]
+fn clone() {
+    let pgm = indoc! {r#"
+        fn main(input: Str) =
+            # Mutating the copy doesn't change the original.
+            let r = (a = (b = 1), c = [1, 2])
+            let copy = clone(r)
+            copy.a.b = 10
+            copy.c[0] = 20
+            printStr("$(r.a.b) $(r.c) $(copy.a.b) $(copy.c)")
+
+            # Shared objects are copied once.
+            let inner = (x = 1)
+            let pair = (fst = inner, snd = inner)
+            let copy = clone(pair)
+            copy.fst.x = 5
+            printStr("$(pair.fst.x) $(pair.snd.x) $(copy.fst.x) $(copy.snd.x)")
+
+            # Cycles in the original are cycles in the copy.
+            let node = (next = Option.None, value = 1)
+            node.next = Option.Some(node)
+            let copy = clone(node)
+            copy.value = 2
+            match copy.next:
+                Option.Some(next): printStr("$(node.value) $(next.value)")
+                Option.None: printStr("None")
+
+            # Uninitialized array elements are not copied.
+            let array = Array.new(3)
+            array.set(1, (x = 1))
+            let copy = clone(array)
+            copy.get(1).x = 2
+            printStr("$(array.get(1).x) $(copy.get(1).x)")
+
+            let set = Set.new()
+            set.add(1)
+            let copy = clone(set)
+            copy.add(2)
+            printStr("$(set) $(copy)")
+    "#};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            1 [1, 2] 10 [20, 2]
+            1 1 5 5
+            1 2
+            1 2
+            Set[1] Set[1, 2]
+        "}
+    );
+}
+
+#[test]
+fn cyclic_value_to_str() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let a = Array.new(2)
+            a.set(0, a)
+            a.set(1, 1)
+            printStr(a.toStr())
+
+            # Shared objects that are not in a cycle are written in full.
+            let inner = (x = 1)
+            printStr(\"$((fst = inner, snd = inner))\")
+
+            let node = (next = Option.None, value = 1)
+            node.next = Option.Some(node)
+            printStr(node.toDebugStr())
+            throw node
+    "};
+    let (out, result) = try_run(pgm, "");
+    assert_eq!(
+        out,
+        indoc! {"
+            [<cycle>, 1]
+            (fst = (x = 1), snd = (x = 1))
+            (next = Option.Some(<cycle>), value = 1)
+        "}
+    );
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Test:14:11: uncaught throw: (next = Option.Some(<cycle>), value = 1)"
+    );
+}
+
+#[test]
+fn ref_eq() {
+    let pgm = indoc! {r#"
+        fn main(input: Str) =
+            let r1 = (x = 1, y = 2)
+            let r2 = (x = 1, y = 2)
+            let r3 = r1
+            printStr("$(r1 == r2) $(refEq(r1, r2))")
+            printStr("$(r1 == r3) $(refEq(r1, r3))")
+
+            # Nullary constructors are allocated once.
+            let none1: Option[I32] = Option.None
+            let none2: Option[I32] = Option.None
+            printStr("$(refEq(none1, none2)) $(refEq(Bool.True, 1 == 1))")
+    "#};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            True False
+            True True
+            True True
+        "}
+    );
+}
+
+#[test]
+fn set_hash() {
+    let pgm = indoc! {r#"
+        type Id:
+            value: I32
+            comment: Str
+
+        # Ids are equal when the values are equal, comments are ignored.
+        fn Id.__eq(self, other: Id): Bool =
+            self.value == other.value
+
+        fn Id.__hash(self): I32 =
+            self.value
+
+        type Name:
+            name: Str
+
+        # Without `__hash`, all `Name`s have the same hash. Names of the same length are equal.
+        fn Name.__eq(self, other: Name): Bool =
+            self.name.len() == other.name.len()
+
+        fn main(input: Str) =
+            let ids = Set.new()
+            ids.add(Id(value = 1, comment = "a"))
+            printStr("$(ids.add(Id(value = 1, comment = "b"))) $(ids.contains(Id(value = 1, comment = "c")))")
+            printStr("$(ids.remove(Id(value = 1, comment = "d"))) $(ids.len())")
+
+            let names = Set.new()
+            names.add(Name(name = "a"))
+            printStr("$(names.add(Name(name = "b"))) $(names.add(Name(name = "bc")))")
+
+            # Removing leaves gaps in the insertion order, which are dropped as the set shrinks.
+            let set = Set.new()
+            for i in 0 .. 1000:
+                set.add(i)
+            for i in 0 .. 995:
+                set.remove(i)
+            set.add(0)
+            printStr("$(set) $(set.contains(998)) $(set.contains(1))")
+    "#};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            False True
+            True 0
+            False True
+            Set[995, 996, 997, 998, 999, 0] True False
+        "}
+    );
+}
+
+#[test]
+fn set_iteration_order() {
+    let pgm = indoc! {r#"
+        fn main(input: Str) =
+            let set = Set.new()
+            for key in input.lines():
+                set.add(key.toStr())
+
+            # A removed element is added at the end when added again.
+            let b = "b"
+            set.remove(b)
+            set.add(b)
+
+            for key in set:
+                printStr(key)
+            printStr("$(set)")
+    "#};
+    assert_eq!(
+        run(pgm, "d\nb\na\nc\na"),
+        indoc! {r#"
+            d
+            a
+            c
+            b
+            Set["d", "a", "c", "b"]
+        "#}
+    );
+}
+
+#[test]
+fn method_not_found() {
+    let pgm = indoc! {"
+        type Point:
+            x: I32
+            y: I32
+
+        fn Point.norm(self): I32 =
+            self.x * self.x + self.y * self.y
+
+        fn Point.scale(self, n: I32): Point =
+            Point(x = self.x * n, y = self.y * n)
+
+        fn main(input: Str) =
+            Point(x = 1, y = 2).nrom()
+    "};
+    let (_, result) = try_run(pgm, "");
+    let err = result.unwrap_err();
+    assert!(matches!(&err, super::RunError::MethodNotFound { .. }));
+    assert_eq!(
+        err.to_string(),
+        "Test:12:5: Point does not have method nrom, available methods: norm, scale"
+    );
+}
+
+#[test]
+fn method_not_found_named_args() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            (x = 1, y = 2).scale(n = 2)
+    "};
+    let (_, result) = try_run(pgm, "");
+    let err = result.unwrap_err();
+    assert!(matches!(&err, super::RunError::MethodNotFound { .. }));
+    assert_eq!(
+        err.to_string(),
+        "Test:2:5: record does not have method scale, it does not have any methods"
+    );
+}
+
+#[test]
+fn pgm_reuse() {
+    // A program can be built once and run with different inputs, in the same or new heaps.
+    let pgm = indoc! {r#"
+        type Greeting:
+            Hi
+            Bye
+
+        const Greeting.DEFAULT: Greeting = Greeting.Hi
+
+        fn main(input: Str) =
+            let greeting = match input:
+                "bye": Greeting.Bye
+                _: Greeting.DEFAULT
+            printStr("$(greeting) $(input.isEmpty()) $(len(input))")
+
+        memo fn len(s: Str): I32 =
+            printStr("computing len")
+            s.len()
+    "#};
+    let mut module = parse("Prelude", include_str!("../../examples/Prelude.fir"));
+    module.extend(parse("Test", pgm));
+    let mut pgm = super::Pgm::new(module);
+
+    let mut run_main = |heap: &mut super::heap::Heap, input: &str| {
+        let mut out: Vec<u8> = vec![];
+        pgm.run_main(&mut out, heap, input).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+
+    let mut heap = super::heap::Heap::new();
+    assert_eq!(
+        run_main(&mut heap, "bye"),
+        "computing len\nGreeting.Bye False 3\n"
+    );
+    assert_eq!(
+        run_main(&mut heap, ""),
+        "computing len\nGreeting.Hi True 0\n"
+    );
+    let mut heap = super::heap::Heap::new();
+    assert_eq!(
+        run_main(&mut heap, "bye"),
+        "computing len\nGreeting.Bye False 3\n"
+    );
+}
+
+#[test]
+fn str_ascii_flag() {
+    let mut heap = super::heap::Heap::new();
+    for (str, ascii) in [("", true), ("abcdefgh", true), ("abcdefgh\u{e9}", false)] {
+        let str = heap.allocate_str(str.as_bytes());
+        assert_eq!(heap.str_is_ascii(str), ascii);
+    }
+
+    // Character built-ins give the same results with and without the ASCII fast paths.
+    let pgm = indoc! {r#"
+        fn main(input: Str) =
+            let s = input.substr(0, 3).toStr()
+            printStr("$(input.charLen()) $(input.charAt(2)) $(input.charAt(-1))")
+            printStr("$(s.charLen()) $(s.charAt(2)) $(s.charAt(-1))")
+            for c in input.chars():
+                printStr(c.toStr())
+    "#};
+    assert_eq!(
+        run(pgm, "abc\u{e9}"),
+        indoc! {"
+            4 c \u{e9}
+            3 c c
+            a
+            b
+            c
+            \u{e9}
+        "}
+    );
+}
+
+#[test]
+fn i32_negative_values() {
+    let mut heap = super::heap::Heap::new();
+    for i in [0, 1, -1, i32::MAX, i32::MIN, -123_456_789] {
+        let alloc = heap.allocate_i32(i);
+        assert_eq!(heap.i32_value(alloc), i);
+    }
+
+    let pgm = indoc! {r#"
+        fn main(input: Str) =
+            let min = I32.MIN
+            let minusOne = -1
+            let large = -2000000000
+            printStr("$(min) $(minusOne) $(large)")
+            printStr("$(min.toStr()) $(minusOne.toStr()) $(large.toStr())")
+            printStr("$(min + 1) $(large - 147483648) $(minusOne * large) $(large / minusOne)")
+            printStr("$(min < minusOne) $(large < min) $(minusOne == 0 - 1)")
+            printStr("$(-7 / 2) $(-7 % 2) $(min % minusOne)")
+    "#};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            -2147483648 -1 -2000000000
+            -2147483648 -1 -2000000000
+            -2147483647 -2147483648 2000000000 2000000000
+            True False True
+            -3 -1 0
+        "}
+    );
+}
+
+#[test]
+fn array_get_negative_index() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            let array = [1, 2, 3]
+            array.get(-1)
+    "};
+    let (_, result) = try_run(pgm, "");
+    let err = result.unwrap_err();
+    assert!(matches!(err, super::RunError::OutOfBounds { .. }));
+    assert_eq!(
+        err.to_string(),
+        "Test:3:5: OOB array access, len = 3, index = -1"
+    );
+}
+
+#[test]
+fn str_substr_negative_index() {
+    let pgm = indoc! {"
+        fn main(input: Str) =
+            input.substr(-1, 2)
+    "};
+    let (_, result) = try_run(pgm, "abc");
+    let err = result.unwrap_err();
+    assert!(matches!(err, super::RunError::OutOfBounds { .. }));
+    assert_eq!(
+        err.to_string(),
+        "Test:2:5: Str.substr start byte index -1 out of bounds, byte length = 3"
+    );
+}
+
+#[test]
+fn str_odd_lengths() {
+    let mut heap = super::heap::Heap::new();
+    let bytes = b"abcdefghijklmnopq";
+    for len in [0, 1, 7, 8, 9, 15, 16, 17] {
+        let str = heap.allocate_str(&bytes[..len]);
+        assert_eq!(heap.str_bytes(str), &bytes[..len]);
+
+        // Padding bytes in the last word are zero.
+        if len % 8 != 0 {
+            let last_word = heap[str + 2 + (len / 8) as u64];
+            let mut expected = [0u8; 8];
+            expected[..len % 8].copy_from_slice(&bytes[len - len % 8..len]);
+            assert_eq!(last_word, u64::from_ne_bytes(expected), "len = {}", len);
+        }
+    }
+
+    // Equal strings of odd lengths allocated in different ways are equal.
+    let pgm = indoc! {r#"
+        fn main(input: Str) =
+            let a = "abcdefghi"
+            let b = input.substr(0, 9).toStr()
+            let c = "$(input.substr(0, 4))$(input.substr(4, 9))"
+            printStr("$(a == b) $(b == c) $(structuralEq(a, c))")
+    "#};
+    assert_eq!(run(pgm, "abcdefghijkl"), "True True True\n");
+}
+
+#[test]
+#[should_panic(
+    expected = "Test:6:5: non-exhaustive pattern match, scrutinee = Option.Some(Tree.Node(Tree.Leaf(1), Tree.Leaf(2)))"
+)]
+fn non_exhaustive_match_nested() {
+    let pgm = indoc! {r#"
+        type Tree:
+            Leaf(I32)
+            Node(Tree, Tree)
+
+        fn main(input: Str) =
+            match Option.Some(Tree.Node(Tree.Leaf(1), Tree.Leaf(2))):
+                Option.Some(Tree.Leaf(n)): printStr(n.toStr())
+                Option.None: printStr("none")
+    "#};
+    run(pgm, "");
+}
+
+#[test]
+fn tree_map() {
+    let pgm = indoc! {r#"
+        fn main(input: Str) =
+            let map = TreeMap.new()
+            for line in input.lines():
+                let n = map.get(line.toStr()).unwrapOr(0)
+                map.insert(line.toStr(), n + 1)
+            for key in map:
+                printStr("$(key) $(map.get(key).unwrap())")
+            printStr(map.toStr())
+
+            printStr(map.insert("b", 10).toStr())
+            printStr(map.remove("a").toStr())
+            printStr(map.remove("a").toStr())
+            printStr(map.get("a").toStr())
+            printStr(map.len().toStr())
+            printStr(map.toStr())
+
+            let nums = TreeMap.new()
+            for i in [5, -3, 12, 0, 7, -3]:
+                nums.insert(i, i * 2)
+            printStr(nums.toStr())
+    "#};
+    assert_eq!(
+        run(pgm, "c\nb\na\nc\nd\nb\nc"),
+        indoc! {r#"
+            a 1
+            b 2
+            c 3
+            d 1
+            TreeMap["a": 1, "b": 2, "c": 3, "d": 1]
+            Option.Some(2)
+            Option.Some(1)
+            Option.None
+            Option.None
+            3
+            TreeMap["b": 10, "c": 3, "d": 1]
+            TreeMap[-3: -6, 0: 0, 5: 10, 7: 14, 12: 24]
+        "#}
+    );
+}
+
+#[test]
+fn tree_map_record_keys() {
+    // Records without `__cmp` are ordered by their fields.
+    let pgm = indoc! {r#"
+        fn main(input: Str) =
+            let map = TreeMap.new()
+            map.insert((x = 2, y = 1), "c")
+            map.insert((x = 1, y = 5), "b")
+            map.insert((x = 1, y = 2), "a")
+            for key in map:
+                printStr("$(key.x) $(key.y) $(map.get(key).unwrap())")
+            printStr(structuralEq(map, clone(map)).toStr())
+    "#};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            1 2 a
+            1 5 b
+            2 1 c
+            True
+        "}
+    );
+}
+
+#[test]
+fn str_repeat() {
+    let pgm = indoc! {r#"
+        fn main(input: Str) =
+            printStr("[$(input.repeat(3))]")
+            printStr("[$(input.repeat(1))]")
+            printStr("[$(input.repeat(0))]")
+            let empty = ""
+            printStr("[$(empty.repeat(5))]")
+            let indent = "  "
+            printStr("$(indent.repeat(2))x")
+    "#};
+    assert_eq!(
+        run(pgm, "ab"),
+        indoc! {"
+            [ababab]
+            [ab]
+            []
+            []
+                x
+        "}
+    );
+}
+
+#[test]
+fn str_repeat_negative() {
+    let pgm = indoc! {r#"
+        fn main(input: Str) =
+            input.repeat(-1)
+    "#};
+    let (_, result) = try_run(pgm, "ab");
+    let err = result.unwrap_err();
+    assert!(matches!(err, super::RunError::InvalidArgument { .. }));
+    assert_eq!(
+        err.to_string(),
+        "Test:2:5: Str.repeat count is negative: -1"
+    );
+}
+
+#[test]
+fn str_builder() {
+    let pgm = indoc! {r#"
+        fn main(input: Str) =
+            let builder = StrBuilder.new()
+            for i in 0 .. 1000:
+                builder.append(i.toStr())
+                builder.append(",")
+            let str = builder.toStr()
+            printStr(str.len().toStr())
+            printStr(str.substr(0, 10).toStr())
+            printStr(str.substr(str.len() - 8, str.len()).toStr())
+
+            let small = StrBuilder.withCapacity(0)
+            small.append(input.substr(0, 1))
+            let copy = clone(small)
+            small.append(input.substr(1, 3))
+            copy.append("!")
+            printStr(small.toStr())
+            printStr("$(small.len()) $(copy.toDebugStr())")
+    "#};
+    assert_eq!(
+        run(pgm, "héllo"),
+        indoc! {r#"
+            3890
+            0,1,2,3,4,
+            998,999,
+            hé
+            3 StrBuilder("h!")
+        "#}
+    );
+}
+
+#[test]
+fn labeled_break_continue() {
+    let pgm = indoc! {"
+        # The loop is the last statement, so the value of `break` is the return value.
+        fn search(): I32 =
+            let i = 0
+            'search: while i < 10:
+                i += 1
+                let j = 0
+                while j < 10:
+                    j += 1
+                    if i * j == 12:
+                        break 'search i * 100 + j
+
+        fn main(input: Str) =
+            'outer: for i in 0 .. 4:
+                for j in 0 .. 4:
+                    if j > i:
+                        continue 'outer
+                    if i == 3:
+                        break 'outer
+                    printStr(\"$(i) $(j)\")
+
+            printStr(search().toStr())
+
+            # Unlabeled `break` and `continue` in a labeled loop target the closest loop.
+            'outer: for i in 0 .. 2:
+                for j in 0 .. 3:
+                    if j == 1:
+                        continue
+                    if j == 2:
+                        break
+                    printStr(\"$(i) $(j)\")
+    "};
+    assert_eq!(
+        run(pgm, ""),
+        indoc! {"
+            0 0
+            1 0
+            1 1
+            2 0
+            2 1
+            2 2
+            206
+            0 0
+            1 0
+        "}
+    );
+}
+
+#[test]
+fn builtin_errors() {
+    // Errors in built-ins unwind through the interpreted calls and are returned by `run`, with the
+    // output printed before the error.
+    let pgm = indoc! {r#"
+        fn get(array: Array[I32], idx: I32): I32 =
+            array.get(idx)
+
+        fn main(input: Str) =
+            printStr("before")
+            printStr(get([1, 2, 3], 3).toStr())
+            printStr("after")
+    "#};
+    let (out, result) = try_run(pgm, "");
+    assert_eq!(out, "before\n");
+    let err = result.unwrap_err();
+    assert!(matches!(err, super::RunError::OutOfBounds { .. }));
+    assert_eq!(
+        err.to_string(),
+        "Test:2:5: OOB array access, len = 3, index = 3"
+    );
+
+    let pgm = indoc! {r#"
+        fn main(input: Str) =
+            let none: Option[I32] = Option.None
+            none.unwrap()
+    "#};
+    let err = try_run(pgm, "").1.unwrap_err();
+    assert!(matches!(err, super::RunError::InvalidArgument { .. }));
+    assert_eq!(
+        err.to_string(),
+        "Test:3:5: Option.unwrap called on Option.None"
+    );
+
+    let pgm = indoc! {r#"
+        fn main(input: Str) =
+            input.startsWith(1)
+    "#};
+    let err = try_run(pgm, "").1.unwrap_err();
+    assert!(matches!(err, super::RunError::TypeError { .. }));
+    assert_eq!(
+        err.to_string(),
+        "Test:2:5: type error: argument is not a string"
+    );
+
+    let pgm = indoc! {r#"
+        fn main(input: Str) =
+            let array = Array.new(2)
+            array.get(1)
+    "#};
+    let err = try_run(pgm, "").1.unwrap_err();
+    assert!(matches!(
+        err,
+        super::RunError::UninitializedArrayElement { idx: 1, .. }
+    ));
+    assert_eq!(
+        err.to_string(),
+        "Test:3:5: Reading uninitialized array element 1"
+    );
+
+    let pgm = indoc! {r#"
+        fn main(input: Str) =
+            let array = [1, 2]
+            array[-3]
+    "#};
+    let err = try_run(pgm, "").1.unwrap_err();
+    assert!(matches!(err, super::RunError::OutOfBounds { .. }));
+    assert_eq!(
+        err.to_string(),
+        "Test:3:5: OOB array access, len = 2, index = -1"
+    );
+}
@@ -0,0 +1,309 @@
+//! Checks a program for errors that would otherwise make the interpreter panic in the middle of a
+//! run: missing types used by the interpreter, a missing `main` or `main` with too many
+//! parameters, and references to undefined types, constructors, and constants.
+//!
+//! Type annotations of `let` statements, function parameters and return types are checked for
+//! undefined types too, even though the interpreter doesn't use them.
+
+use crate::ast::Visitor;
+use crate::diagnostics::Diagnostic;
+use crate::interpreter::*;
+
+/// Types the interpreter needs, with their constructors in the order the interpreter expects.
+const REQUIRED_TYPES: [(&str, &[&str]); 3] = [
+    ("Bool", &["False", "True"]),
+    ("Option", &["None", "Some"]),
+    ("Ordering", &["Less", "Equal", "Greater"]),
+];
+
+pub fn validate(pgm: &[L<ast::TopDecl>], ty_cons: &Map<SmolStr, TyCon>) -> Vec<Diagnostic> {
+    let mut validator = Validator {
+        ty_cons,
+        consts: Default::default(),
+        type_decl_params: Default::default(),
+        ty_params: vec![],
+        loop_labels: vec![],
+        diagnostics: vec![],
+    };
+
+    for (ty, constrs) in REQUIRED_TYPES {
+        let ty_con = match ty_cons.get(ty) {
+            Some(ty_con) => ty_con,
+            None => {
+                validator.pgm_error(format!(
+                    "type {} is not defined, is the prelude imported?",
+                    ty
+                ));
+                continue;
+            }
+        };
+        let constr_names: Vec<&str> = ty_con
+            .value_constrs
+            .iter()
+            .filter_map(|constr| constr.name.as_deref())
+            .collect();
+        if constr_names != constrs {
+            validator.pgm_error(format!(
+                "type {} should have constructors {}",
+                ty,
+                constrs.join(", ")
+            ));
+        }
+    }
+
+    for (ty, name, _) in init::BUILTIN_CONSTS {
+        validator.consts.insert((ty.into(), name.into()));
+    }
+    for decl in pgm {
+        match &decl.node {
+            ast::TopDecl::Const(const_decl) => {
+                validator.consts.insert((
+                    const_decl.node.type_name.clone(),
+                    const_decl.node.name.clone(),
+                ));
+            }
+            ast::TopDecl::Type(type_decl) => {
+                validator
+                    .type_decl_params
+                    .insert(type_decl.node.name.clone(), &type_decl.node.type_params);
+            }
+            ast::TopDecl::Fun(_) | ast::TopDecl::Import(_) => {}
+        }
+    }
+
+    let mut main_found = false;
+
+    for decl in pgm {
+        match &decl.node {
+            ast::TopDecl::Type(_) => {}
+
+            ast::TopDecl::Fun(fun_decl) => {
+                let fun_decl = &fun_decl.node;
+                match &fun_decl.type_name {
+                    Some(type_name) => {
+                        validator.check_ty(type_name, &decl.loc);
+                    }
+                    None if fun_decl.name == "main" => {
+                        main_found = true;
+                        // `main` is called with the input, or without arguments.
+                        if fun_decl.params.len() > 1 {
+                            validator.error(
+                                &decl.loc,
+                                format!(
+                                    "main function should take 0 or 1 arguments, it takes {}",
+                                    fun_decl.params.len()
+                                ),
+                            );
+                        }
+                    }
+                    None => {}
+                }
+                validator.visit_fun_decl_at(fun_decl, &decl.loc);
+            }
+
+            ast::TopDecl::Const(const_decl) => {
+                validator.check_ty(&const_decl.node.type_name, &decl.loc);
+                validator.visit_expr(&const_decl.node.rhs);
+            }
+
+            ast::TopDecl::Import(_) => panic!("Import declaration in validation"),
+        }
+    }
+
+    if !main_found {
+        validator.pgm_error("main function not defined".to_string());
+    }
+
+    validator.diagnostics
+}
+
+struct Validator<'a> {
+    ty_cons: &'a Map<SmolStr, TyCon>,
+
+    /// Associated constants, as type and constant names.
+    consts: Set<(SmolStr, SmolStr)>,
+
+    /// Type parameters of the type declarations, for the associated functions of the types.
+    type_decl_params: Map<SmolStr, &'a [SmolStr]>,
+
+    /// Type parameters in scope in the current function.
+    ty_params: Vec<SmolStr>,
+
+    /// Labels of the loops enclosing the current statement.
+    loop_labels: Vec<Option<SmolStr>>,
+
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Validator<'a> {
+    fn error(&mut self, loc: &Loc, msg: String) {
+        self.diagnostics.push(Diagnostic {
+            loc: Some(loc.clone()),
+            msg,
+        });
+    }
+
+    /// Report an error about the whole program, without a location.
+    fn pgm_error(&mut self, msg: String) {
+        self.diagnostics.push(Diagnostic { loc: None, msg });
+    }
+
+    /// Check that the type is defined, returns the type when it is.
+    fn check_ty(&mut self, ty: &SmolStr, loc: &Loc) -> Option<&'a TyCon> {
+        let ty_con = self.ty_cons.get(ty);
+        if ty_con.is_none() {
+            self.error(loc, format!("type {} is not defined", ty));
+        }
+        ty_con
+    }
+
+    /// Check that the types in a type annotation are defined or are type parameters in scope.
+    ///
+    /// Types don't have locations, so errors are reported at `loc` of the annotated declaration or
+    /// statement.
+    fn check_type(&mut self, ty: &ast::Type, loc: &Loc) {
+        match ty {
+            ast::Type::Named(ast::NamedType { name, args }) => {
+                if !self.ty_params.contains(name) {
+                    self.check_ty(name, loc);
+                }
+                for arg in args {
+                    self.check_type(arg, loc);
+                }
+            }
+            ast::Type::Record(fields) => {
+                for field in fields {
+                    self.check_type(&field.node, loc);
+                }
+            }
+        }
+    }
+
+    /// Visit a function declared at `loc`, checking the parameter and return types in the scope of
+    /// the type parameters of the function and its type.
+    fn visit_fun_decl_at(&mut self, fun_decl: &ast::FunDecl, loc: &Loc) {
+        self.ty_params = fun_decl.type_params.clone();
+        if let Some(type_name) = &fun_decl.type_name {
+            if let Some(type_params) = self.type_decl_params.get(type_name) {
+                self.ty_params.extend(type_params.iter().cloned());
+            }
+        }
+
+        for param in &fun_decl.params {
+            self.check_type(&param.ty, loc);
+        }
+        if let Some(return_ty) = &fun_decl.return_ty {
+            self.check_type(return_ty, loc);
+        }
+        self.visit_fun_decl(fun_decl);
+
+        self.ty_params.clear();
+    }
+
+    /// Check that the type is defined and has the constructor.
+    fn check_constr(&mut self, ty: &SmolStr, constr: &SmolStr, loc: &Loc) {
+        if let Some(ty_con) = self.check_ty(ty, loc) {
+            if !ty_con
+                .value_constrs
+                .iter()
+                .any(|value_constr| value_constr.name.as_ref() == Some(constr))
+            {
+                self.error(
+                    loc,
+                    format!("type {} does not have a constructor named {}", ty, constr),
+                );
+            }
+        }
+    }
+
+    /// Visit the body of a loop with the label.
+    fn visit_loop_body(&mut self, label: &Option<SmolStr>, body: &[L<ast::Stmt>]) {
+        self.loop_labels.push(label.clone());
+        self.visit_stmts(body);
+        self.loop_labels.pop();
+    }
+
+    /// Check that a `break` or `continue` label is the label of an enclosing loop.
+    fn check_loop_label(&mut self, label: &Option<SmolStr>, loc: &Loc) {
+        if let Some(label) = label {
+            if !self.loop_labels.contains(&Some(label.clone())) {
+                self.error(loc, format!("loop label '{} is not defined", label));
+            }
+        }
+    }
+}
+
+impl Visitor for Validator<'_> {
+    fn visit_stmt(&mut self, stmt: &L<ast::Stmt>) {
+        match &stmt.node {
+            ast::Stmt::Break(label, _) | ast::Stmt::Continue(label) => {
+                self.check_loop_label(label, &stmt.loc);
+                ast::walk_stmt(self, stmt);
+            }
+
+            ast::Stmt::For(ast::ForStatement {
+                label,
+                var: _,
+                ty: _,
+                expr,
+                body,
+            }) => {
+                self.visit_expr(expr);
+                self.visit_loop_body(label, body);
+            }
+
+            ast::Stmt::While(ast::WhileStatement { label, cond, body }) => {
+                self.visit_expr(cond);
+                self.visit_loop_body(label, body);
+            }
+
+            ast::Stmt::WhileLet(ast::WhileLetStatement {
+                label,
+                pat,
+                expr,
+                body,
+            }) => {
+                self.visit_pat(pat);
+                self.visit_expr(expr);
+                self.visit_loop_body(label, body);
+            }
+
+            ast::Stmt::Let(ast::LetStatement { lhs: _, ty, rhs: _ }) => {
+                if let Some(ty) = ty {
+                    self.check_type(ty, &stmt.loc);
+                }
+                ast::walk_stmt(self, stmt);
+            }
+
+            ast::Stmt::Assign(_) | ast::Stmt::Expr(_) => ast::walk_stmt(self, stmt),
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &L<ast::Expr>) {
+        match &expr.node {
+            ast::Expr::UpperVar(ty) => {
+                self.check_ty(ty, &expr.loc);
+            }
+
+            ast::Expr::ConstrSelect(ast::ConstrSelectExpr { ty, constr }) => {
+                if !self.consts.contains(&(ty.clone(), constr.clone())) {
+                    self.check_constr(ty, constr, &expr.loc);
+                }
+            }
+
+            _ => ast::walk_expr(self, expr),
+        }
+    }
+
+    fn visit_pat(&mut self, pat: &L<ast::Pat>) {
+        if let ast::Pat::Constr(ast::ConstrPattern { constr, fields: _ }) = &pat.node {
+            match &constr.constr {
+                Some(constr_name) => self.check_constr(&constr.type_, constr_name, &pat.loc),
+                None => {
+                    self.check_ty(&constr.type_, &pat.loc);
+                }
+            }
+        }
+        ast::walk_pat(self, pat);
+    }
+}
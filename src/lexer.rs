@@ -25,6 +25,10 @@ lexgen::lexer! {
 
         // Keywords
         "as" = TokenKind::As,
+        "break" = TokenKind::Break,
+        "catch" = TokenKind::Catch,
+        "const" = TokenKind::Const,
+        "continue" = TokenKind::Continue,
         "elif" = TokenKind::Elif,
         "else" = TokenKind::Else,
         "export" = TokenKind::Export,
@@ -36,9 +40,12 @@ lexgen::lexer! {
         "jump" = TokenKind::Jump,
         "let" = TokenKind::Let,
         "match" = TokenKind::Match,
+        "memo" = TokenKind::Memo,
         "return" = TokenKind::Return, // maybe shorten as "ret"?
         "self" = TokenKind::Self_,
+        "throw" = TokenKind::Throw,
         "trait" = TokenKind::Trait,
+        "try" = TokenKind::Try,
         "type" = TokenKind::Type,
         "var" = TokenKind::Var,
         "while" = TokenKind::While,
@@ -65,9 +72,14 @@ lexgen::lexer! {
         "==" = TokenKind::EqEq,
         "+=" = TokenKind::PlusEq,
         "-=" = TokenKind::MinusEq,
+        "*=" = TokenKind::StarEq,
+        "/=" = TokenKind::SlashEq,
+        "%=" = TokenKind::PercentEq,
         "+" = TokenKind::Plus,
         "-" = TokenKind::Minus,
         "*" = TokenKind::Star,
+        "/" = TokenKind::Slash,
+        "%" = TokenKind::Percent,
         "!" = TokenKind::Exclamation,
         "&&" = TokenKind::AmpAmp,
         "|" = TokenKind::Pipe,
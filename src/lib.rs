@@ -1,5 +1,7 @@
 mod ast;
 mod collections;
+mod const_fold;
+mod diagnostics;
 mod import_resolver;
 mod interpolation;
 mod interpreter;
@@ -9,27 +11,204 @@ mod record_collector;
 mod scanner;
 mod scope_map;
 mod token;
+mod unused_vars;
+
+pub use diagnostics::Diagnostic;
+pub use interpreter::{
+    Breakpoint, Clock, DebugAction, Debugger, Event, EventSink, EventWriter, PauseState, Program,
+    RunError, RunOptions, StderrSink,
+};
+
+use std::io::Write;
+use std::rc::Rc;
+
+use lalrpop_util::ParseError;
+
+/// Errors of `run_source`.
+#[derive(Debug)]
+pub enum Error {
+    /// The program or a module it imports could not be parsed.
+    Parse(Diagnostic),
+
+    /// The program was parsed, but can't be run, e.g. `main` is not defined.
+    Invalid(Vec<Diagnostic>),
+
+    /// The program failed while running.
+    Run(RunError),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Parse(Diagnostic { loc, msg }) => match loc {
+                Some(loc) => write!(f, "{}: parse error: {}", interpreter::LocDisplay(loc), msg),
+                None => write!(f, "parse error: {}", msg),
+            },
+            Error::Invalid(errors) => {
+                for (i, error) in errors.iter().enumerate() {
+                    if i != 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}", error)?;
+                }
+                Ok(())
+            }
+            Error::Run(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// Parse the source of the module `module`, resolve its imports in the directory `root_path`, and
+/// run its `main` with the input.
+///
+/// Imported modules are loaded with `parse_file`, which panics when a module can't be read. Errors
+/// found before parsing, e.g. unterminated strings and unbalanced parentheses, also panic for now.
+pub fn run_source<W: Write>(
+    w: &mut W,
+    module: &str,
+    root_path: &str,
+    source: &str,
+    input: &str,
+    options: &RunOptions,
+) -> Result<(), Error> {
+    build_source(module, root_path, source, options)?
+        .run(w, input)
+        .map_err(Error::Run)
+}
+
+/// Same as `run_source`, but returns the program instead of running it, to run it multiple times
+/// with `Program::run`.
+pub fn build_source(
+    module: &str,
+    root_path: &str,
+    source: &str,
+    options: &RunOptions,
+) -> Result<Program, Error> {
+    let module = parse_module(module, source).map_err(Error::Parse)?;
+    let module = import_resolver::resolve_imports(root_path, module);
+    Program::new(module, options).map_err(|err| match err {
+        RunError::InvalidProgram { errors } => Error::Invalid(errors),
+        err => Error::Run(err),
+    })
+}
+
+/// Parse the source of the module `module`.
+fn parse_module(module: &str, source: &str) -> Result<ast::Module, Diagnostic> {
+    let module: Rc<str> = module.into();
+    let tokens = scanner::scan(lexer::lex(source));
+    parser::TopDeclsParser::new()
+        .parse(&module, tokens)
+        .map_err(|err| {
+            let (start, end, msg) = match err {
+                ParseError::InvalidToken { location } => {
+                    (location, location, "invalid token".to_string())
+                }
+                ParseError::UnrecognizedEof { location, .. } => {
+                    (location, location, "unexpected end of input".to_string())
+                }
+                ParseError::UnrecognizedToken {
+                    token: (start, token, end),
+                    ..
+                } => (start, end, format!("unexpected token {:?}", token.text)),
+                ParseError::ExtraToken {
+                    token: (start, token, end),
+                } => (start, end, format!("extra token {:?}", token.text)),
+                ParseError::User { error } => {
+                    (error.location, error.location, format!("{:?}", error.kind))
+                }
+            };
+            Diagnostic {
+                loc: Some(ast::Loc::from_lexgen(&module, start, end)),
+                msg,
+            }
+        })
+}
 
 #[cfg(not(target_arch = "wasm32"))]
 mod native {
     use super::*;
 
     use smol_str::SmolStr;
+    use std::cell::RefCell;
     use std::path::Path;
+    use std::rc::Rc;
 
     pub fn main() {
         let args: Vec<String> = std::env::args().collect();
 
-        let file_path = Path::new(&args[1]); // "examples/Foo.fir"
+        // `--break=Module:line` pauses before the statements at the line.
+        let breakpoints: Vec<interpreter::Breakpoint> = args
+            .iter()
+            .filter_map(|arg| arg.strip_prefix("--break="))
+            .map(|bp| {
+                let (module, line) = bp
+                    .rsplit_once(':')
+                    .unwrap_or_else(|| panic!("Breakpoint {} is not in Module:line format", bp));
+                interpreter::Breakpoint {
+                    module: SmolStr::new(module),
+                    line: line
+                        .parse()
+                        .unwrap_or_else(|_| panic!("Invalid breakpoint line: {}", line)),
+                }
+            })
+            .collect();
+
+        let debugger = if breakpoints.is_empty() {
+            None
+        } else {
+            Some(Rc::new(RefCell::new(interpreter::Debugger::new(
+                breakpoints,
+                debugger_prompt,
+            ))))
+        };
+
+        let options = interpreter::RunOptions {
+            print_heap_stats: args.iter().any(|arg| arg == "--heap-stats"),
+            profile_allocations: args.iter().any(|arg| arg == "--alloc-profile"),
+            trace: args.iter().any(|arg| arg == "--trace"),
+            compact_locations: args.iter().any(|arg| arg == "--compact-locs"),
+            print_warnings: args.iter().any(|arg| arg == "--warnings"),
+            debugger,
+            // `--fake-clock` makes `now()` return 0, 1, 2, ... for reproducible output.
+            clock: if args.iter().any(|arg| arg == "--fake-clock") {
+                interpreter::Clock::Fake { step_ms: 1 }
+            } else {
+                interpreter::Clock::Monotonic
+            },
+            ..Default::default()
+        };
+        let args: Vec<&String> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+
+        let file_path = Path::new(args[1]); // "examples/Foo.fir"
         let file_name_wo_ext = file_path.file_stem().unwrap(); // "Foo"
         let root_path = file_path.parent().unwrap(); // "examples/"
 
         let module = parse_file(file_path, &SmolStr::new(file_name_wo_ext.to_str().unwrap()));
         let module = import_resolver::resolve_imports(root_path.to_str().unwrap(), module);
 
-        let input = &args[2];
+        let input = args[2];
         let mut w = std::io::stdout();
-        interpreter::run(&mut w, module, input);
+        if let Err(err) = interpreter::run(&mut w, module, input, &options) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+
+    /// Prints the local variables to stderr and reads a command from stdin: `s` to step to the
+    /// next statement, anything else to continue to the next breakpoint.
+    fn debugger_prompt(state: &interpreter::PauseState) -> interpreter::DebugAction {
+        eprintln!("Paused at {}", interpreter::LocDisplay(state.loc));
+        for (var, value) in state.locals() {
+            eprintln!("  {} = {}", var, value);
+        }
+        eprint!("(s)tep or (c)ontinue? ");
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line).unwrap();
+        if line.trim() == "s" {
+            interpreter::DebugAction::Step
+        } else {
+            interpreter::DebugAction::Continue
+        }
     }
 
     pub fn parse_file<P: AsRef<Path> + Clone>(path: P, module: &SmolStr) -> ast::Module {
@@ -41,9 +220,7 @@ mod native {
                 err
             )
         });
-        let tokens = scanner::scan(lexer::lex(&contents));
-        let parser = parser::TopDeclsParser::new();
-        parser.parse(&(module.as_str().into()), tokens).unwrap()
+        parse_module(module, &contents).unwrap_or_else(|err| panic!("{}", Error::Parse(err)))
     }
 }
 
@@ -54,8 +231,9 @@ pub use native::{main, parse_file};
 mod wasm {
     use super::*;
 
-    use std::io::Write;
+    use std::cell::RefCell;
     use std::path::Path;
+    use std::rc::Rc;
 
     use smol_str::SmolStr;
     use wasm_bindgen::prelude::wasm_bindgen;
@@ -64,11 +242,8 @@ mod wasm {
     pub fn parse_file<P: AsRef<Path> + Clone>(path: P, module: &SmolStr) -> ast::Module {
         let path = path.as_ref().to_string_lossy();
         match fetch_sync(&path) {
-            Some(contents) => {
-                let tokens = scanner::scan(lexer::lex(&contents));
-                let parser = parser::TopDeclsParser::new();
-                parser.parse(&(module.as_str().into()), tokens).unwrap()
-            }
+            Some(contents) => parse_module(module, &contents)
+                .unwrap_or_else(|err| panic!("{}", Error::Parse(err))),
             None => {
                 panic!("Unable to fetch {}", path);
             }
@@ -122,25 +297,46 @@ mod wasm {
         clear_interpreter_output();
         clear_program_output();
 
-        let tokens = scanner::scan(lexer::lex(pgm));
-        let parser = parser::TopDeclsParser::new();
-        let module = parser.parse(&("FirWeb".into()), tokens).unwrap();
-        let module = import_resolver::resolve_imports("", module);
-
-        let mut w = WasmOutput;
-        interpreter::run(&mut w, module, input.trim());
-    }
-
-    struct WasmOutput;
-
-    impl Write for WasmOutput {
-        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-            add_program_output(&String::from_utf8_lossy(buf));
-            Ok(buf.len())
+        let events: Rc<RefCell<dyn interpreter::EventSink>> = Rc::new(RefCell::new(WasmSink));
+        let mut w = interpreter::EventWriter(events.clone());
+        let options = interpreter::RunOptions {
+            events: Some(events),
+            // `Instant` is not available in wasm.
+            clock: interpreter::Clock::Disabled,
+            // The playground runs a single module.
+            compact_locations: true,
+            ..Default::default()
+        };
+        if let Err(err) = run_source(&mut w, "FirWeb", "", pgm, input.trim(), &options) {
+            add_interpreter_output(&err.to_string());
         }
+    }
 
-        fn flush(&mut self) -> std::io::Result<()> {
-            Ok(())
+    /// Shows the program output in the program output pane, and the other events in the
+    /// interpreter output pane.
+    #[derive(Debug)]
+    struct WasmSink;
+
+    impl interpreter::EventSink for WasmSink {
+        fn event(&mut self, event: interpreter::Event) {
+            match event {
+                interpreter::Event::Output(bytes) => {
+                    add_program_output(&String::from_utf8_lossy(bytes))
+                }
+                interpreter::Event::Warning(diagnostic) => {
+                    add_interpreter_output(&match &diagnostic.loc {
+                        Some(loc) => format!(
+                            "{}: warning: {}",
+                            interpreter::LocDisplay(loc).without_module(),
+                            diagnostic.msg
+                        ),
+                        None => format!("warning: {}", diagnostic.msg),
+                    })
+                }
+                interpreter::Event::Trace(line) | interpreter::Event::Stats(line) => {
+                    add_interpreter_output(line)
+                }
+            }
         }
     }
 }
@@ -207,4 +403,102 @@ mod tests {
             .unwrap();
         dbg!(ast);
     }
+
+    #[test]
+    fn run_source_errors() {
+        use crate::{run_source, Error, RunError};
+
+        let run = |source: &str| {
+            let mut out: Vec<u8> = vec![];
+            let result = run_source(
+                &mut out,
+                "Test",
+                "examples",
+                source,
+                "input",
+                &Default::default(),
+            );
+            (String::from_utf8(out).unwrap(), result)
+        };
+
+        let (out, result) = run(indoc::indoc! {"
+            import Prelude
+
+            fn main(input: Str) =
+                printStr(input)
+        "});
+        assert_eq!(out, "input\n");
+        assert!(result.is_ok());
+
+        let (_, result) = run(indoc::indoc! {"
+            import Prelude
+
+            fn main(input: Str) =
+                let = input
+        "});
+        let err = result.unwrap_err();
+        assert!(matches!(err, Error::Parse(_)), "{:?}", err);
+        assert_eq!(
+            err.to_string(),
+            "Test:4:9: parse error: unexpected token \"=\""
+        );
+
+        let (_, result) = run(indoc::indoc! {"
+            import Prelude
+
+            fn f(): I32 =
+                1
+        "});
+        let err = result.unwrap_err();
+        assert!(matches!(err, Error::Invalid(_)), "{:?}", err);
+        assert_eq!(err.to_string(), "main function not defined");
+
+        let (_, result) = run(indoc::indoc! {"
+            import Prelude
+
+            fn main(input: Str) =
+                panic(input)
+        "});
+        let err = result.unwrap_err();
+        assert!(
+            matches!(err, Error::Run(RunError::UserPanic { .. })),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn build_source_run_twice() {
+        use crate::{build_source, Clock, RunOptions};
+
+        let options = RunOptions {
+            clock: Clock::Fake { step_ms: 5 },
+            random_seed: 42,
+            ..Default::default()
+        };
+        let mut program = build_source(
+            "Test",
+            "examples",
+            indoc::indoc! {"
+                import Prelude
+
+                fn main(input: Str) =
+                    printStr(\"$(input) $(now()) $(now()) $(random())\")
+            "},
+            &options,
+        )
+        .unwrap();
+
+        let mut run = |input: &str| {
+            let mut out: Vec<u8> = vec![];
+            program.run(&mut out, input).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        // The clock and random numbers start over in each run.
+        let out1 = run("a");
+        let out2 = run("b");
+        assert!(out1.starts_with("a 0 5 "), "{}", out1);
+        assert_eq!(out1.replacen('a', "b", 1), out2);
+    }
 }
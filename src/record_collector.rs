@@ -23,10 +23,13 @@ impl RecordShape {
         }
 
         if things[0].name.is_some() {
+            // Duplicate names, and unnamed fields mixed with named ones, are reported by the
+            // interpreter when evaluating the record expression.
             let mut names: Set<SmolStr> = Default::default();
             for thing in things {
-                let new = names.insert(thing.name.clone().unwrap());
-                assert!(new);
+                if let Some(name) = &thing.name {
+                    names.insert(name.clone());
+                }
             }
             let mut fields: Vec<SmolStr> = names.into_iter().collect();
             fields.sort();
@@ -46,6 +49,7 @@ pub fn collect_records(pgm: &[ast::L<ast::TopDecl>]) -> Set<RecordShape> {
         match &decl.node {
             ast::TopDecl::Type(ty_decl) => visit_ty_decl(&ty_decl.node, &mut records),
             ast::TopDecl::Fun(fun_decl) => visit_fun_decl(&fun_decl.node, &mut records),
+            ast::TopDecl::Const(const_decl) => visit_const_decl(&const_decl.node, &mut records),
             ast::TopDecl::Import(_) => panic!("Import declaration in record collector"),
         }
     }
@@ -67,8 +71,11 @@ fn visit_ty_decl(ty_decl: &ast::TypeDecl, records: &mut Set<RecordShape>) {
 }
 
 fn visit_fun_decl(fun_decl: &ast::FunDecl, records: &mut Set<RecordShape>) {
-    for (_param_name, param_ty) in &fun_decl.params {
-        visit_ty(param_ty, records);
+    for param in &fun_decl.params {
+        visit_ty(&param.ty, records);
+        if let Some(default) = &param.default {
+            visit_expr(&default.node, records);
+        }
     }
 
     if let Some(return_ty) = &fun_decl.return_ty {
@@ -80,6 +87,13 @@ fn visit_fun_decl(fun_decl: &ast::FunDecl, records: &mut Set<RecordShape>) {
     }
 }
 
+fn visit_const_decl(const_decl: &ast::ConstDecl, records: &mut Set<RecordShape>) {
+    if let Some(ty) = &const_decl.ty {
+        visit_ty(ty, records);
+    }
+    visit_expr(&const_decl.rhs.node, records);
+}
+
 fn visit_fields(fields: &ast::ConstructorFields, records: &mut Set<RecordShape>) {
     match fields {
         ast::ConstructorFields::Empty => {}
@@ -144,7 +158,16 @@ fn visit_stmt(stmt: &ast::Stmt, records: &mut Set<RecordShape>) {
 
         ast::Stmt::Expr(expr) => visit_expr(&expr.node, records),
 
+        ast::Stmt::Break(_, expr) => {
+            if let Some(expr) = expr {
+                visit_expr(&expr.node, records);
+            }
+        }
+
+        ast::Stmt::Continue(_) => {}
+
         ast::Stmt::For(ast::ForStatement {
+            label: _,
             var: _,
             ty,
             expr,
@@ -159,18 +182,35 @@ fn visit_stmt(stmt: &ast::Stmt, records: &mut Set<RecordShape>) {
             }
         }
 
-        ast::Stmt::While(ast::WhileStatement { cond, body }) => {
+        ast::Stmt::While(ast::WhileStatement {
+            label: _,
+            cond,
+            body,
+        }) => {
             visit_expr(&cond.node, records);
             for stmt in body {
                 visit_stmt(&stmt.node, records);
             }
         }
+
+        ast::Stmt::WhileLet(ast::WhileLetStatement {
+            label: _,
+            pat,
+            expr,
+            body,
+        }) => {
+            visit_pat(&pat.node, records);
+            visit_expr(&expr.node, records);
+            for stmt in body {
+                visit_stmt(&stmt.node, records);
+            }
+        }
     }
 }
 
 fn visit_pat(pat: &ast::Pat, records: &mut Set<RecordShape>) {
     match pat {
-        ast::Pat::Var(_) | ast::Pat::Ignore | ast::Pat::Str(_) | ast::Pat::StrPfx(_, _) => {}
+        ast::Pat::Var(_) | ast::Pat::Ignore | ast::Pat::Str(_) | ast::Pat::StrPfx(_) => {}
 
         ast::Pat::Constr(ast::ConstrPattern { constr: _, fields }) => {
             for field in fields {
@@ -200,7 +240,9 @@ fn visit_expr(expr: &ast::Expr, records: &mut Set<RecordShape>) {
             for part in parts {
                 match part {
                     crate::interpolation::StringPart::Str(_) => {}
-                    crate::interpolation::StringPart::Expr(expr) => visit_expr(&expr.node, records),
+                    crate::interpolation::StringPart::Expr(expr, _) => {
+                        visit_expr(&expr.node, records)
+                    }
                 }
             }
         }
@@ -241,6 +283,12 @@ fn visit_expr(expr: &ast::Expr, records: &mut Set<RecordShape>) {
             visit_expr(&index.node, records);
         }
 
+        ast::Expr::Array(elems) => {
+            for elem in elems {
+                visit_expr(&elem.node, records);
+            }
+        }
+
         ast::Expr::Record(fields) => {
             for field in fields {
                 visit_expr(&field.node.node, records);
@@ -248,7 +296,25 @@ fn visit_expr(expr: &ast::Expr, records: &mut Set<RecordShape>) {
             records.insert(RecordShape::from_named_things(fields));
         }
 
-        ast::Expr::Return(expr) => visit_expr(&expr.node, records),
+        // The updated record has the same shape as the original.
+        ast::Expr::RecordUpdate(ast::RecordUpdateExpr { record, fields }) => {
+            visit_expr(&record.node, records);
+            for (_, expr) in fields {
+                visit_expr(&expr.node, records);
+            }
+        }
+
+        ast::Expr::Return(expr) | ast::Expr::Throw(expr) => visit_expr(&expr.node, records),
+
+        ast::Expr::Try(ast::TryExpr {
+            body,
+            var: _,
+            handler,
+        }) => {
+            for stmt in body.iter().chain(handler.iter()) {
+                visit_stmt(&stmt.node, records);
+            }
+        }
 
         ast::Expr::Match(ast::MatchExpr { scrutinee, alts }) => {
             visit_expr(&scrutinee.node, records);
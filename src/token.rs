@@ -22,6 +22,10 @@ pub enum TokenKind {
 
     // Keywords
     As,
+    Break,
+    Catch,
+    Const,
+    Continue,
     Elif,
     Else,
     Export,
@@ -33,9 +37,12 @@ pub enum TokenKind {
     Jump,
     Let,
     Match,
+    Memo,
     Return,
     Self_,
+    Throw,
     Trait,
+    Try,
     Type,
     Var,
     While,
@@ -62,10 +69,15 @@ pub enum TokenKind {
     ExclamationEq,
     PlusEq,
     MinusEq,
+    StarEq,
+    SlashEq,
+    PercentEq,
     DotDot,
     Plus,
     Minus,
     Star,
+    Slash,
+    Percent,
     Exclamation,
     AmpAmp,
     Pipe,
@@ -0,0 +1,320 @@
+//! Finds `let`-bound variables that are never read.
+//!
+//! Blocks (bodies of loops, `if` branches, `match` alternatives, `try` and `catch`) are scopes:
+//! variables bound in a block are not visible after the block. A `let` that binds a variable that
+//! is already bound shadows the old binding, which is reported if it's not read before the `let`.
+//! Variables with names starting with `_` are not reported.
+
+use crate::ast::{self, Visitor};
+use crate::diagnostics::Diagnostic;
+use crate::scope_map::ScopeMap;
+
+use smol_str::SmolStr;
+
+pub fn unused_vars(pgm: &[ast::L<ast::TopDecl>]) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = vec![];
+
+    for decl in pgm {
+        match &decl.node {
+            ast::TopDecl::Type(_) => {}
+            ast::TopDecl::Fun(fun_decl) => {
+                let mut analysis = Analysis::default();
+                analysis.visit_fun_decl(&fun_decl.node);
+                diagnostics.extend(analysis.into_diagnostics());
+            }
+            ast::TopDecl::Const(const_decl) => {
+                let mut analysis = Analysis::default();
+                analysis.visit_expr(&const_decl.node.rhs);
+                diagnostics.extend(analysis.into_diagnostics());
+            }
+            ast::TopDecl::Import(_) => panic!("Import declaration in unused variable analysis"),
+        }
+    }
+
+    diagnostics
+}
+
+#[derive(Default)]
+struct Analysis {
+    /// Maps variables in scope to their indices in `bindings`.
+    scopes: ScopeMap<SmolStr, usize>,
+
+    bindings: Vec<Binding>,
+}
+
+struct Binding {
+    var: SmolStr,
+
+    /// Location of the pattern binding the variable, when the binding is reported when not read.
+    /// Parameters, loop variables, and variables bound in `match` alternatives and `catch` are
+    /// tracked to handle shadowing, but not reported.
+    loc: Option<ast::Loc>,
+
+    used: bool,
+}
+
+impl Analysis {
+    fn into_diagnostics(self) -> Vec<Diagnostic> {
+        self.bindings
+            .into_iter()
+            .filter(|binding| !binding.used && !binding.var.starts_with('_'))
+            .filter_map(|binding| {
+                Some(Diagnostic {
+                    loc: Some(binding.loc?),
+                    msg: format!("unused variable `{}`", binding.var),
+                })
+            })
+            .collect()
+    }
+
+    fn bind(&mut self, var: &SmolStr, loc: Option<&ast::Loc>) {
+        let idx = self.bindings.len();
+        self.bindings.push(Binding {
+            var: var.clone(),
+            loc: loc.cloned(),
+            used: false,
+        });
+        self.scopes.bind(var.clone(), idx);
+    }
+
+    fn use_var(&mut self, var: &str) {
+        // Variables that are not bound are top-level functions.
+        if let Some(idx) = self.scopes.get(var) {
+            self.bindings[*idx].used = true;
+        }
+    }
+
+    /// Visit statements of a block, in a new scope.
+    fn visit_block(&mut self, stmts: &[ast::L<ast::Stmt>]) {
+        self.scopes.enter();
+        self.visit_stmts(stmts);
+        self.scopes.exit();
+    }
+
+    /// Bind variables of a pattern. `report` is whether to report the variables when not read.
+    fn bind_pat(&mut self, pat: &ast::L<ast::Pat>, report: bool) {
+        let loc = if report { Some(&pat.loc) } else { None };
+        match &pat.node {
+            ast::Pat::Var(var) => self.bind(var, loc),
+
+            ast::Pat::Ignore | ast::Pat::Str(_) => {}
+
+            ast::Pat::StrPfx(ast::StrPfxPattern {
+                pfx: _,
+                pfx_var,
+                rest_var,
+            }) => {
+                if let Some(pfx_var) = pfx_var {
+                    self.bind(pfx_var, loc);
+                }
+                self.bind(rest_var, loc);
+            }
+
+            ast::Pat::Constr(ast::ConstrPattern { constr: _, fields }) => {
+                for field in fields {
+                    self.bind_pat(&field.node, report);
+                }
+            }
+
+            ast::Pat::Record(fields) => {
+                for field in fields {
+                    self.bind_pat(&field.node, report);
+                }
+            }
+
+            // Both sides bind the same variables.
+            ast::Pat::Or(pat1, _) => self.bind_pat(pat1, report),
+        }
+    }
+}
+
+impl Visitor for Analysis {
+    fn visit_fun_decl(&mut self, fun_decl: &ast::FunDecl) {
+        // Parameter defaults are evaluated in the callee's scope and can refer to the parameters
+        // before them.
+        for param in &fun_decl.params {
+            if let Some(default) = &param.default {
+                self.visit_expr(default);
+            }
+            self.bind(&param.name, None);
+        }
+        self.visit_stmts(&fun_decl.body.node);
+    }
+
+    fn visit_stmt(&mut self, stmt: &ast::L<ast::Stmt>) {
+        match &stmt.node {
+            ast::Stmt::Let(ast::LetStatement { lhs, ty: _, rhs }) => {
+                self.visit_expr(rhs);
+                self.bind_pat(lhs, true);
+            }
+
+            ast::Stmt::Assign(ast::AssignStatement { lhs, rhs, op }) => {
+                self.visit_expr(rhs);
+                match (&lhs.node, op) {
+                    // Assigning a variable doesn't read it.
+                    (ast::Expr::Var(_), ast::AssignOp::Eq) => {}
+                    _ => self.visit_expr(lhs),
+                }
+            }
+
+            ast::Stmt::For(ast::ForStatement {
+                label: _,
+                var,
+                ty: _,
+                expr,
+                body,
+            }) => {
+                self.visit_expr(expr);
+                self.scopes.enter();
+                self.bind(var, None);
+                self.visit_stmts(body);
+                self.scopes.exit();
+            }
+
+            ast::Stmt::While(ast::WhileStatement {
+                label: _,
+                cond,
+                body,
+            }) => {
+                self.visit_expr(cond);
+                self.visit_block(body);
+            }
+
+            ast::Stmt::WhileLet(ast::WhileLetStatement {
+                label: _,
+                pat,
+                expr,
+                body,
+            }) => {
+                self.visit_expr(expr);
+                self.scopes.enter();
+                self.bind_pat(pat, true);
+                self.visit_stmts(body);
+                self.scopes.exit();
+            }
+
+            ast::Stmt::Expr(_) | ast::Stmt::Break(_, _) | ast::Stmt::Continue(_) => {
+                ast::walk_stmt(self, stmt)
+            }
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &ast::L<ast::Expr>) {
+        match &expr.node {
+            ast::Expr::Var(var) => self.use_var(var),
+
+            ast::Expr::Try(ast::TryExpr { body, var, handler }) => {
+                self.visit_block(body);
+                self.scopes.enter();
+                self.bind(var, None);
+                self.visit_stmts(handler);
+                self.scopes.exit();
+            }
+
+            ast::Expr::Match(ast::MatchExpr { scrutinee, alts }) => {
+                self.visit_expr(scrutinee);
+                for alt in alts {
+                    self.scopes.enter();
+                    self.bind_pat(&alt.pattern, false);
+                    if let Some(guard) = &alt.guard {
+                        self.visit_expr(guard);
+                    }
+                    self.visit_stmts(&alt.rhs);
+                    self.scopes.exit();
+                }
+            }
+
+            ast::Expr::If(ast::IfExpr {
+                branches,
+                else_branch,
+            }) => {
+                for (cond, stmts) in branches {
+                    self.visit_expr(cond);
+                    self.visit_block(stmts);
+                }
+                if let Some(else_branch) = else_branch {
+                    self.visit_block(else_branch);
+                }
+            }
+
+            _ => ast::walk_expr(self, expr),
+        }
+    }
+}
+
+#[cfg(test)]
+fn unused_vars_in(pgm: &str) -> Vec<String> {
+    let module = crate::parser::TopDeclsParser::new()
+        .parse(&"Test".into(), crate::scanner::scan(crate::lexer::lex(pgm)))
+        .unwrap();
+    unused_vars(&module)
+        .into_iter()
+        .map(|diagnostic| {
+            let loc = diagnostic.loc.unwrap();
+            format!(
+                "{}:{}: {}",
+                loc.line_start + 1,
+                loc.col_start + 1,
+                diagnostic.msg
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn unused_let() {
+    let pgm = indoc::indoc! {"
+        fn f(a: I32, b: I32) =
+            let x = a
+            let y = 1
+            let _z = 2
+            x
+    "};
+    assert_eq!(unused_vars_in(pgm), vec!["3:9: unused variable `y`"]);
+}
+
+#[test]
+fn unused_shadowed() {
+    let pgm = indoc::indoc! {"
+        fn f() =
+            let x = 1
+            let x = 2
+            let y = 3
+            let y = y + 1
+            if True:
+                let y = 5
+            x + y
+    "};
+    assert_eq!(
+        unused_vars_in(pgm),
+        vec!["2:9: unused variable `x`", "7:13: unused variable `y`"]
+    );
+}
+
+#[test]
+fn unused_pattern_bindings() {
+    let pgm = indoc::indoc! {"
+        fn f(p: Point) =
+            let Point(x = a, y = b) = p
+            let (c, d) = (1, 2)
+            match p:
+                Point(x, y): printStr(\"$(a) $(c)\")
+    "};
+    assert_eq!(
+        unused_vars_in(pgm),
+        vec!["2:26: unused variable `b`", "3:13: unused variable `d`"]
+    );
+}
+
+#[test]
+fn assignment_is_not_a_read() {
+    let pgm = indoc::indoc! {"
+        fn f() =
+            let x = 1
+            x = 2
+            let i = 0
+            while i < 10:
+                i = i + 1
+    "};
+    assert_eq!(unused_vars_in(pgm), vec!["2:9: unused variable `x`"]);
+}